@@ -0,0 +1,298 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    str::FromStr,
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use libp2p::{
+    identity::{Keypair, PublicKey},
+    PeerId,
+};
+
+use crate::{
+    error::{Error, Result},
+    store::Cid,
+};
+
+// 签名内容的前缀，避免与其他签名混用
+const CAPABILITY_DOMAIN: &str = "/dfs/capability";
+
+// 提供节点签发的能力令牌，授权指定节点在有效期内获取根清单下的所有内容
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Capability {
+    // 授权访问的根清单
+    pub root: Cid,
+    // 被授权的节点
+    pub grantee: PeerId,
+    // 过期时间（Unix时间戳，秒）
+    pub expires: u64,
+    // 签发节点对以上内容的签名
+    pub signature: Vec<u8>,
+}
+
+impl Capability {
+    // 用本节点私钥签发能力令牌
+    pub fn issue(keypair: &Keypair, root: Cid, grantee: PeerId, ttl: Duration) -> Result<Self> {
+        let expires = (SystemTime::now() + ttl)
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let signature = keypair
+            .sign(&signing_bytes(&root, &grantee, expires))
+            .map_err(|e| Error::InvalidData(format!("Failed to sign capability: {}", e)))?;
+        Ok(Self {
+            root,
+            grantee,
+            expires,
+            signature,
+        })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        bincode::serialize(&(
+            self.root,
+            self.grantee.to_bytes(),
+            self.expires,
+            &self.signature,
+        ))
+        .expect("Capability to be serializable.")
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        let (root, grantee, expires, signature): (Cid, Vec<u8>, u64, Vec<u8>) =
+            bincode::deserialize(data)
+                .map_err(|e| Error::InvalidData(format!("Invalid capability: {}", e)))?;
+        let grantee = PeerId::from_bytes(&grantee)
+            .map_err(|e| Error::InvalidData(format!("Invalid capability grantee: {}", e)))?;
+        Ok(Self {
+            root,
+            grantee,
+            expires,
+            signature,
+        })
+    }
+
+    // 校验令牌由`issuer`签发、授权给`peer`且尚未过期
+    pub fn verify(&self, issuer: &PublicKey, peer: &PeerId) -> bool {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        self.grantee == *peer
+            && self.expires > now
+            && issuer.verify(
+                &signing_bytes(&self.root, &self.grantee, self.expires),
+                &self.signature,
+            )
+    }
+}
+
+// 命令行中的令牌为编码后的十六进制字符串
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&hex::encode(self.encode()))
+    }
+}
+
+impl FromStr for Capability {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let data = hex::decode(s).map_err(|e| e.to_string())?;
+        Capability::decode(&data).map_err(|e| e.to_string())
+    }
+}
+
+fn signing_bytes(root: &Cid, grantee: &PeerId, expires: u64) -> Vec<u8> {
+    bincode::serialize(&(CAPABILITY_DOMAIN, root, grantee.to_bytes(), expires))
+        .expect("Capability to be serializable.")
+}
+
+// 提供的文件的访问策略
+struct FileAccess {
+    // 根清单的内容标识
+    root: Cid,
+    // 允许访问的节点，为空时文件公开
+    allow: Option<HashSet<PeerId>>,
+}
+
+#[derive(Default)]
+struct Policies {
+    // 按名称索引的提供文件
+    files: HashMap<String, FileAccess>,
+    // 清单节点或数据块 -> 包含它的文件名称
+    owners: HashMap<Cid, HashSet<String>>,
+    // 曾属于受限文件的清单节点和数据块，不再属于任何提供文件后仍拒绝访问
+    withdrawn: HashSet<Cid>,
+}
+
+// 提供节点的访问控制策略。数据块属于任一公开文件，或请求节点在任一包含它的受限文件的
+// 允许列表中，或出示了该文件的有效能力令牌时才返回内容。不属于任何提供文件、
+// 也从未属于受限文件的数据块（例如下载缓存）视为公开
+#[derive(Clone)]
+pub struct AccessPolicy {
+    // 本节点公钥，用于校验本节点签发的能力令牌
+    issuer: PublicKey,
+    policies: Arc<RwLock<Policies>>,
+}
+
+impl AccessPolicy {
+    pub fn new(issuer: PublicKey) -> Self {
+        Self {
+            issuer,
+            policies: Default::default(),
+        }
+    }
+
+    // 设置文件的访问策略，`cids`为根清单下所有清单节点和数据块
+    pub fn set(&self, name: &str, root: Cid, cids: HashSet<Cid>, allow: Option<HashSet<PeerId>>) {
+        self.remove(name);
+        let mut policies = self.policies.write().expect("Lock not to be poisoned.");
+        for cid in cids {
+            policies
+                .owners
+                .entry(cid)
+                .or_default()
+                .insert(name.to_string());
+        }
+        policies
+            .files
+            .insert(name.to_string(), FileAccess { root, allow });
+    }
+
+    // 删除文件的访问策略，受限文件的内容继续拒绝访问
+    pub fn remove(&self, name: &str) {
+        let mut policies = self.policies.write().expect("Lock not to be poisoned.");
+        let file = match policies.files.remove(name) {
+            Some(file) => file,
+            None => return,
+        };
+        let Policies {
+            owners, withdrawn, ..
+        } = &mut *policies;
+        owners.retain(|cid, names| {
+            if names.remove(name) && file.allow.is_some() {
+                withdrawn.insert(*cid);
+            }
+            !names.is_empty()
+        });
+    }
+
+    // 曾属于受限文件的内容
+    pub fn withdrawn(&self) -> HashSet<Cid> {
+        let policies = self.policies.read().expect("Lock not to be poisoned.");
+        policies.withdrawn.clone()
+    }
+
+    // 恢复持久化的曾属于受限文件的内容
+    pub fn withdraw(&self, cids: HashSet<Cid>) {
+        let mut policies = self.policies.write().expect("Lock not to be poisoned.");
+        policies.withdrawn.extend(cids);
+    }
+
+    // 只保留满足条件的曾属于受限文件的内容，例如本地仍存储的数据块。返回是否有变化
+    pub fn retain_withdrawn(&self, keep: impl Fn(&Cid) -> bool) -> bool {
+        let mut policies = self.policies.write().expect("Lock not to be poisoned.");
+        let count = policies.withdrawn.len();
+        policies.withdrawn.retain(|cid| keep(cid));
+        policies.withdrawn.len() != count
+    }
+
+    // 判断是否向节点返回该清单节点或数据块
+    pub fn is_allowed(&self, peer: &PeerId, cid: &Cid, token: Option<&Capability>) -> bool {
+        let policies = self.policies.read().expect("Lock not to be poisoned.");
+        let names = match policies.owners.get(cid) {
+            Some(names) => names,
+            None => return !policies.withdrawn.contains(cid),
+        };
+        names
+            .iter()
+            .filter_map(|name| policies.files.get(name))
+            .any(|file| match &file.allow {
+                None => true,
+                Some(allow) => {
+                    allow.contains(peer)
+                        || token.is_some_and(|token| {
+                            token.root == file.root && token.verify(&self.issuer, peer)
+                        })
+                }
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TTL: Duration = Duration::from_secs(3600);
+
+    #[test]
+    fn verifies_capabilities() {
+        let issuer = Keypair::generate_ed25519();
+        let (grantee, other) = (PeerId::random(), PeerId::random());
+        let root = Cid::of(b"root");
+        let token = Capability::issue(&issuer, root, grantee, TTL).unwrap();
+        assert!(token.verify(&issuer.public(), &grantee));
+
+        // 编码往返后仍然有效
+        let decoded: Capability = token.to_string().parse().unwrap();
+        assert_eq!(decoded, token);
+        assert!(decoded.verify(&issuer.public(), &grantee));
+
+        assert!(!token.verify(&issuer.public(), &other));
+        assert!(!token.verify(&Keypair::generate_ed25519().public(), &grantee));
+        let mut tampered = token.clone();
+        tampered.root = Cid::of(b"other");
+        assert!(!tampered.verify(&issuer.public(), &grantee));
+        let mut extended = token.clone();
+        extended.expires += 1;
+        assert!(!extended.verify(&issuer.public(), &grantee));
+        let expired = Capability::issue(&issuer, root, grantee, Duration::ZERO).unwrap();
+        assert!(!expired.verify(&issuer.public(), &grantee));
+        assert!("zz".parse::<Capability>().is_err());
+        assert!(Capability::decode(b"short").is_err());
+    }
+
+    #[test]
+    fn restricts_blocks_of_private_files() {
+        let issuer = Keypair::generate_ed25519();
+        let policy = AccessPolicy::new(issuer.public());
+        let (friend, grantee, stranger) = (PeerId::random(), PeerId::random(), PeerId::random());
+        let (private, public, shared) =
+            (Cid::of(b"private"), Cid::of(b"public"), Cid::of(b"shared"));
+        let block = Cid::of(b"block");
+        policy.set(
+            "private",
+            private,
+            HashSet::from([private, block, shared]),
+            Some(HashSet::from([friend])),
+        );
+        policy.set("public", public, HashSet::from([public, shared]), None);
+
+        assert!(policy.is_allowed(&friend, &block, None));
+        assert!(!policy.is_allowed(&stranger, &block, None));
+        // 同时属于公开文件的数据块，以及不属于任何文件的数据块都是公开的
+        assert!(policy.is_allowed(&stranger, &shared, None));
+        assert!(policy.is_allowed(&stranger, &Cid::of(b"cache"), None));
+
+        let token = Capability::issue(&issuer, private, grantee, TTL).unwrap();
+        assert!(policy.is_allowed(&grantee, &block, Some(&token)));
+        assert!(!policy.is_allowed(&stranger, &block, Some(&token)));
+        let wrong_root = Capability::issue(&issuer, public, grantee, TTL).unwrap();
+        assert!(!policy.is_allowed(&grantee, &block, Some(&wrong_root)));
+
+        // 停止提供后受限文件的内容仍拒绝访问，公开文件的内容仍公开
+        policy.remove("private");
+        policy.remove("public");
+        assert!(!policy.is_allowed(&stranger, &block, None));
+        assert!(!policy.is_allowed(&friend, &block, None));
+        assert!(policy.is_allowed(&stranger, &public, None));
+        assert_eq!(policy.withdrawn(), HashSet::from([private, block, shared]));
+
+        assert!(policy.retain_withdrawn(|cid| *cid != block));
+        assert!(policy.is_allowed(&stranger, &block, None));
+        assert!(!policy.retain_withdrawn(|_| true));
+    }
+}
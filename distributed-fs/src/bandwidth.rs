@@ -0,0 +1,280 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+
+// 清理空闲节点令牌桶的间隔
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+// 带宽限制（字节每秒），为空时不限制
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RateLimits {
+    // 全局上传速率
+    pub upload: Option<u64>,
+    // 全局下载速率
+    pub download: Option<u64>,
+    // 每个节点的上传速率
+    pub peer_upload: Option<u64>,
+    // 每个节点的下载速率
+    pub peer_download: Option<u64>,
+}
+
+impl RateLimits {
+    // 用`changes`中给出的限制覆盖当前限制，0表示不限制
+    pub fn merge(self, changes: RateLimits) -> Self {
+        let merge = |current: Option<u64>, change: Option<u64>| match change {
+            Some(0) => None,
+            Some(rate) => Some(rate),
+            None => current,
+        };
+        Self {
+            upload: merge(self.upload, changes.upload),
+            download: merge(self.download, changes.download),
+            peer_upload: merge(self.peer_upload, changes.peer_upload),
+            peer_download: merge(self.peer_download, changes.peer_download),
+        }
+    }
+}
+
+impl fmt::Display for RateLimits {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let show = |limit: Option<u64>| match limit {
+            Some(rate) => format!("{} B/s", rate),
+            None => "unlimited".to_string(),
+        };
+        write!(
+            f,
+            "upload {}, download {}, per peer upload {}, per peer download {}",
+            show(self.upload),
+            show(self.download),
+            show(self.peer_upload),
+            show(self.peer_download)
+        )
+    }
+}
+
+// 传输方向
+#[derive(Debug, Clone, Copy)]
+enum Direction {
+    Upload,
+    Download,
+}
+
+// 令牌桶，允许透支，透支的字节数按速率折算为等待时间
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    // 新建的令牌桶是满的
+    fn new() -> Self {
+        Self {
+            tokens: f64::MAX,
+            updated: Instant::now(),
+        }
+    }
+
+    // 按速率补充令牌后扣除`bytes`，返回需要等待的时间。最多积累一秒的令牌
+    fn reserve(&mut self, rate: Option<u64>, bytes: usize, now: Instant) -> Duration {
+        let rate = match rate {
+            Some(rate) if rate > 0 => rate as f64,
+            _ => return Duration::ZERO,
+        };
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(rate);
+        self.updated = now;
+        self.tokens -= bytes as f64;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / rate)
+        }
+    }
+
+    fn is_idle(&self, now: Instant) -> bool {
+        self.tokens >= 0.0 && now.duration_since(self.updated) > PRUNE_INTERVAL
+    }
+}
+
+struct Buckets {
+    limits: RateLimits,
+    upload: Bucket,
+    download: Bucket,
+    // 节点 -> (上传, 下载)令牌桶
+    peers: HashMap<PeerId, (Bucket, Bucket)>,
+    pruned: Instant,
+}
+
+// `/dfs`流的带宽限制，在编解码器读写每个数据帧之前申请带宽。
+// 同时受全局和每个节点的限制，限制可以在运行时调整
+#[derive(Clone)]
+pub struct Bandwidth {
+    buckets: Arc<Mutex<Buckets>>,
+}
+
+impl Bandwidth {
+    pub fn new(limits: RateLimits) -> Self {
+        Self {
+            buckets: Arc::new(Mutex::new(Buckets {
+                limits,
+                upload: Bucket::new(),
+                download: Bucket::new(),
+                peers: HashMap::new(),
+                pruned: Instant::now(),
+            })),
+        }
+    }
+
+    pub fn limits(&self) -> RateLimits {
+        self.lock().limits
+    }
+
+    pub fn set_limits(&self, limits: RateLimits) {
+        self.lock().limits = limits;
+    }
+
+    // 发送`bytes`字节之前等待上传带宽
+    pub async fn upload(&self, peer: Option<PeerId>, bytes: usize) {
+        self.acquire(Direction::Upload, peer, bytes).await
+    }
+
+    // 接收`bytes`字节之前等待下载带宽
+    pub async fn download(&self, peer: Option<PeerId>, bytes: usize) {
+        self.acquire(Direction::Download, peer, bytes).await
+    }
+
+    async fn acquire(&self, direction: Direction, peer: Option<PeerId>, bytes: usize) {
+        let wait = self.reserve(direction, peer, bytes);
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    // 从全局和节点的令牌桶中扣除字节数，返回两者中较长的等待时间
+    fn reserve(&self, direction: Direction, peer: Option<PeerId>, bytes: usize) -> Duration {
+        let now = Instant::now();
+        let mut buckets = self.lock();
+        let limits = buckets.limits;
+        let (global_rate, peer_rate) = match direction {
+            Direction::Upload => (limits.upload, limits.peer_upload),
+            Direction::Download => (limits.download, limits.peer_download),
+        };
+        let global = match direction {
+            Direction::Upload => &mut buckets.upload,
+            Direction::Download => &mut buckets.download,
+        };
+        let mut wait = global.reserve(global_rate, bytes, now);
+        if let (Some(peer), Some(_)) = (peer, peer_rate) {
+            let (upload, download) = buckets
+                .peers
+                .entry(peer)
+                .or_insert_with(|| (Bucket::new(), Bucket::new()));
+            let bucket = match direction {
+                Direction::Upload => upload,
+                Direction::Download => download,
+            };
+            wait = wait.max(bucket.reserve(peer_rate, bytes, now));
+        }
+        if now.duration_since(buckets.pruned) > PRUNE_INTERVAL {
+            buckets
+                .peers
+                .retain(|_, (upload, download)| !(upload.is_idle(now) && download.is_idle(now)));
+            buckets.pruned = now;
+        }
+        wait
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Buckets> {
+        self.buckets.lock().expect("Lock not to be poisoned.")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refills_at_rate_up_to_one_second() {
+        let start = Instant::now();
+        let mut bucket = Bucket::new();
+        assert_eq!(bucket.reserve(None, 1 << 30, start), Duration::ZERO);
+        assert_eq!(bucket.reserve(Some(1000), 1000, start), Duration::ZERO);
+        assert_eq!(
+            bucket.reserve(Some(1000), 500, start),
+            Duration::from_millis(500)
+        );
+
+        // 半秒后补充的令牌刚好抵消透支
+        let now = start + Duration::from_millis(500);
+        assert_eq!(bucket.reserve(Some(1000), 0, now), Duration::ZERO);
+
+        // 长时间空闲后最多积累一秒的令牌
+        let now = now + Duration::from_secs(10);
+        assert_eq!(bucket.reserve(Some(1000), 1000, now), Duration::ZERO);
+        assert_eq!(
+            bucket.reserve(Some(1000), 250, now),
+            Duration::from_millis(250)
+        );
+        // 透支未补足前不视为空闲
+        assert!(!bucket.is_idle(now + PRUNE_INTERVAL * 2));
+        let now = now + Duration::from_secs(1);
+        assert_eq!(bucket.reserve(Some(1000), 0, now), Duration::ZERO);
+        assert!(!bucket.is_idle(now));
+        assert!(bucket.is_idle(now + PRUNE_INTERVAL * 2));
+    }
+
+    #[test]
+    fn waits_for_slower_of_global_and_peer_limits() {
+        let bandwidth = Bandwidth::new(RateLimits {
+            upload: Some(1000),
+            peer_upload: Some(100),
+            ..Default::default()
+        });
+        let (peer, other) = (PeerId::random(), PeerId::random());
+
+        let wait = bandwidth.reserve(Direction::Upload, Some(peer), 200);
+        assert!(wait > Duration::from_millis(900) && wait <= Duration::from_secs(1));
+        // 其他节点有自己的令牌桶
+        assert_eq!(
+            bandwidth.reserve(Direction::Upload, Some(other), 50),
+            Duration::ZERO
+        );
+        // 全局令牌已用去250字节
+        let wait = bandwidth.reserve(Direction::Upload, None, 800);
+        assert!(wait > Duration::from_millis(40) && wait <= Duration::from_millis(50));
+        assert_eq!(
+            bandwidth.reserve(Direction::Download, Some(peer), 1 << 30),
+            Duration::ZERO
+        );
+    }
+
+    #[test]
+    fn merges_limit_changes() {
+        let limits = RateLimits {
+            upload: Some(10),
+            download: Some(20),
+            ..Default::default()
+        };
+        let merged = limits.merge(RateLimits {
+            upload: Some(0),
+            peer_download: Some(5),
+            ..Default::default()
+        });
+        assert_eq!(
+            merged,
+            RateLimits {
+                upload: None,
+                download: Some(20),
+                peer_upload: None,
+                peer_download: Some(5),
+            }
+        );
+    }
+}
@@ -1,17 +1,24 @@
 use libp2p::{
-    kad::{store::MemoryStore, Kademlia, KademliaEvent},
+    kad::{Kademlia, KademliaEvent},
     request_response::{RequestResponse, RequestResponseEvent},
     NetworkBehaviour,
 };
 
-use super::protocol::{FileSwapCodec, FileRequest, FileResponse};
+use record_store::DiskStore;
 
-// 组合Kademlia和请求-响应协议
+use super::protocol::{
+    ExchangeCodec, ExchangeMessage, FileRequest, FileResponse, FileSwapCodec, ReplicaCodec,
+    ReplicaRequest, ReplicaResponse,
+};
+
+// 组合Kademlia、数据块请求、副本请求和数据块交换协议
 #[derive(NetworkBehaviour)]
 #[behaviour(out_event = "ComposedEvent")]
 pub struct ComposedBehaviour {
     pub request_response: RequestResponse<FileSwapCodec>,
-    pub kademlia: Kademlia<MemoryStore>,
+    pub kademlia: Kademlia<DiskStore>,
+    pub replication: RequestResponse<ReplicaCodec>,
+    pub exchange: RequestResponse<ExchangeCodec>,
 }
 
 // 网络行为事件
@@ -19,6 +26,8 @@ pub struct ComposedBehaviour {
 pub enum ComposedEvent {
     RequestResponse(RequestResponseEvent<FileRequest, FileResponse>),
     Kademlia(KademliaEvent),
+    Replication(RequestResponseEvent<ReplicaRequest, ReplicaResponse>),
+    Exchange(RequestResponseEvent<ExchangeMessage, ()>),
 }
 
 impl From<RequestResponseEvent<FileRequest, FileResponse>> for ComposedEvent {
@@ -31,4 +40,16 @@ impl From<KademliaEvent> for ComposedEvent {
     fn from(event: KademliaEvent) -> Self {
         ComposedEvent::Kademlia(event)
     }
-}
\ No newline at end of file
+}
+
+impl From<RequestResponseEvent<ReplicaRequest, ReplicaResponse>> for ComposedEvent {
+    fn from(event: RequestResponseEvent<ReplicaRequest, ReplicaResponse>) -> Self {
+        ComposedEvent::Replication(event)
+    }
+}
+
+impl From<RequestResponseEvent<ExchangeMessage, ()>> for ComposedEvent {
+    fn from(event: RequestResponseEvent<ExchangeMessage, ()>) -> Self {
+        ComposedEvent::Exchange(event)
+    }
+}
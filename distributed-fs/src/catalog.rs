@@ -0,0 +1,223 @@
+use std::{
+    collections::BTreeMap,
+    ops::Bound,
+    sync::{Arc, Mutex},
+    time::UNIX_EPOCH,
+};
+
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    access::AccessPolicy,
+    control::ProvidedFile,
+    store::{BlockStore, Cid, Node},
+};
+
+// 每页的默认条目数
+pub const PAGE_SIZE: u32 = 100;
+// 每页的最大条目数
+const MAX_PAGE_SIZE: u32 = 256;
+// 每页条目编码后的最大字节数，名称路径很长时提前结束本页
+const MAX_PAGE_BYTES: u64 = 256 * 1024;
+
+// 目录请求，按名称路径的顺序返回`after`之后的一页
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CatalogRequest {
+    // 上一页最后一个条目的名称路径，为空时从头开始
+    pub after: Option<String>,
+    // 本页最多的条目数
+    pub limit: u32,
+}
+
+// 提供节点目录中的文件或目录
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CatalogEntry {
+    // 提供节点命名空间下的名称路径
+    pub name: String,
+    // 根清单的内容标识
+    pub root: Cid,
+    // 整个文件内容的SHA-256哈希，目录为目录清单的内容标识
+    pub hash: Cid,
+    // 文件大小或目录总大小
+    pub size: u64,
+    // 是否为目录
+    pub is_dir: bool,
+    // 本地文件的修改时间（Unix时间戳，秒），无法获取时为空
+    pub modified: Option<u64>,
+}
+
+// 目录的一页
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CatalogPage {
+    pub entries: Vec<CatalogEntry>,
+    // 还有后续条目时为请求下一页的`after`
+    pub next: Option<String>,
+}
+
+// 本节点提供的文件，按名称路径索引。守护进程与其提供列表共享，
+// 请求节点只能看到无需能力令牌即可访问的文件
+#[derive(Clone, Default)]
+pub struct Catalog {
+    files: Arc<Mutex<BTreeMap<String, ProvidedFile>>>,
+}
+
+impl Catalog {
+    pub fn new(files: Arc<Mutex<BTreeMap<String, ProvidedFile>>>) -> Self {
+        Self { files }
+    }
+
+    // 只包含一个文件的目录
+    pub fn single(file: ProvidedFile) -> Self {
+        Self::new(Arc::new(Mutex::new(BTreeMap::from([(
+            file.name.clone(),
+            file,
+        )]))))
+    }
+
+    // 返回请求节点可以访问的一页条目
+    pub fn page(
+        &self,
+        store: &BlockStore,
+        policy: &AccessPolicy,
+        peer: &PeerId,
+        request: &CatalogRequest,
+    ) -> CatalogPage {
+        let limit = request.limit.clamp(1, MAX_PAGE_SIZE) as usize;
+        let start = match &request.after {
+            Some(after) => Bound::Excluded(after.clone()),
+            None => Bound::Unbounded,
+        };
+        // 读取清单和文件元数据之前释放锁。只复制本页需要的文件，多取的一个用于判断是否还有下一页
+        let files: Vec<ProvidedFile> = self
+            .files
+            .lock()
+            .expect("Lock not to be poisoned.")
+            .range((start, Bound::Unbounded))
+            .map(|(_, file)| file)
+            .filter(|file| policy.is_allowed(peer, &file.root, None))
+            .take(limit + 1)
+            .cloned()
+            .collect();
+
+        let truncated = files.len() > limit;
+        let last = files.last().map(|file| file.name.clone());
+
+        let mut entries = Vec::new();
+        let mut bytes = 0;
+        let mut remaining = files.into_iter().peekable();
+        while let Some(file) = remaining.peek() {
+            if entries.len() == limit {
+                break;
+            }
+            let entry = match entry(store, file) {
+                Some(entry) => entry,
+                None => {
+                    remaining.next();
+                    continue;
+                }
+            };
+            bytes += bincode::serialized_size(&entry).expect("Catalog entry to be serializable.");
+            if bytes > MAX_PAGE_BYTES && !entries.is_empty() {
+                break;
+            }
+            entries.push(entry);
+            remaining.next();
+        }
+        let next = match remaining.peek() {
+            Some(_) => entries.last().map(|entry| entry.name.clone()),
+            // 取出的文件都已检查但有被跳过的，从最后检查的文件之后继续
+            None if truncated => last,
+            None => None,
+        };
+        CatalogPage { entries, next }
+    }
+}
+
+// 由提供的文件生成目录条目，根清单不在本地存储中时跳过
+fn entry(store: &BlockStore, file: &ProvidedFile) -> Option<CatalogEntry> {
+    let hash = match store.node(&file.root) {
+        Ok(Node::File(manifest)) => manifest.hash,
+        Ok(Node::Directory(_)) => file.root,
+        Err(e) => {
+            warn!("Failed to read manifest of {}: {}", file.name, e);
+            return None;
+        }
+    };
+    let modified = std::fs::metadata(&file.path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs());
+    Some(CatalogEntry {
+        name: file.name.clone(),
+        root: file.root,
+        hash,
+        size: file.size,
+        is_dir: file.is_dir,
+        modified,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::testing::{import_bytes, open_policy, temp_store};
+
+    fn file(name: &str, root: Cid) -> ProvidedFile {
+        ProvidedFile {
+            name: name.to_string(),
+            path: Default::default(),
+            root,
+            size: 5,
+            is_dir: false,
+        }
+    }
+
+    #[test]
+    fn pages_through_visible_files() {
+        let (dir, store) = temp_store();
+        let (root, _) = import_bytes(&store, dir.path(), "hello", b"hello");
+        let private = Cid::of(b"private");
+        let policy = open_policy();
+        policy.set("d", private, HashSet::from([private]), Some(HashSet::new()));
+
+        let files = BTreeMap::from_iter(
+            [
+                file("a", root),
+                file("b", Cid::of(b"missing")),
+                file("c", root),
+                file("d", private),
+                file("e", root),
+                file("f", root),
+            ]
+            .map(|file| (file.name.clone(), file)),
+        );
+        let catalog = Catalog::new(Arc::new(Mutex::new(files)));
+
+        let peer = PeerId::random();
+        let mut names = Vec::new();
+        let mut after = None;
+        for _ in 0..10 {
+            let request = CatalogRequest { after, limit: 1 };
+            let page = catalog.page(&store, &policy, &peer, &request);
+            assert!(page.entries.len() <= 1);
+            names.extend(page.entries.into_iter().map(|entry| entry.name));
+            after = page.next;
+            if after.is_none() {
+                break;
+            }
+        }
+        assert_eq!(names, ["a", "c", "e", "f"]);
+
+        let request = CatalogRequest {
+            after: None,
+            limit: 10,
+        };
+        let page = catalog.page(&store, &policy, &peer, &request);
+        assert_eq!(page.entries.len(), 4);
+        assert_eq!(page.next, None);
+    }
+}
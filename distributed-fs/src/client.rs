@@ -1,7 +1,29 @@
-use std::{collections::HashSet, error::Error};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
-use crate::protocol::FileResponse;
-use libp2p::{request_response::ResponseChannel, Multiaddr, PeerId};
+use crate::{
+    access::Capability,
+    bandwidth::{Bandwidth, RateLimits},
+    catalog::{CatalogEntry, CatalogPage, CatalogRequest, PAGE_SIZE},
+    crypto,
+    error::{Error, Result},
+    exchange::Exchange,
+    name::{Name, NameRecord},
+    protocol::{ExchangeMessage, FileResponse, ReplicaRequest, ReplicaResponse},
+    search::{
+        self, keyword_cid, keyword_key, IndexEntry, KeywordRecord, SearchResult, MAX_ENTRIES,
+    },
+    store::{Cid, FileRecord},
+};
+use libp2p::{
+    identity::{Keypair, PublicKey},
+    request_response::ResponseChannel,
+    Multiaddr, PeerId,
+};
+use serde::{Deserialize, Serialize};
 use tokio::sync::{
     mpsc::{self, Sender},
     oneshot,
@@ -14,7 +36,7 @@ pub enum Command {
         // 本地监听地址
         addr: Multiaddr,
         // 用于发送命令执行状态的通道
-        sender: oneshot::Sender<Result<(), Box<dyn Error + Send>>>,
+        sender: oneshot::Sender<Result<()>>,
     },
     // 链接给定节点命令
     Dial {
@@ -23,38 +45,174 @@ pub enum Command {
         // 节点地址
         peer_addr: Multiaddr,
         // 用于发送命令执行状态的通道
-        sender: oneshot::Sender<Result<(), Box<dyn Error + Send>>>,
+        sender: oneshot::Sender<Result<()>>,
     },
     // 宣称本节点提供共享文件命令
     StartProviding {
-        // 文件名称
-        file_name: String,
+        // 文件根清单的内容标识
+        cid: Cid,
         // 用于发送命令执行状态的通道
-        sender: oneshot::Sender<()>,
+        sender: oneshot::Sender<Result<()>>,
+    },
+    // 停止宣称本节点提供共享文件命令
+    StopProviding {
+        // 文件根清单的内容标识
+        cid: Cid,
     },
     // 获取提供共享文件的节点命令
     GetProviders {
-        // 文件名称
-        file_name: String,
+        // 文件根清单的内容标识
+        cid: Cid,
+        // 用于发送命令执行状态的通道
+        sender: oneshot::Sender<Result<HashSet<PeerId>>>,
+    },
+    // 获取DHT中距离内容标识最近的节点命令
+    GetClosestPeers {
+        // 文件根清单的内容标识
+        cid: Cid,
+        // 用于发送命令执行状态的通道
+        sender: oneshot::Sender<Result<Vec<PeerId>>>,
+    },
+    // 在DHT中存储记录命令
+    PutRecord {
+        // 记录的键
+        key: Vec<u8>,
+        // 记录的值
+        value: Vec<u8>,
+        // 用于发送命令执行状态的通道
+        sender: oneshot::Sender<Result<()>>,
+    },
+    // 删除本地存储的DHT记录命令
+    RemoveRecord {
+        // 记录的键
+        key: Vec<u8>,
+    },
+    // 从DHT中获取记录命令，返回多个节点上的记录
+    GetRecord {
+        // 记录的键
+        key: Vec<u8>,
         // 用于发送命令执行状态的通道
-        sender: oneshot::Sender<HashSet<PeerId>>,
+        sender: oneshot::Sender<Result<Vec<Vec<u8>>>>,
     },
-    // 请求共享文件命令
-    RequestFile {
-        // 文件名称
-        file_name: String,
+    // 请求数据块命令
+    RequestBlock {
+        // 数据块的内容标识
+        cid: Cid,
+        // 提供节点签发的能力令牌
+        token: Option<Capability>,
         // 节点ID
         peer: PeerId,
         // 用于发送命令执行状态的通道
-        sender: oneshot::Sender<Result<String, Box<dyn Error + Send>>>,
+        sender: oneshot::Sender<Result<PathBuf>>,
+    },
+    // 将节点加入黑名单命令
+    BlacklistPeer {
+        // 节点ID
+        peer: PeerId,
     },
     // 返回共享文件内容命令
     RespondFile {
-        // 文件名称
-        file: String,
+        // 请求节点
+        peer: PeerId,
+        // 本地文件路径
+        file: PathBuf,
         // 返回文件内容
         channel: ResponseChannel<FileResponse>,
     },
+    // 拒绝访问命令
+    RespondForbidden {
+        channel: ResponseChannel<FileResponse>,
+    },
+    // 请求节点目录的一页命令
+    RequestCatalog {
+        // 提供节点
+        peer: PeerId,
+        request: CatalogRequest,
+        // 用于发送命令执行状态的通道
+        sender: oneshot::Sender<Result<CatalogPage>>,
+    },
+    // 请求文件清单下各数据块弱校验和的命令
+    RequestChecksums {
+        // 文件清单的内容标识
+        cid: Cid,
+        // 提供节点签发的能力令牌
+        token: Option<Capability>,
+        // 提供节点
+        peer: PeerId,
+        // 用于发送命令执行状态的通道
+        sender: oneshot::Sender<Result<Vec<u32>>>,
+    },
+    // 返回弱校验和命令
+    RespondChecksums {
+        checksums: Vec<u32>,
+        channel: ResponseChannel<FileResponse>,
+    },
+    // 返回目录命令
+    RespondCatalog {
+        page: CatalogPage,
+        channel: ResponseChannel<FileResponse>,
+    },
+    // 请求节点获取并提供文件副本命令
+    RequestReplica {
+        // 目标节点
+        peer: PeerId,
+        request: ReplicaRequest,
+        // 用于发送命令执行状态的通道
+        sender: oneshot::Sender<Result<ReplicaResponse>>,
+    },
+    // 回复副本请求命令
+    RespondReplica {
+        response: ReplicaResponse,
+        channel: ResponseChannel<ReplicaResponse>,
+    },
+    // 向节点发送数据块交换消息命令
+    SendExchange {
+        // 目标节点
+        peer: PeerId,
+        message: ExchangeMessage,
+        // 用于发送命令执行状态的通道，对方确认后返回
+        sender: oneshot::Sender<Result<()>>,
+    },
+    // 确认数据块交换消息命令
+    RespondExchange {
+        channel: ResponseChannel<()>,
+    },
+    // 获取本地节点网络状态命令
+    NetworkStatus {
+        // 用于发送命令执行状态的通道
+        sender: oneshot::Sender<NetworkStatus>,
+    },
+    // 获取本节点宣称提供的文件命令
+    Advertised {
+        // 用于发送命令执行状态的通道
+        sender: oneshot::Sender<Vec<Advertised>>,
+    },
+}
+
+// 本地节点网络状态
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NetworkStatus {
+    // 本地节点ID
+    pub peer_id: String,
+    // 本地监听地址
+    pub listeners: Vec<String>,
+    // 已连接的节点
+    pub connected_peers: Vec<String>,
+}
+
+// 本节点在DHT上宣称提供的文件及其公布状态
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Advertised {
+    // 文件根清单的内容标识
+    pub cid: Cid,
+    // 距上次成功公布的时间，尚未成功公布时为空
+    pub published: Option<Duration>,
+    // 距下次公布的时间
+    pub next_publish: Duration,
+    // 连续公布失败的次数
+    pub failures: u32,
+    // 其他节点上的提供者记录是否已过期
+    pub expired: bool,
 }
 
 // 用于发送命令的Client
@@ -62,79 +220,508 @@ pub enum Command {
 pub struct Client {
     // 将命令发送到mpsc通道
     sender: mpsc::Sender<Command>,
+    // 本地节点密钥对，用于签名名称记录
+    keypair: Keypair,
+    // 与编解码器共享的带宽限制
+    bandwidth: Bandwidth,
+    // 数据块交换的想要列表和各节点的账本
+    exchange: Exchange,
 }
 
 impl Client {
-    pub fn new(sender: Sender<Command>) -> Client {
-        Client { sender }
+    pub fn new(
+        sender: Sender<Command>,
+        keypair: Keypair,
+        bandwidth: Bandwidth,
+        exchange: Exchange,
+    ) -> Client {
+        Client {
+            sender,
+            keypair,
+            bandwidth,
+            exchange,
+        }
     }
 
-    pub async fn start_listening(&mut self, addr: Multiaddr) -> Result<(), Box<dyn Error + Send>> {
-        let (sender, receiver) = oneshot::channel();
-        self.sender
-            .send(Command::StartListening { addr, sender })
-            .await
-            .expect("Command receiver not to be dropped.");
-        receiver.await.expect("Sender not to be dropped.")
+    pub fn local_peer_id(&self) -> PeerId {
+        self.keypair.public().to_peer_id()
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        self.keypair.public()
+    }
+
+    // 将命令发送给事件循环，事件循环停止时返回错误
+    async fn send(&mut self, command: Command) -> Result<()> {
+        self.sender.send(command).await.map_err(|_| Error::Shutdown)
     }
 
-    pub async fn dial(
+    // 发送命令并等待执行结果
+    async fn execute<T>(
         &mut self,
-        peer_id: PeerId,
-        peer_addr: Multiaddr,
-    ) -> Result<(), Box<dyn Error + Send>> {
+        command: impl FnOnce(oneshot::Sender<Result<T>>) -> Command,
+    ) -> Result<T> {
         let (sender, receiver) = oneshot::channel();
-        self.sender
-            .send(Command::Dial {
-                peer_id,
-                peer_addr,
-                sender,
-            })
+        self.send(command(sender)).await?;
+        receiver.await.map_err(|_| Error::Shutdown)?
+    }
+
+    pub async fn start_listening(&mut self, addr: Multiaddr) -> Result<()> {
+        self.execute(|sender| Command::StartListening { addr, sender })
             .await
-            .expect("Command receiver not to be dropped.");
-        receiver.await.expect("Sender not to be dropped.")
     }
 
-    pub async fn start_providing(&mut self, file_name: String) {
-        let (sender, receiver) = oneshot::channel();
-        self.sender
-            .send(Command::StartProviding { file_name, sender })
+    pub async fn dial(&mut self, peer_id: PeerId, peer_addr: Multiaddr) -> Result<()> {
+        self.execute(|sender| Command::Dial {
+            peer_id,
+            peer_addr,
+            sender,
+        })
+        .await
+    }
+
+    pub async fn start_providing(&mut self, cid: Cid) -> Result<()> {
+        self.execute(|sender| Command::StartProviding { cid, sender })
             .await
-            .expect("Command receiver not to be dropped.");
-        receiver.await.expect("Sender not to be dropped.");
     }
 
-    pub async fn get_providers(&mut self, file_name: String) -> HashSet<PeerId> {
-        let (sender, receiver) = oneshot::channel();
-        self.sender
-            .send(Command::GetProviders { file_name, sender })
+    // 停止在DHT上宣称提供该文件，已公布到其他节点的提供者记录在过期后失效
+    pub async fn stop_providing(&mut self, cid: Cid) -> Result<()> {
+        self.send(Command::StopProviding { cid }).await
+    }
+
+    pub async fn get_providers(&mut self, cid: Cid) -> Result<HashSet<PeerId>> {
+        self.execute(|sender| Command::GetProviders { cid, sender })
+            .await
+    }
+
+    // 按Kademlia距离获取最接近内容标识的节点，不包括本节点
+    pub async fn get_closest_peers(&mut self, cid: Cid) -> Result<Vec<PeerId>> {
+        self.execute(|sender| Command::GetClosestPeers { cid, sender })
             .await
-            .expect("Command receiver not to be dropped.");
-        receiver.await.expect("Sender not to be dropped.")
     }
 
-    pub async fn request_file(
+    pub async fn put_record(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
+        self.execute(|sender| Command::PutRecord { key, value, sender })
+            .await
+    }
+
+    // 删除本地存储的DHT记录，不再重新公布，其他节点上的记录在过期后失效
+    pub async fn remove_record(&mut self, key: Vec<u8>) -> Result<()> {
+        self.send(Command::RemoveRecord { key }).await
+    }
+
+    pub async fn get_records(&mut self, key: Vec<u8>) -> Result<Vec<Vec<u8>>> {
+        self.execute(|sender| Command::GetRecord { key, sender })
+            .await
+    }
+
+    // 发布名称记录，使名称指向新的内容。版本序号取当前时间和已有最新版本加一中的较大值
+    pub async fn publish_name(&mut self, name: &Name, value: FileRecord) -> Result<()> {
+        let previous = match self.get_records(name.key()).await {
+            Ok(values) => NameRecord::latest(name, values.iter().map(|v| v.as_slice()))
+                .map(|record| record.sequence),
+            Err(_) => None,
+        };
+        let record = NameRecord::sign(&self.keypair, name, next_sequence(previous), value)?;
+        self.put_record(name.key(), record.encode()).await
+    }
+
+    // 解析名称，返回签名有效的最新版本指向的内容
+    pub async fn resolve_name(&mut self, name: &Name) -> Result<FileRecord> {
+        let values = self.get_records(name.key()).await.map_err(|e| match e {
+            Error::GetRecord(_) | Error::NotFound(_) => {
+                Error::NotFound(format!("Name record of {}", name))
+            }
+            e => e,
+        })?;
+        let record = NameRecord::latest(name, values.iter().map(|v| v.as_slice()))
+            .ok_or_else(|| Error::NotFound(format!("Valid name record of {}", name)))?;
+        debug!("Resolved {} to version {}", name, record.sequence);
+        Ok(record.value)
+    }
+
+    // 删除本地的名称记录，不再重新公布
+    pub async fn unpublish_name(&mut self, name: &Name) -> Result<()> {
+        self.remove_record(name.key()).await
+    }
+
+    // 将文件加入各关键词的索引记录并宣称提供关键词，使其他节点可以搜索到。
+    // 单个关键词发布失败时记录警告并继续
+    pub async fn publish_keywords(
+        &mut self,
+        entry: IndexEntry,
+        keywords: &BTreeSet<String>,
+    ) -> Result<()> {
+        for keyword in keywords {
+            let result = self
+                .update_keyword(keyword, |entries| {
+                    entries.retain(|e| e.path != entry.path);
+                    entries.push(entry.clone());
+                })
+                .await;
+            let result = match result {
+                Ok(_) => self.start_providing(keyword_cid(keyword)).await,
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(()) => {}
+                Err(Error::Shutdown) => return Err(Error::Shutdown),
+                Err(e) => warn!("Failed to publish keyword {}: {}", keyword, e),
+            }
+        }
+        Ok(())
+    }
+
+    // 从各关键词的索引记录中删除文件，记录为空时不再宣称提供关键词
+    pub async fn unpublish_keywords(
+        &mut self,
+        path: &str,
+        keywords: &BTreeSet<String>,
+    ) -> Result<()> {
+        for keyword in keywords {
+            let result = self
+                .update_keyword(keyword, |entries| entries.retain(|e| e.path != path))
+                .await;
+            match result {
+                Ok(0) => {
+                    // 其他节点保存空记录直到过期，本地不再重新公布
+                    self.remove_record(keyword_key(keyword, &self.local_peer_id()))
+                        .await?;
+                    self.stop_providing(keyword_cid(keyword)).await?;
+                }
+                Ok(_) => {}
+                Err(Error::Shutdown) => return Err(Error::Shutdown),
+                Err(e) => warn!("Failed to unpublish keyword {}: {}", keyword, e),
+            }
+        }
+        Ok(())
+    }
+
+    // 修改本节点关键词索引记录的最新版本并重新发布，返回修改后的条目数
+    async fn update_keyword(
+        &mut self,
+        keyword: &str,
+        update: impl FnOnce(&mut Vec<IndexEntry>),
+    ) -> Result<usize> {
+        let local = self.local_peer_id();
+        let key = keyword_key(keyword, &local);
+        let previous = match self.get_records(key.clone()).await {
+            Ok(values) => {
+                KeywordRecord::latest(keyword, &local, values.iter().map(|v| v.as_slice()))
+            }
+            Err(Error::Shutdown) => return Err(Error::Shutdown),
+            Err(_) => None,
+        };
+        let sequence = next_sequence(previous.as_ref().map(|record| record.sequence));
+        let mut entries = previous.map(|record| record.entries).unwrap_or_default();
+        update(&mut entries);
+        if entries.len() > MAX_ENTRIES {
+            return Err(Error::InvalidArgument(format!(
+                "More than {} files have keyword {}",
+                MAX_ENTRIES, keyword
+            )));
+        }
+        let len = entries.len();
+        let record = KeywordRecord::sign(&self.keypair, keyword, sequence, entries)?;
+        match self.put_record(key, record.encode()).await {
+            // 记录已保存在本地，由事件循环定期重新公布
+            Err(e @ Error::PutRecord(_)) => {
+                warn!("Failed to replicate keyword record of {}: {}", keyword, e)
+            }
+            result => result?,
+        }
+        Ok(len)
+    }
+
+    // 搜索名称路径或标签包含查询中所有关键词的文件，返回文件信息和提供节点数
+    pub async fn search(&mut self, query: &str) -> Result<Vec<SearchResult>> {
+        let keywords = search::keywords([query]);
+        if keywords.is_empty() {
+            return Err(Error::InvalidArgument(
+                "Expect at least one keyword of two or more characters.".to_string(),
+            ));
+        }
+        // (发布者, 名称路径) -> 文件
+        let mut matches: Option<BTreeMap<(PeerId, String), IndexEntry>> = None;
+        for keyword in &keywords {
+            let mut found = BTreeMap::new();
+            for publisher in self.get_providers(keyword_cid(keyword)).await? {
+                let values = match self.get_records(keyword_key(keyword, &publisher)).await {
+                    Ok(values) => values,
+                    Err(Error::Shutdown) => return Err(Error::Shutdown),
+                    Err(e) => {
+                        debug!(
+                            "Keyword record of {} by {} not found: {}",
+                            keyword, publisher, e
+                        );
+                        continue;
+                    }
+                };
+                let record =
+                    KeywordRecord::latest(keyword, &publisher, values.iter().map(|v| v.as_slice()));
+                if let Some(record) = record {
+                    found.extend(
+                        record
+                            .entries
+                            .into_iter()
+                            .map(|entry| ((publisher, entry.path.clone()), entry)),
+                    );
+                }
+            }
+            matches = Some(match matches {
+                Some(matches) => matches
+                    .into_iter()
+                    .filter(|(key, _)| found.contains_key(key))
+                    .collect(),
+                None => found,
+            });
+        }
+
+        let mut results = Vec::new();
+        for ((publisher, path), entry) in matches.unwrap_or_default() {
+            let providers = self.get_providers(entry.root).await?.len();
+            results.push(SearchResult {
+                name: format!("{}/{}", publisher, path),
+                root: entry.root,
+                size: entry.size,
+                is_dir: entry.is_dir,
+                providers,
+            });
+        }
+        Ok(results)
+    }
+
+    // 请求数据块，校验内容后返回接收到的临时文件路径。
+    // 内容与内容标识不一致时，将提供节点加入黑名单
+    pub async fn request_block(
         &mut self,
         peer: PeerId,
-        file_name: String,
-    ) -> Result<String, Box<dyn Error + Send>> {
-        let (sender, receiver) = oneshot::channel();
-        self.sender
-            .send(Command::RequestFile {
-                file_name,
+        cid: Cid,
+        token: Option<Capability>,
+    ) -> Result<PathBuf> {
+        let tmp = self
+            .execute(|sender| Command::RequestBlock {
+                cid,
+                token,
                 peer,
                 sender,
             })
+            .await?;
+
+        let data = tokio::fs::read(&tmp).await?;
+        if Cid::of(&data) != cid {
+            let _ = tokio::fs::remove_file(&tmp).await;
+            let error = Error::CorruptedBlock { peer, cid };
+            warn!("{}", error);
+            self.blacklist_peer(peer).await?;
+            return Err(error);
+        }
+        Ok(tmp)
+    }
+
+    // 将节点加入黑名单，不再向其请求数据，也不再将其作为提供节点
+    pub async fn blacklist_peer(&mut self, peer: PeerId) -> Result<()> {
+        self.send(Command::BlacklistPeer { peer }).await
+    }
+
+    pub async fn respond_file(
+        &mut self,
+        peer: PeerId,
+        file: PathBuf,
+        channel: ResponseChannel<FileResponse>,
+    ) -> Result<()> {
+        self.send(Command::RespondFile {
+            peer,
+            file,
+            channel,
+        })
+        .await
+    }
+
+    pub async fn respond_forbidden(
+        &mut self,
+        channel: ResponseChannel<FileResponse>,
+    ) -> Result<()> {
+        self.send(Command::RespondForbidden { channel }).await
+    }
+
+    // 请求节点目录的一页
+    pub async fn request_catalog(
+        &mut self,
+        peer: PeerId,
+        request: CatalogRequest,
+    ) -> Result<CatalogPage> {
+        self.execute(|sender| Command::RequestCatalog {
+            peer,
+            request,
+            sender,
+        })
+        .await
+    }
+
+    // 逐页请求节点的目录，返回本节点可以访问的所有文件和目录
+    pub async fn list_files(&mut self, peer: PeerId) -> Result<Vec<CatalogEntry>> {
+        let mut entries = Vec::new();
+        let mut after = None;
+        loop {
+            let request = CatalogRequest {
+                after: after.clone(),
+                limit: PAGE_SIZE,
+            };
+            let page = self.request_catalog(peer, request).await?;
+            entries.extend(page.entries);
+            match page.next {
+                // 名称路径必须递增，防止节点返回的目录无限循环
+                Some(next) if after.as_ref().is_none_or(|after| next > *after) => {
+                    after = Some(next)
+                }
+                Some(next) => {
+                    return Err(Error::InvalidData(format!(
+                        "Catalog of {} does not advance after {}",
+                        peer, next
+                    )))
+                }
+                None => return Ok(entries),
+            }
+        }
+    }
+
+    pub async fn respond_catalog(
+        &mut self,
+        page: CatalogPage,
+        channel: ResponseChannel<FileResponse>,
+    ) -> Result<()> {
+        self.send(Command::RespondCatalog { page, channel }).await
+    }
+
+    // 请求文件清单下各数据块的弱校验和
+    pub async fn request_checksums(
+        &mut self,
+        peer: PeerId,
+        cid: Cid,
+        token: Option<Capability>,
+    ) -> Result<Vec<u32>> {
+        self.execute(|sender| Command::RequestChecksums {
+            cid,
+            token,
+            peer,
+            sender,
+        })
+        .await
+    }
+
+    pub async fn respond_checksums(
+        &mut self,
+        checksums: Vec<u32>,
+        channel: ResponseChannel<FileResponse>,
+    ) -> Result<()> {
+        self.send(Command::RespondChecksums { checksums, channel })
             .await
-            .expect("Command receiver not to be dropped.");
-        receiver.await.expect("Sender not be dropped.")
     }
 
-    #[allow(dead_code)]
-    pub async fn respond_file(&mut self, file: String, channel: ResponseChannel<FileResponse>) {
-        self.sender
-            .send(Command::RespondFile { file, channel })
+    pub async fn request_replica(
+        &mut self,
+        peer: PeerId,
+        request: ReplicaRequest,
+    ) -> Result<ReplicaResponse> {
+        self.execute(|sender| Command::RequestReplica {
+            peer,
+            request,
+            sender,
+        })
+        .await
+    }
+
+    pub async fn respond_replica(
+        &mut self,
+        response: ReplicaResponse,
+        channel: ResponseChannel<ReplicaResponse>,
+    ) -> Result<()> {
+        self.send(Command::RespondReplica { response, channel })
             .await
-            .expect("Command receiver not to be dropped.");
+    }
+
+    pub async fn send_exchange(&mut self, peer: PeerId, message: ExchangeMessage) -> Result<()> {
+        self.execute(|sender| Command::SendExchange {
+            peer,
+            message,
+            sender,
+        })
+        .await
+    }
+
+    pub async fn respond_exchange(&mut self, channel: ResponseChannel<()>) -> Result<()> {
+        self.send(Command::RespondExchange { channel }).await
+    }
+
+    pub fn exchange(&self) -> &Exchange {
+        &self.exchange
+    }
+
+    // 数据块交换消息不经过`/dfs`编解码器，发送和接收数据块前在此申请带宽
+    pub async fn throttle_upload(&self, peer: PeerId, bytes: usize) {
+        self.bandwidth.upload(Some(peer), bytes).await
+    }
+
+    pub async fn throttle_download(&self, peer: PeerId, bytes: usize) {
+        self.bandwidth.download(Some(peer), bytes).await
+    }
+
+    // 当前的带宽限制
+    pub fn bandwidth_limits(&self) -> RateLimits {
+        self.bandwidth.limits()
+    }
+
+    // 调整带宽限制，对进行中的传输立即生效
+    pub fn set_bandwidth_limits(&self, limits: RateLimits) {
+        self.bandwidth.set_limits(limits)
+    }
+
+    // 用本节点私钥原地解密加密给本节点的文件
+    pub fn decrypt(&self, path: &Path) -> Result<()> {
+        crypto::decrypt_in_place(&self.keypair, path)
+    }
+
+    // 为节点签发访问根清单下内容的能力令牌，由本节点校验
+    pub fn grant(&self, root: Cid, grantee: PeerId, ttl: Duration) -> Result<Capability> {
+        Capability::issue(&self.keypair, root, grantee, ttl)
+    }
+
+    pub async fn network_status(&mut self) -> Result<NetworkStatus> {
+        let (sender, receiver) = oneshot::channel();
+        self.send(Command::NetworkStatus { sender }).await?;
+        receiver.await.map_err(|_| Error::Shutdown)
+    }
+
+    // 列出本节点宣称提供的文件，包括之前运行时公布、从数据目录恢复的文件
+    pub async fn advertised(&mut self) -> Result<Vec<Advertised>> {
+        let (sender, receiver) = oneshot::channel();
+        self.send(Command::Advertised { sender }).await?;
+        receiver.await.map_err(|_| Error::Shutdown)
+    }
+}
+
+// 新版本记录的序号，取当前时间和已有最新版本加一中的较大值
+fn next_sequence(previous: Option<u64>) -> u64 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+    previous.map_or(now, |previous| now.max(previous + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sequences_increase_past_previous() {
+        let first = next_sequence(None);
+        assert!(first > 0);
+        assert!(next_sequence(Some(first)) > first);
+        // 上一个序号来自时钟更快的节点时仍然递增
+        let ahead = first + 1_000_000;
+        assert_eq!(next_sequence(Some(ahead)), ahead + 1);
     }
 }
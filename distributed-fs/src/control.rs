@@ -0,0 +1,254 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::UnixStream,
+};
+
+use crate::{
+    bandwidth::RateLimits,
+    catalog::CatalogEntry,
+    client::{Advertised, NetworkStatus},
+    download::Progress,
+    error::{Error, Result},
+    exchange::LedgerStatus,
+    replication::Replica,
+    search::SearchResult,
+    store::{Cid, GcStats},
+    sync::SyncStats,
+};
+
+// 数据目录下默认的控制接口套接字文件名称
+pub const SOCKET_FILE: &str = "dfs.sock";
+// 控制消息的最大长度
+const MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
+// 守护进程提供的共享文件或目录
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProvidedFile {
+    // 本节点命名空间下的名称路径
+    pub name: String,
+    // 本地路径
+    pub path: PathBuf,
+    // 根清单的内容标识
+    pub root: Cid,
+    // 文件大小或目录总大小
+    pub size: u64,
+    // 是否为目录
+    pub is_dir: bool,
+}
+
+// 提供文件的副本状态
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplicationStatus {
+    // 本节点命名空间下的名称路径
+    pub name: String,
+    // 根清单的内容标识
+    pub root: Cid,
+    // 期望的副本数
+    pub wanted: usize,
+    // DHT上本节点之外的提供节点数
+    pub providers: usize,
+}
+
+// 控制接口请求，路径均为绝对路径
+#[derive(Debug, Serialize, Deserialize)]
+pub enum ControlRequest {
+    // 提供文件或目录，`allow`不为空时只允许其中的节点和持有能力令牌的节点访问，
+    // `replicas`为在其他节点上维持的副本数，`recipients`不为空时提供加密给它们的文件，
+    // `tags`为名称路径之外用于搜索的标签
+    Provide {
+        path: PathBuf,
+        name: String,
+        allow: Option<Vec<String>>,
+        replicas: usize,
+        recipients: Vec<String>,
+        tags: Vec<String>,
+    },
+    // 停止提供文件或目录
+    Unprovide {
+        name: String,
+    },
+    // 获取文件或目录，名称形如`<peer-id>/<path>`，保存路径为空时保存到`dir`下的文件名称
+    Get {
+        name: Option<String>,
+        cid: Option<Cid>,
+        sub_path: Option<PathBuf>,
+        output: Option<PathBuf>,
+        dir: PathBuf,
+        token: Option<String>,
+    },
+    // 将本地路径同步为远端的文件或目录树，`delete`为真时删除远端没有的文件和子目录
+    Sync {
+        name: Option<String>,
+        cid: Option<Cid>,
+        sub_path: Option<PathBuf>,
+        path: PathBuf,
+        delete: bool,
+        token: Option<String>,
+    },
+    // 为节点签发访问提供文件的能力令牌，有效期以秒为单位
+    Grant {
+        name: String,
+        peer: String,
+        ttl: u64,
+    },
+    // 设置提供文件在其他节点上维持的副本数，0表示不再维持
+    Replicate {
+        name: String,
+        replicas: usize,
+    },
+    // 查询提供文件的副本状态和本节点为其他节点持有的副本
+    Replicas,
+    // 删除本节点为其他节点持有的副本
+    DropReplica {
+        root: Cid,
+    },
+    // 固定文件或目录，本地缺失的内容先从网络获取。固定的内容不被垃圾回收
+    Pin {
+        name: Option<String>,
+        cid: Option<Cid>,
+    },
+    // 取消固定
+    Unpin {
+        name: Option<String>,
+        cid: Option<Cid>,
+    },
+    // 列出固定的文件和目录
+    Pins,
+    // 回收未固定且未提供的缓存内容，直到占用不超过存储配额，没有配额时回收所有可回收的内容
+    Gc,
+    // 调整带宽限制，为空的项保持不变，0表示不限制。全部为空时只查询当前限制
+    Bandwidth(RateLimits),
+    // 搜索名称路径或标签包含所有关键词的文件
+    Search {
+        query: String,
+    },
+    // 列出提供的文件和目录
+    List,
+    // 列出节点提供的文件和目录
+    Catalog {
+        peer: String,
+    },
+    // 列出本节点在DHT上宣称提供的文件及其公布状态
+    Advertised,
+    // 查询节点状态
+    Status,
+    // 列出与各节点交换数据块的账本
+    Ledgers,
+}
+
+// 控制接口响应
+#[derive(Debug, Serialize, Deserialize)]
+pub enum ControlResponse {
+    // 提供的文件及其完整名称
+    Provided {
+        file: ProvidedFile,
+        name: String,
+    },
+    Unprovided(ProvidedFile),
+    Saved {
+        cid: Cid,
+        path: PathBuf,
+        size: u64,
+    },
+    Synced {
+        cid: Cid,
+        path: PathBuf,
+        stats: SyncStats,
+    },
+    Files(Vec<ProvidedFile>),
+    // 节点提供的文件和目录
+    Catalog {
+        peer: String,
+        entries: Vec<CatalogEntry>,
+    },
+    SearchResults(Vec<SearchResult>),
+    // 编码后的能力令牌
+    Granted(String),
+    // 设置的副本数
+    Replicating {
+        name: String,
+        replicas: usize,
+    },
+    Replicas {
+        files: Vec<ReplicationStatus>,
+        held: Vec<(Cid, Replica)>,
+    },
+    // 删除的副本的根清单
+    DroppedReplica(Cid),
+    // 固定或取消固定的根清单
+    Pinned(Cid),
+    Unpinned(Cid),
+    // 固定的根清单及其名称和大小
+    Pins(Vec<(Cid, String, u64)>),
+    Collected(GcStats),
+    // 调整后的带宽限制
+    Bandwidth(RateLimits),
+    // 宣称提供的文件及其共享名称
+    Advertised(Vec<(Advertised, Option<String>)>),
+    Status {
+        network: NetworkStatus,
+        provided: usize,
+    },
+    Ledgers(Vec<LedgerStatus>),
+    // 获取过程中的下载进度，最终响应之前可能有多条
+    Progress(Progress),
+    Error(String),
+}
+
+// 读取一条长度前缀的控制消息
+pub async fn read_message<T, M>(io: &mut T) -> io::Result<M>
+where
+    T: AsyncRead + Unpin,
+    M: DeserializeOwned,
+{
+    let len = io.read_u32().await? as usize;
+    if len > MAX_MESSAGE_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Control message of {} bytes exceeds the limit.", len),
+        ));
+    }
+    let mut buf = vec![0u8; len];
+    io.read_exact(&mut buf).await?;
+    bincode::deserialize(&buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+// 写入一条长度前缀的控制消息
+pub async fn write_message<T, M>(io: &mut T, message: &M) -> io::Result<()>
+where
+    T: AsyncWrite + Unpin,
+    M: Serialize,
+{
+    let buf = bincode::serialize(message).map_err(io::Error::other)?;
+    io.write_u32(buf.len() as u32).await?;
+    io.write_all(&buf).await?;
+    io.flush().await
+}
+
+// 向守护进程发送一条请求并等待响应，最终响应之前的下载进度交给`on_progress`
+pub async fn request(
+    socket: &Path,
+    request: &ControlRequest,
+    mut on_progress: impl FnMut(&Progress),
+) -> Result<ControlResponse> {
+    let mut stream = UnixStream::connect(socket).await.map_err(|e| {
+        Error::Control(format!(
+            "Could not connect to daemon at {}: {}",
+            socket.display(),
+            e
+        ))
+    })?;
+    write_message(&mut stream, request).await?;
+    loop {
+        match read_message(&mut stream).await? {
+            ControlResponse::Progress(progress) => on_progress(&progress),
+            response => return Ok(response),
+        }
+    }
+}
@@ -0,0 +1,363 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+use chacha20poly1305::{
+    aead::{Aead, NewAead, Payload},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use curve25519_dalek::edwards::CompressedEdwardsY;
+use libp2p::{
+    identity::{Keypair, PublicKey},
+    PeerId,
+};
+use sha2::{Digest, Sha256, Sha512};
+use x25519_dalek::StaticSecret;
+
+use crate::error::{Error, Result};
+
+// 加密文件开头的标识
+const MAGIC: &[u8; 8] = b"dfs-enc1";
+// 派生密钥封装密钥时的域分隔前缀
+const WRAP_DOMAIN: &[u8] = b"/dfs/encryption/wrap/1";
+// 每段明文的字节数，每段单独加密和认证
+const SEGMENT_SIZE: usize = 64 * 1024;
+// ChaCha20-Poly1305认证标签的字节数
+const TAG_SIZE: usize = 16;
+// 每个接收者的封装文件密钥的字节数
+const SLOT_SIZE: usize = 32 + TAG_SIZE;
+// 接收者的最大数量
+pub const MAX_RECIPIENTS: usize = 256;
+
+// 端到端加密的文件格式：
+//
+// ```text
+// "dfs-enc1" | 临时公钥(32) | 接收者数量(u16) | 封装的文件密钥(48) * n | 密文分段
+// ```
+//
+// 每个文件随机生成文件密钥，用临时X25519密钥与每个接收者的X25519公钥协商出的密钥封装。
+// 接收者的X25519公钥由节点ID中内联的Ed25519公钥换算得到，文件中不记录接收者身份，
+// 解密时逐个尝试封装的文件密钥。明文按段以ChaCha20-Poly1305加密，
+// nonce为段序号和末段标记，防止分段被重排或截断
+
+// 由节点ID中内联的Ed25519公钥换算出X25519公钥
+fn recipient_key(peer: &PeerId) -> Result<x25519_dalek::PublicKey> {
+    let invalid = || {
+        Error::InvalidArgument(format!(
+            "Recipient {} does not have an inlined Ed25519 public key",
+            peer
+        ))
+    };
+    let multihash = peer.as_ref();
+    // 身份哈希的摘要为公钥本身
+    if multihash.code() != 0 {
+        return Err(invalid());
+    }
+    let public = match PublicKey::from_protobuf_encoding(multihash.digest()) {
+        Ok(PublicKey::Ed25519(public)) => public,
+        _ => return Err(invalid()),
+    };
+    let point = CompressedEdwardsY(public.encode())
+        .decompress()
+        .ok_or_else(invalid)?;
+    Ok(x25519_dalek::PublicKey::from(
+        point.to_montgomery().to_bytes(),
+    ))
+}
+
+// 由Ed25519私钥的种子换算出X25519私钥，与公钥的换算一致
+fn local_key(keypair: &Keypair) -> Result<StaticSecret> {
+    match keypair {
+        Keypair::Ed25519(keypair) => {
+            let hash = Sha512::digest(keypair.secret().as_ref());
+            let mut secret = [0u8; 32];
+            secret.copy_from_slice(&hash[..32]);
+            Ok(StaticSecret::from(secret))
+        }
+        _ => Err(Error::InvalidArgument(
+            "Decryption requires an Ed25519 keypair".to_string(),
+        )),
+    }
+}
+
+// 由协商出的共享密钥派生封装文件密钥的密钥
+fn wrap_key(
+    shared: &[u8; 32],
+    ephemeral: &x25519_dalek::PublicKey,
+    recipient: &x25519_dalek::PublicKey,
+) -> ChaCha20Poly1305 {
+    let key = Sha256::new()
+        .chain_update(WRAP_DOMAIN)
+        .chain_update(shared)
+        .chain_update(ephemeral.as_bytes())
+        .chain_update(recipient.as_bytes())
+        .finalize();
+    ChaCha20Poly1305::new(&key)
+}
+
+// 第`index`段的nonce，末段的最后一个字节为1
+fn segment_nonce(index: u64, last: bool) -> [u8; 12] {
+    let mut nonce = [0u8; 12];
+    nonce[..8].copy_from_slice(&index.to_be_bytes());
+    nonce[11] = last as u8;
+    nonce
+}
+
+// 读满`buf`，返回读到的字节数，到达文件末尾时可能不足
+fn read_full(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match reader.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(read)
+}
+
+// 将`src`加密给`recipients`，写入`dst`
+pub fn encrypt_file(src: &Path, dst: &Path, recipients: &[PeerId]) -> Result<()> {
+    if recipients.is_empty() || recipients.len() > MAX_RECIPIENTS {
+        return Err(Error::InvalidArgument(format!(
+            "Expect 1 to {} recipients",
+            MAX_RECIPIENTS
+        )));
+    }
+    let recipients = recipients
+        .iter()
+        .map(recipient_key)
+        .collect::<Result<Vec<_>>>()?;
+
+    let file_key: [u8; 32] = rand::random();
+    let ephemeral = StaticSecret::from(rand::random::<[u8; 32]>());
+    let ephemeral_public = x25519_dalek::PublicKey::from(&ephemeral);
+
+    let mut input = BufReader::new(File::open(src)?);
+    let size = input.get_ref().metadata()?.len();
+    let mut output = BufWriter::new(File::create(dst)?);
+    output.write_all(MAGIC)?;
+    output.write_all(ephemeral_public.as_bytes())?;
+    output.write_all(&(recipients.len() as u16).to_be_bytes())?;
+    for recipient in &recipients {
+        let shared = ephemeral.diffie_hellman(recipient);
+        let slot = wrap_key(shared.as_bytes(), &ephemeral_public, recipient)
+            .encrypt(Nonce::from_slice(&[0u8; 12]), &file_key[..])
+            .map_err(|_| Error::InvalidData("Failed to wrap the file key".to_string()))?;
+        output.write_all(&slot)?;
+    }
+
+    // 空文件也写入一个末段
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(&file_key));
+    let segments = size.div_ceil(SEGMENT_SIZE as u64).max(1);
+    let mut buf = vec![0u8; SEGMENT_SIZE];
+    for index in 0..segments {
+        let last = index + 1 == segments;
+        let expected = if last {
+            (size - index * SEGMENT_SIZE as u64) as usize
+        } else {
+            SEGMENT_SIZE
+        };
+        if read_full(&mut input, &mut buf[..expected])? != expected {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+        let segment = cipher
+            .encrypt(
+                Nonce::from_slice(&segment_nonce(index, last)),
+                Payload {
+                    msg: &buf[..expected],
+                    aad: ephemeral_public.as_bytes(),
+                },
+            )
+            .map_err(|_| Error::InvalidData("Failed to encrypt the file".to_string()))?;
+        output.write_all(&segment)?;
+    }
+    output.flush()?;
+    Ok(())
+}
+
+// 用本节点私钥解密`src`写入`dst`，本节点不是接收者时返回错误
+pub fn decrypt_file(keypair: &Keypair, src: &Path, dst: &Path) -> Result<()> {
+    let invalid = |what: &str| Error::InvalidData(format!("Encrypted file has {}", what));
+    let secret = local_key(keypair)?;
+    let public = x25519_dalek::PublicKey::from(&secret);
+
+    let mut input = BufReader::new(File::open(src)?);
+    let len = input.get_ref().metadata()?.len();
+    let mut header = [0u8; MAGIC.len() + 32 + 2];
+    input.read_exact(&mut header)?;
+    if &header[..MAGIC.len()] != MAGIC {
+        return Err(invalid("no encryption header"));
+    }
+    let mut ephemeral_public = [0u8; 32];
+    ephemeral_public.copy_from_slice(&header[MAGIC.len()..MAGIC.len() + 32]);
+    let ephemeral_public = x25519_dalek::PublicKey::from(ephemeral_public);
+    let count = u16::from_be_bytes([header[header.len() - 2], header[header.len() - 1]]) as usize;
+    if count == 0 || count > MAX_RECIPIENTS {
+        return Err(invalid("an invalid number of recipients"));
+    }
+
+    // 逐个尝试封装的文件密钥
+    let wrap = wrap_key(
+        secret.diffie_hellman(&ephemeral_public).as_bytes(),
+        &ephemeral_public,
+        &public,
+    );
+    let mut file_key = None;
+    let mut slot = [0u8; SLOT_SIZE];
+    for _ in 0..count {
+        input.read_exact(&mut slot)?;
+        if file_key.is_none() {
+            file_key = wrap
+                .decrypt(Nonce::from_slice(&[0u8; 12]), &slot[..])
+                .ok()
+                .filter(|key| key.len() == 32);
+        }
+    }
+    let file_key = file_key.ok_or(Error::NotRecipient)?;
+
+    let body = len - (header.len() + count * SLOT_SIZE) as u64;
+    let sealed = (SEGMENT_SIZE + TAG_SIZE) as u64;
+    let segments = body.div_ceil(sealed).max(1);
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(&file_key));
+    let mut output = BufWriter::new(File::create(dst)?);
+    let mut buf = vec![0u8; SEGMENT_SIZE + TAG_SIZE];
+    for index in 0..segments {
+        let last = index + 1 == segments;
+        let expected = if last {
+            (body - index * sealed) as usize
+        } else {
+            sealed as usize
+        };
+        if expected < TAG_SIZE || read_full(&mut input, &mut buf[..expected])? != expected {
+            return Err(invalid("a truncated segment"));
+        }
+        let segment = cipher
+            .decrypt(
+                Nonce::from_slice(&segment_nonce(index, last)),
+                Payload {
+                    msg: &buf[..expected],
+                    aad: ephemeral_public.as_bytes(),
+                },
+            )
+            .map_err(|_| invalid(&format!("a corrupted segment {}", index)))?;
+        output.write_all(&segment)?;
+    }
+    output.flush()?;
+    Ok(())
+}
+
+// 原地解密下载的文件，先写入同目录下的临时文件再替换
+pub fn decrypt_in_place(keypair: &Keypair, path: &Path) -> Result<()> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".decrypting");
+    let temp = Path::new(&temp);
+    if let Err(e) = decrypt_file(keypair, path, temp) {
+        let _ = fs::remove_file(temp);
+        return Err(e);
+    }
+    fs::rename(temp, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 写入明文并加密给`recipients`，返回临时目录和密文路径
+    fn encrypt(plain: &[u8], recipients: &[&Keypair]) -> (tempfile::TempDir, std::path::PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("plain");
+        let dst = dir.path().join("sealed");
+        fs::write(&src, plain).unwrap();
+        let peers: Vec<PeerId> = recipients.iter().map(|k| k.public().to_peer_id()).collect();
+        encrypt_file(&src, &dst, &peers).unwrap();
+        (dir, dst)
+    }
+
+    fn decrypt(keypair: &Keypair, sealed: &Path) -> Result<Vec<u8>> {
+        let out = sealed.with_extension("out");
+        decrypt_file(keypair, sealed, &out)?;
+        Ok(fs::read(out)?)
+    }
+
+    // 密文中第一个分段的偏移
+    fn body_offset(recipients: usize) -> usize {
+        MAGIC.len() + 32 + 2 + recipients * SLOT_SIZE
+    }
+
+    #[test]
+    fn roundtrip_for_every_recipient() {
+        let alice = Keypair::generate_ed25519();
+        let bob = Keypair::generate_ed25519();
+        for size in [0, 1, SEGMENT_SIZE, SEGMENT_SIZE * 2 + 7] {
+            let plain: Vec<u8> = (0..size).map(|i| (i * 31) as u8).collect();
+            let (_dir, sealed) = encrypt(&plain, &[&alice, &bob]);
+            assert_eq!(decrypt(&alice, &sealed).unwrap(), plain);
+            assert_eq!(decrypt(&bob, &sealed).unwrap(), plain);
+        }
+    }
+
+    #[test]
+    fn rejects_other_keys() {
+        let alice = Keypair::generate_ed25519();
+        let mallory = Keypair::generate_ed25519();
+        let (_dir, sealed) = encrypt(b"secret", &[&alice]);
+        assert!(matches!(
+            decrypt(&mallory, &sealed),
+            Err(Error::NotRecipient)
+        ));
+    }
+
+    #[test]
+    fn rejects_truncated_files() {
+        let alice = Keypair::generate_ed25519();
+        let plain = vec![7u8; SEGMENT_SIZE * 2 + 100];
+        let (_dir, sealed) = encrypt(&plain, &[&alice]);
+        let data = fs::read(&sealed).unwrap();
+        // 删除最后一段后，原来的第二段不带末段标记，无法通过认证
+        let sealed_segment = SEGMENT_SIZE + TAG_SIZE;
+        let cut = body_offset(1) + 2 * sealed_segment;
+        fs::write(&sealed, &data[..cut]).unwrap();
+        assert!(matches!(
+            decrypt(&alice, &sealed),
+            Err(Error::InvalidData(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_reordered_segments() {
+        let alice = Keypair::generate_ed25519();
+        let plain: Vec<u8> = (0..SEGMENT_SIZE * 3)
+            .map(|i| (i / SEGMENT_SIZE) as u8)
+            .collect();
+        let (_dir, sealed) = encrypt(&plain, &[&alice]);
+        let mut data = fs::read(&sealed).unwrap();
+        let sealed_segment = SEGMENT_SIZE + TAG_SIZE;
+        let first = body_offset(1);
+        let (head, tail) = data[first..].split_at_mut(sealed_segment);
+        head.swap_with_slice(&mut tail[..sealed_segment]);
+        fs::write(&sealed, &data).unwrap();
+        assert!(matches!(
+            decrypt(&alice, &sealed),
+            Err(Error::InvalidData(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_tampered_tags() {
+        let alice = Keypair::generate_ed25519();
+        let (_dir, sealed) = encrypt(b"attack at dawn", &[&alice]);
+        let mut data = fs::read(&sealed).unwrap();
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        fs::write(&sealed, &data).unwrap();
+        assert!(matches!(
+            decrypt(&alice, &sealed),
+            Err(Error::InvalidData(_))
+        ));
+    }
+}
@@ -0,0 +1,998 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use libp2p::PeerId;
+use tokio::{
+    net::{UnixListener, UnixStream},
+    sync::{mpsc::Receiver, watch},
+    task::JoinHandle,
+};
+
+use crate::{
+    access::{AccessPolicy, Capability},
+    catalog::Catalog,
+    client::Client,
+    control::{
+        read_message, write_message, ControlRequest, ControlResponse, ProvidedFile,
+        ReplicationStatus,
+    },
+    download::{Downloader, Progress, PROGRESS_INTERVAL},
+    error::{Error, Result},
+    event::Event,
+    get,
+    name::Name,
+    protocol::ReplicaRequest,
+    provide, publish_keywords, publish_name,
+    replication::{maintain_replicas, Replicator},
+    search::file_keywords,
+    serve_blocks,
+    store::{BlockStore, Cid, GcStats, Node},
+    sync_path,
+    watcher::{self, Stamp, WatchFolder, WATCH_DELAY},
+    Target,
+};
+
+// 数据目录下保存提供列表的文件名称
+const PROVIDED_FILE: &str = "provided";
+// 数据目录下保存受限文件允许列表的文件名称
+const ACCESS_FILE: &str = "access";
+// 数据目录下保存曾属于受限文件的内容标识的文件名称
+const WITHDRAWN_FILE: &str = "withdrawn";
+// 数据目录下保存提供文件期望副本数的文件名称
+const REPLICATION_FILE: &str = "replication";
+// 数据目录下保存固定内容的文件名称
+const PINS_FILE: &str = "pins";
+// 数据目录下保存提供文件搜索标签的文件名称
+const TAGS_FILE: &str = "tags";
+// 数据目录下保存加密文件接收者的文件名称
+const RECIPIENTS_FILE: &str = "recipients";
+// 数据目录下保存监视目录中已提供文件状态的文件名称
+const WATCHED_FILE: &str = "watched";
+// 设置存储配额时检查占用的间隔
+const GC_INTERVAL: Duration = Duration::from_secs(60);
+// 检查为其他节点持有的副本是否过期的间隔
+const REPLICA_EXPIRY_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+// 守护进程的存储限制
+#[derive(Debug, Clone, Copy)]
+pub struct StorageLimits {
+    // 数据块存储配额，超出时回收未固定且未提供的缓存内容
+    pub quota: Option<u64>,
+    // 为其他节点持有的副本总大小上限，为空时拒绝副本请求
+    pub replicas: Option<u64>,
+}
+
+// 长期运行的守护进程，保持网络事件循环运行，通过本地套接字接收控制命令，
+// 同时管理多个共享文件
+#[derive(Clone)]
+pub struct Daemon {
+    client: Client,
+    store: BlockStore,
+    // 提供列表的持久化路径
+    index_path: PathBuf,
+    // 按名称索引的提供列表
+    provided: Arc<Mutex<BTreeMap<String, ProvidedFile>>>,
+    // 访问策略
+    policy: AccessPolicy,
+    // 受限文件允许列表的持久化路径
+    access_path: PathBuf,
+    // 按名称索引的受限文件允许访问的节点
+    access: Arc<Mutex<BTreeMap<String, Vec<String>>>>,
+    // 曾属于受限文件的内容标识的持久化路径，停止提供后这些内容仍拒绝访问
+    withdrawn_path: PathBuf,
+    // 期望副本数的持久化路径
+    replication_path: PathBuf,
+    // 按名称索引的提供文件期望的副本数
+    replication: Arc<Mutex<BTreeMap<String, usize>>>,
+    // 搜索标签的持久化路径
+    tags_path: PathBuf,
+    // 按名称索引的提供文件的搜索标签
+    tags: Arc<Mutex<BTreeMap<String, Vec<String>>>>,
+    // 加密文件接收者的持久化路径
+    recipients_path: PathBuf,
+    // 按名称索引的加密文件的接收者，重新提供时沿用
+    recipients: Arc<Mutex<BTreeMap<String, Vec<String>>>>,
+    // 按名称索引的副本维持任务
+    replicating: Arc<Mutex<HashMap<String, JoinHandle<()>>>>,
+    // 检查副本数量的间隔
+    replication_interval: Duration,
+    // 处理其他节点的副本请求
+    replicator: Replicator,
+    // 固定内容的持久化路径
+    pins_path: PathBuf,
+    // 固定的根清单
+    pins: Arc<Mutex<BTreeSet<Cid>>>,
+    // 存储配额，超出时回收未固定且未提供的缓存内容
+    quota: Option<u64>,
+    // 自动提供其中文件的监视目录
+    watch: Option<WatchFolder>,
+    // 监视目录中已提供文件状态的持久化路径
+    watched_path: PathBuf,
+    // 按名称索引的监视目录中已提供文件的大小和修改时间
+    watched: Arc<Mutex<BTreeMap<String, Stamp>>>,
+}
+
+impl Daemon {
+    // 加载数据目录下的提供列表、受限文件的允许列表、期望的副本数、搜索标签、加密文件的接收者、
+    // 固定内容和监视目录中已提供文件的状态
+    pub fn new(
+        client: Client,
+        store: BlockStore,
+        policy: AccessPolicy,
+        data_dir: &Path,
+        replication_interval: Duration,
+        limits: StorageLimits,
+        watch: Option<WatchFolder>,
+    ) -> io::Result<Self> {
+        let index_path = data_dir.join(PROVIDED_FILE);
+        let provided = load_index::<Vec<ProvidedFile>>(&index_path)?
+            .unwrap_or_default()
+            .into_iter()
+            .map(|f| (f.name.clone(), f))
+            .collect();
+        let access_path = data_dir.join(ACCESS_FILE);
+        let access = load_index(&access_path)?.unwrap_or_default();
+        let withdrawn_path = data_dir.join(WITHDRAWN_FILE);
+        policy.withdraw(load_index(&withdrawn_path)?.unwrap_or_default());
+        let replication_path = data_dir.join(REPLICATION_FILE);
+        let replication = load_index(&replication_path)?.unwrap_or_default();
+        let tags_path = data_dir.join(TAGS_FILE);
+        let tags = load_index(&tags_path)?.unwrap_or_default();
+        let recipients_path = data_dir.join(RECIPIENTS_FILE);
+        let recipients = load_index(&recipients_path)?.unwrap_or_default();
+        let replicator = Replicator::new(
+            client.clone(),
+            store.clone(),
+            data_dir,
+            limits.replicas,
+            limits.quota,
+        )?;
+        let pins_path = data_dir.join(PINS_FILE);
+        let pins = load_index(&pins_path)?.unwrap_or_default();
+        let watched_path = data_dir.join(WATCHED_FILE);
+        let watched = load_index(&watched_path)?.unwrap_or_default();
+        Ok(Self {
+            client,
+            store,
+            index_path,
+            provided: Arc::new(Mutex::new(provided)),
+            policy,
+            access_path,
+            access: Arc::new(Mutex::new(access)),
+            withdrawn_path,
+            replication_path,
+            replication: Arc::new(Mutex::new(replication)),
+            tags_path,
+            tags: Arc::new(Mutex::new(tags)),
+            recipients_path,
+            recipients: Arc::new(Mutex::new(recipients)),
+            replicating: Default::default(),
+            replication_interval,
+            replicator,
+            pins_path,
+            pins: Arc::new(Mutex::new(pins)),
+            quota: limits.quota,
+            watch,
+            watched_path,
+            watched: Arc::new(Mutex::new(watched)),
+        })
+    }
+
+    // 重新公布已提供文件的名称记录，在套接字上接收控制命令，直到收到中断信号
+    pub async fn run(self, socket: &Path, events: Receiver<Event>) -> Result<()> {
+        let listener = bind(socket)?;
+        info!("Control API listening on {}", socket.display());
+
+        // 在返回数据块之前恢复受限文件的访问策略
+        let provided: Vec<ProvidedFile> = self.provided().values().cloned().collect();
+        for file in &provided {
+            if let Err(e) = self.restrict(file) {
+                warn!("Failed to restore access policy of {}: {}", file.name, e);
+            }
+        }
+        tokio::spawn(serve_blocks(
+            self.client.clone(),
+            self.store.clone(),
+            self.policy.clone(),
+            Catalog::new(self.provided.clone()),
+            Some(self.replicator.clone()),
+            events,
+        ));
+
+        // 提供者记录由事件循环从数据目录恢复并定期重新公布，缺失的重新宣称提供
+        let advertised: HashSet<Cid> = self
+            .client
+            .clone()
+            .advertised()
+            .await?
+            .into_iter()
+            .map(|a| a.cid)
+            .collect();
+        for file in provided {
+            let mut daemon = self.clone();
+            let advertised = advertised.contains(&file.root);
+            tokio::spawn(async move {
+                daemon.announce(&file, advertised).await;
+                info!("Providing {} as {}", file.name, file.root);
+                let replicas = daemon.replication().get(&file.name).copied();
+                if let Some(replicas) = replicas {
+                    daemon.replicate(&file, replicas);
+                }
+            });
+        }
+
+        if let Some(folder) = self.watch.clone() {
+            tokio::spawn(self.clone().watch_folder(folder));
+        }
+
+        // 未设置存储配额时不自动回收
+        let mut gc = tokio::time::interval(GC_INTERVAL);
+        let mut expiry = tokio::time::interval(REPLICA_EXPIRY_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = expiry.tick() => {
+                    let mut daemon = self.clone();
+                    tokio::spawn(async move { daemon.expire_replicas().await });
+                }
+                _ = gc.tick(), if self.quota.is_some() => {
+                    let mut daemon = self.clone();
+                    tokio::spawn(async move { daemon.enforce_quota().await });
+                }
+                accepted = listener.accept() => match accepted {
+                    Ok((stream, _)) => {
+                        tokio::spawn(self.clone().handle(stream));
+                    }
+                    Err(e) => warn!("Failed to accept control connection: {}", e),
+                },
+                _ = tokio::signal::ctrl_c() => {
+                    info!("Shutting down daemon");
+                    let _ = fs::remove_file(socket);
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    // 处理一个控制连接上的请求
+    async fn handle(mut self, mut stream: UnixStream) {
+        let request: ControlRequest = match read_message(&mut stream).await {
+            Ok(request) => request,
+            Err(e) => {
+                warn!("Invalid control request: {}", e);
+                return;
+            }
+        };
+        debug!("Control request {:?}", request);
+
+        // 执行请求的同时将下载进度发送给控制端
+        let (progress, mut updates) = watch::channel(Progress::default());
+        let execute = self.execute(request, progress);
+        tokio::pin!(execute);
+        let mut last_update: Option<Instant> = None;
+        let result = loop {
+            tokio::select! {
+                result = &mut execute => break result,
+                Ok(()) = updates.changed() => {
+                    if last_update.is_some_and(|last| last.elapsed() < PROGRESS_INTERVAL) {
+                        continue;
+                    }
+                    last_update = Some(Instant::now());
+                    let update = ControlResponse::Progress(updates.borrow().clone());
+                    if let Err(e) = write_message(&mut stream, &update).await {
+                        debug!("Failed to send progress: {}", e);
+                    }
+                }
+            }
+        };
+        let response = result.unwrap_or_else(|e| ControlResponse::Error(e.to_string()));
+        if let Err(e) = write_message(&mut stream, &response).await {
+            warn!("Failed to send control response: {}", e);
+        }
+    }
+
+    async fn execute(
+        &mut self,
+        request: ControlRequest,
+        progress: watch::Sender<Progress>,
+    ) -> Result<ControlResponse> {
+        match request {
+            ControlRequest::Provide {
+                path,
+                name,
+                allow,
+                replicas,
+                recipients,
+                tags,
+            } => {
+                if replicas > 0 && allow.is_some() {
+                    return Err(Error::InvalidArgument(
+                        "Only public files can be replicated.".to_string(),
+                    ));
+                }
+                let peers = allow.as_deref().map(parse_peers).transpose()?;
+                let keys = recipients
+                    .iter()
+                    .map(|peer| parse_peer(peer))
+                    .collect::<Result<_>>()?;
+                let (root, node, name) = provide(
+                    &mut self.client,
+                    &self.store,
+                    &self.policy,
+                    &path,
+                    &name,
+                    peers,
+                    keys,
+                )
+                .await?;
+                let file = ProvidedFile {
+                    name: name.path.clone(),
+                    path,
+                    root,
+                    size: node.size(),
+                    is_dir: matches!(node, Node::Directory(_)),
+                };
+                let previous = self.provided().insert(file.name.clone(), file.clone());
+                // 手动提供接管该名称后不再作为监视目录中的文件处理，监视目录提供时随后重新记录
+                if self.watched().remove(&file.name).is_some() {
+                    save_index(&self.watched_path, &*self.watched())?;
+                }
+                match allow {
+                    Some(allow) => self.access().insert(file.name.clone(), allow),
+                    None => self.access().remove(&file.name),
+                };
+                match recipients.is_empty() {
+                    true => self.recipients().remove(&file.name),
+                    false => self.recipients().insert(file.name.clone(), recipients),
+                };
+                self.set_replicas(&file, replicas);
+                // 重新提供时从索引中删除不再匹配的关键词
+                let keywords = self.keywords(&file.name);
+                match tags.is_empty() {
+                    true => self.tags().remove(&file.name),
+                    false => self.tags().insert(file.name.clone(), tags.clone()),
+                };
+                self.save()?;
+                // 名称指向新内容后，停止提供没有其他名称使用的旧版本
+                if let Some(previous) = previous.filter(|previous| previous.root != root) {
+                    self.release(previous.root).await?;
+                }
+                let stale = keywords
+                    .difference(&file_keywords(&file.name, &tags))
+                    .cloned()
+                    .collect();
+                self.client.unpublish_keywords(&file.name, &stale).await?;
+                publish_keywords(&mut self.client, root, &node, &name, &tags).await;
+                Ok(ControlResponse::Provided {
+                    file,
+                    name: name.to_string(),
+                })
+            }
+            ControlRequest::Unprovide { name } => {
+                let file = self
+                    .provided()
+                    .remove(&name)
+                    .ok_or_else(|| Error::NotFound(format!("Provided file {}", name)))?;
+                self.access().remove(&name);
+                self.set_replicas(&file, 0);
+                let keywords = self.keywords(&name);
+                self.tags().remove(&name);
+                self.recipients().remove(&name);
+                self.policy.remove(&name);
+                self.save()?;
+                self.client.unpublish_keywords(&name, &keywords).await?;
+                self.release(file.root).await?;
+                let name = Name::new(self.client.local_peer_id(), &file.name)?;
+                self.client.unpublish_name(&name).await?;
+                info!("Stopped providing {} ({})", file.name, file.root);
+                Ok(ControlResponse::Unprovided(file))
+            }
+            ControlRequest::Get {
+                name,
+                cid,
+                sub_path,
+                output,
+                dir,
+                token,
+            } => {
+                let target = parse_target(name, cid, sub_path, token)?;
+                let (cid, node, path) = get(
+                    self.client.clone(),
+                    &self.store,
+                    target,
+                    output,
+                    &dir,
+                    progress,
+                )
+                .await?;
+                self.enforce_quota().await;
+                Ok(ControlResponse::Saved {
+                    cid,
+                    path,
+                    size: node.size(),
+                })
+            }
+            ControlRequest::Sync {
+                name,
+                cid,
+                sub_path,
+                path,
+                delete,
+                token,
+            } => {
+                let target = parse_target(name, cid, sub_path, token)?;
+                let (cid, stats) = sync_path(
+                    self.client.clone(),
+                    &self.store,
+                    target,
+                    &path,
+                    delete,
+                    progress,
+                )
+                .await?;
+                self.enforce_quota().await;
+                Ok(ControlResponse::Synced { cid, path, stats })
+            }
+            ControlRequest::Grant { name, peer, ttl } => {
+                let root = self
+                    .provided()
+                    .get(&name)
+                    .map(|file| file.root)
+                    .ok_or_else(|| Error::NotFound(format!("Provided file {}", name)))?;
+                if !self.access().contains_key(&name) {
+                    warn!("{} is public, the capability is not required", name);
+                }
+                let peer = parse_peer(&peer)?;
+                let token = self.client.grant(root, peer, Duration::from_secs(ttl))?;
+                info!("Granted {} access to {} for {}s", peer, name, ttl);
+                Ok(ControlResponse::Granted(token.to_string()))
+            }
+            ControlRequest::Replicate { name, replicas } => {
+                let file = self
+                    .provided()
+                    .get(&name)
+                    .cloned()
+                    .ok_or_else(|| Error::NotFound(format!("Provided file {}", name)))?;
+                if replicas > 0 && self.access().contains_key(&name) {
+                    return Err(Error::InvalidArgument(
+                        "Only public files can be replicated.".to_string(),
+                    ));
+                }
+                self.set_replicas(&file, replicas);
+                self.save()?;
+                Ok(ControlResponse::Replicating { name, replicas })
+            }
+            ControlRequest::Replicas => {
+                let wanted: Vec<(String, usize)> = self
+                    .replication()
+                    .iter()
+                    .map(|(name, replicas)| (name.clone(), *replicas))
+                    .collect();
+                let local = self.client.local_peer_id();
+                let mut files = Vec::new();
+                for (name, wanted) in wanted {
+                    let root = match self.provided().get(&name) {
+                        Some(file) => file.root,
+                        None => continue,
+                    };
+                    let providers = self.client.get_providers(root).await?;
+                    files.push(ReplicationStatus {
+                        name,
+                        root,
+                        wanted,
+                        providers: providers.iter().filter(|p| **p != local).count(),
+                    });
+                }
+                let held = self
+                    .replicator
+                    .replicas()
+                    .iter()
+                    .map(|(root, replica)| (*root, replica.clone()))
+                    .collect();
+                Ok(ControlResponse::Replicas { files, held })
+            }
+            ControlRequest::DropReplica { root } => {
+                if !self.replicator.remove(&root)? {
+                    return Err(Error::NotFound(format!("Replica {}", root)));
+                }
+                self.release(root).await?;
+                info!("Dropped replica {}", root);
+                Ok(ControlResponse::DroppedReplica(root))
+            }
+            ControlRequest::Pin { name, cid } => {
+                let root = self.resolve(name, cid).await?;
+                if !self.store.is_complete(&root) {
+                    let providers = self.client.get_providers(root).await?;
+                    if providers.is_empty() {
+                        return Err(Error::NoProviders(root));
+                    }
+                    let mut downloader = Downloader::new(
+                        self.client.clone(),
+                        self.store.clone(),
+                        providers,
+                        None,
+                        progress,
+                    );
+                    downloader.hold(root);
+                    downloader.fetch_tree(root).await?;
+                }
+                self.pins().insert(root);
+                save_index(&self.pins_path, &*self.pins())?;
+                info!("Pinned {}", root);
+                Ok(ControlResponse::Pinned(root))
+            }
+            ControlRequest::Unpin { name, cid } => {
+                let root = self.resolve(name, cid).await?;
+                if !self.pins().remove(&root) {
+                    return Err(Error::NotFound(format!("Pin {}", root)));
+                }
+                save_index(&self.pins_path, &*self.pins())?;
+                info!("Unpinned {}", root);
+                Ok(ControlResponse::Unpinned(root))
+            }
+            ControlRequest::Pins => {
+                let pins: Vec<Cid> = self.pins().iter().copied().collect();
+                Ok(ControlResponse::Pins(
+                    pins.into_iter()
+                        .map(|root| match self.store.node(&root) {
+                            Ok(node) => (root, node.name().to_string(), node.size()),
+                            Err(_) => (root, String::new(), 0),
+                        })
+                        .collect(),
+                ))
+            }
+            ControlRequest::Gc => Ok(ControlResponse::Collected(
+                self.collect_garbage(self.quota.unwrap_or(0)).await?,
+            )),
+            ControlRequest::Bandwidth(changes) => {
+                let limits = self.client.bandwidth_limits().merge(changes);
+                if limits != self.client.bandwidth_limits() {
+                    self.client.set_bandwidth_limits(limits);
+                    info!("Bandwidth limits set to {}", limits);
+                }
+                Ok(ControlResponse::Bandwidth(limits))
+            }
+            ControlRequest::Search { query } => Ok(ControlResponse::SearchResults(
+                self.client.search(&query).await?,
+            )),
+            ControlRequest::Catalog { peer } => {
+                let peer = parse_peer(&peer)?;
+                Ok(ControlResponse::Catalog {
+                    peer: peer.to_string(),
+                    entries: self.client.list_files(peer).await?,
+                })
+            }
+            ControlRequest::List => Ok(ControlResponse::Files(
+                self.provided().values().cloned().collect(),
+            )),
+            ControlRequest::Advertised => {
+                let advertised = self.client.advertised().await?;
+                let provided = self.provided();
+                Ok(ControlResponse::Advertised(
+                    advertised
+                        .into_iter()
+                        .map(|a| {
+                            let name = provided
+                                .values()
+                                .find(|f| f.root == a.cid)
+                                .map(|f| f.name.clone());
+                            (a, name)
+                        })
+                        .collect(),
+                ))
+            }
+            ControlRequest::Status => Ok(ControlResponse::Status {
+                network: self.client.network_status().await?,
+                provided: self.provided().len(),
+            }),
+            ControlRequest::Ledgers => {
+                Ok(ControlResponse::Ledgers(self.client.exchange().ledgers()))
+            }
+        }
+    }
+
+    // 监视目录中文件的创建、修改和删除，相应地提供、重新提供或停止提供。
+    // 启动时先与上次记录的状态比较，处理守护进程未运行期间的变化
+    async fn watch_folder(mut self, folder: WatchFolder) {
+        // 监视器被丢弃时停止监视，需要在整个循环期间持有
+        let (_watcher, mut changes) = match watcher::watch(&folder.path) {
+            Ok(watch) => watch,
+            Err(e) => {
+                warn!("Failed to watch {}: {}", folder.path.display(), e);
+                return;
+            }
+        };
+        info!("Watching {} as {}", folder.path.display(), folder.name);
+        self.update_watched(&folder, &folder.path).await;
+
+        // 路径在一段时间内没有继续变化后再处理，避免提供写入到一半的文件
+        let mut pending: HashMap<PathBuf, Instant> = HashMap::new();
+        let mut check = tokio::time::interval(WATCH_DELAY / 2);
+        loop {
+            tokio::select! {
+                change = changes.recv() => match change {
+                    Some(path) => {
+                        pending.insert(path, Instant::now());
+                    }
+                    None => {
+                        warn!("Stopped watching {}", folder.path.display());
+                        return;
+                    }
+                },
+                _ = check.tick(), if !pending.is_empty() => {
+                    let ready: Vec<PathBuf> = pending
+                        .iter()
+                        .filter(|(_, changed)| changed.elapsed() >= WATCH_DELAY)
+                        .map(|(path, _)| path.clone())
+                        .collect();
+                    for path in ready {
+                        pending.remove(&path);
+                        self.update_watched(&folder, &path).await;
+                    }
+                }
+            }
+        }
+    }
+
+    // 同步监视目录中`path`下的文件与提供列表：提供新增或有变化的文件，
+    // 停止提供已删除的文件。只处理由监视目录提供的文件
+    async fn update_watched(&mut self, folder: &WatchFolder, path: &Path) {
+        let prefix = match folder.name_of(path) {
+            Some(prefix) => prefix,
+            None => return,
+        };
+        let files = match folder.scan(path) {
+            Ok(files) => files,
+            Err(e) => {
+                warn!("Failed to scan {}: {}", path.display(), e);
+                return;
+            }
+        };
+        let removed: Vec<String> = self
+            .watched()
+            .keys()
+            .filter(|name| {
+                *name == &prefix
+                    || name
+                        .strip_prefix(&prefix)
+                        .is_some_and(|rest| rest.starts_with('/'))
+            })
+            .filter(|name| !files.contains_key(*name))
+            .cloned()
+            .collect();
+        for name in removed {
+            self.watched().remove(&name);
+            // 只停止提供仍指向监视目录中文件的名称
+            let watched = self
+                .provided()
+                .get(&name)
+                .is_some_and(|file| file.path.starts_with(&folder.path));
+            if !watched {
+                continue;
+            }
+            let request = ControlRequest::Unprovide { name: name.clone() };
+            if let Err(e) = self
+                .execute(request, watch::channel(Progress::default()).0)
+                .await
+            {
+                warn!("Failed to stop providing {}: {}", name, e);
+            }
+        }
+
+        for (name, path) in files {
+            let stamp = match Stamp::of(&path) {
+                Ok(stamp) => stamp,
+                Err(e) => {
+                    debug!("Failed to stat {}: {}", path.display(), e);
+                    continue;
+                }
+            };
+            let (provided, taken_over) = match self.provided().get(&name) {
+                Some(file) => (file.path == path, !file.path.starts_with(&folder.path)),
+                None => (false, false),
+            };
+            // 已手动提供监视目录之外内容的名称不再自动提供
+            if taken_over || (provided && self.watched().get(&name) == Some(&stamp)) {
+                continue;
+            }
+            // 重新提供时保留允许列表、副本数、接收者和搜索标签
+            let request = ControlRequest::Provide {
+                path: path.clone(),
+                name: name.clone(),
+                allow: self.access().get(&name).cloned(),
+                replicas: self.replication().get(&name).copied().unwrap_or(0),
+                recipients: self.recipients().get(&name).cloned().unwrap_or_default(),
+                tags: self.tags().get(&name).cloned().unwrap_or_default(),
+            };
+            match self
+                .execute(request, watch::channel(Progress::default()).0)
+                .await
+            {
+                Ok(_) => {
+                    self.watched().insert(name, stamp);
+                }
+                Err(e) => warn!("Failed to provide {}: {}", path.display(), e),
+            }
+        }
+        if let Err(e) = save_index(&self.watched_path, &*self.watched()) {
+            warn!("Failed to save watched files: {}", e);
+        }
+    }
+
+    // 公布名称记录，尚未宣称提供时在DHT上宣称提供文件
+    async fn announce(&mut self, file: &ProvidedFile, advertised: bool) {
+        let node = match self.store.node(&file.root) {
+            Ok(node) => node,
+            Err(e) => {
+                warn!("Manifest of {} is missing: {}", file.name, e);
+                return;
+            }
+        };
+        if !advertised {
+            if let Err(e) = self.client.start_providing(file.root).await {
+                warn!("Failed to announce provider record: {}", e);
+            }
+        }
+        match Name::new(self.client.local_peer_id(), &file.name) {
+            Ok(name) => publish_name(&mut self.client, file.root, &node, &name).await,
+            Err(e) => warn!("Invalid name {}: {}", file.name, e),
+        }
+    }
+
+    // 解析名称或内容标识对应的根清单
+    async fn resolve(&mut self, name: Option<String>, cid: Option<Cid>) -> Result<Cid> {
+        match (cid, name) {
+            (Some(cid), _) => Ok(cid),
+            (None, Some(name)) => {
+                let name = name.parse::<Name>().map_err(Error::InvalidArgument)?;
+                Ok(self.client.resolve_name(&name).await?.root)
+            }
+            (None, None) => Err(Error::InvalidArgument(
+                "Expect either --name or --cid.".to_string(),
+            )),
+        }
+    }
+
+    // 其他提供的名称或持有的副本没有使用该根清单时停止提供。
+    // 相同内容可能以多个名称提供，不能因为停止提供其中一个而撤回提供者记录
+    async fn release(&mut self, root: Cid) -> Result<()> {
+        let in_use = self.provided().values().any(|file| file.root == root)
+            || self.replicator.replicas().contains_key(&root);
+        if in_use {
+            debug!("Still providing {} under another name", root);
+            return Ok(());
+        }
+        self.client.stop_providing(root).await
+    }
+
+    // 回收缓存内容直到占用不超过`quota`。固定的内容、提供的文件、持有的副本和正在获取的内容不被回收
+    async fn collect_garbage(&mut self, quota: u64) -> Result<GcStats> {
+        let mut roots: HashSet<Cid> = self.pins().iter().copied().collect();
+        roots.extend(self.provided().values().map(|file| file.root));
+        roots.extend(self.replicator.replicas().keys().copied());
+        roots.extend(self.replicator.fetching_roots());
+        roots.extend(self.store.active_roots());
+        roots.extend(self.client.advertised().await?.into_iter().map(|a| a.cid));
+        // 获取中或不完整的内容只能遍历本地已有的清单节点
+        let mut protected = HashSet::new();
+        for root in roots {
+            protected.extend(self.store.local_cids(&root));
+        }
+        let stats = self.store.collect_garbage(&protected, quota)?;
+        // 已回收的内容不再需要拒绝访问，之后从其他节点获得时视为缓存
+        if self.policy.retain_withdrawn(|cid| self.store.has(cid)) {
+            save_index(&self.withdrawn_path, &self.policy.withdrawn())?;
+        }
+        if stats.removed > 0 {
+            info!(
+                "Removed {} blocks ({} bytes), {} bytes in use",
+                stats.removed, stats.freed, stats.used
+            );
+        }
+        Ok(stats)
+    }
+
+    // 删除请求节点不再请求的副本，没有其他用途时停止提供，内容留给垃圾回收
+    async fn expire_replicas(&mut self) {
+        let expired = match self.replicator.expire() {
+            Ok(expired) => expired,
+            Err(e) => {
+                warn!("Failed to expire replicas: {}", e);
+                return;
+            }
+        };
+        for root in expired {
+            info!("Replica {} expired", root);
+            if let Err(e) = self.release(root).await {
+                warn!("Failed to stop providing {}: {}", root, e);
+            }
+        }
+    }
+
+    // 占用超出存储配额时回收缓存内容
+    async fn enforce_quota(&mut self) {
+        let quota = match self.quota {
+            Some(quota) => quota,
+            None => return,
+        };
+        match self.store.usage() {
+            Ok(used) if used <= quota => return,
+            Ok(_) => {}
+            Err(e) => {
+                warn!("Failed to check storage usage: {}", e);
+                return;
+            }
+        }
+        match self.collect_garbage(quota).await {
+            Ok(stats) if stats.used > quota => warn!(
+                "Storage usage {} bytes exceeds the quota of {} bytes",
+                stats.used, quota
+            ),
+            Ok(_) => {}
+            Err(e) => warn!("Failed to collect garbage: {}", e),
+        }
+    }
+
+    // 记录期望的副本数并重新开始维持副本，0表示不再维持
+    fn set_replicas(&self, file: &ProvidedFile, replicas: usize) {
+        if replicas > 0 {
+            self.replication().insert(file.name.clone(), replicas);
+        } else {
+            self.replication().remove(&file.name);
+        }
+        self.replicate(file, replicas);
+    }
+
+    // 启动副本维持任务，替换该文件已有的任务
+    fn replicate(&self, file: &ProvidedFile, replicas: usize) {
+        let mut replicating = self.replicating();
+        if let Some(task) = replicating.remove(&file.name) {
+            task.abort();
+        }
+        if replicas == 0 {
+            return;
+        }
+        let request = ReplicaRequest {
+            root: file.root,
+            size: file.size,
+        };
+        let task = tokio::spawn(maintain_replicas(
+            self.client.clone(),
+            request,
+            replicas,
+            self.replication_interval,
+        ));
+        replicating.insert(file.name.clone(), task);
+    }
+
+    // 设置文件的访问策略，不在允许列表中的文件公开
+    fn restrict(&self, file: &ProvidedFile) -> Result<()> {
+        let allow = self
+            .access()
+            .get(&file.name)
+            .map(|peers| parse_peers(peers))
+            .transpose()?;
+        let cids = self.store.cids(&file.root)?;
+        self.policy.set(&file.name, file.root, cids, allow);
+        Ok(())
+    }
+
+    fn provided(&self) -> std::sync::MutexGuard<'_, BTreeMap<String, ProvidedFile>> {
+        self.provided.lock().expect("Lock not to be poisoned.")
+    }
+
+    fn access(&self) -> std::sync::MutexGuard<'_, BTreeMap<String, Vec<String>>> {
+        self.access.lock().expect("Lock not to be poisoned.")
+    }
+
+    fn replication(&self) -> std::sync::MutexGuard<'_, BTreeMap<String, usize>> {
+        self.replication.lock().expect("Lock not to be poisoned.")
+    }
+
+    fn tags(&self) -> std::sync::MutexGuard<'_, BTreeMap<String, Vec<String>>> {
+        self.tags.lock().expect("Lock not to be poisoned.")
+    }
+
+    fn recipients(&self) -> std::sync::MutexGuard<'_, BTreeMap<String, Vec<String>>> {
+        self.recipients.lock().expect("Lock not to be poisoned.")
+    }
+
+    // 文件的搜索关键词，取自名称路径和标签
+    fn keywords(&self, name: &str) -> BTreeSet<String> {
+        let tags = self.tags().get(name).cloned().unwrap_or_default();
+        file_keywords(name, &tags)
+    }
+
+    fn pins(&self) -> std::sync::MutexGuard<'_, BTreeSet<Cid>> {
+        self.pins.lock().expect("Lock not to be poisoned.")
+    }
+
+    fn watched(&self) -> std::sync::MutexGuard<'_, BTreeMap<String, Stamp>> {
+        self.watched.lock().expect("Lock not to be poisoned.")
+    }
+
+    fn replicating(&self) -> std::sync::MutexGuard<'_, HashMap<String, JoinHandle<()>>> {
+        self.replicating.lock().expect("Lock not to be poisoned.")
+    }
+
+    // 持久化提供列表、允许列表、曾属于受限文件的内容、期望的副本数、搜索标签和接收者
+    fn save(&self) -> io::Result<()> {
+        let files: Vec<ProvidedFile> = self.provided().values().cloned().collect();
+        save_index(&self.index_path, &files)?;
+        save_index(&self.access_path, &*self.access())?;
+        save_index(&self.withdrawn_path, &self.policy.withdrawn())?;
+        save_index(&self.replication_path, &*self.replication())?;
+        save_index(&self.tags_path, &*self.tags())?;
+        save_index(&self.recipients_path, &*self.recipients())
+    }
+}
+
+// 读取数据目录下的索引文件，文件不存在时返回空
+pub fn load_index<T: serde::de::DeserializeOwned>(path: &Path) -> io::Result<Option<T>> {
+    match fs::read(path) {
+        Ok(data) => bincode::deserialize(&data)
+            .map(Some)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+// 先写入临时文件再替换，避免写入中断时损坏索引文件
+pub fn save_index<T: serde::Serialize>(path: &Path, value: &T) -> io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    fs::write(
+        &tmp,
+        bincode::serialize(value).expect("Index to be serializable."),
+    )?;
+    fs::rename(tmp, path)
+}
+
+fn parse_peer(peer: &str) -> Result<PeerId> {
+    peer.parse()
+        .map_err(|e| Error::InvalidArgument(format!("Invalid peer ID {}: {}", peer, e)))
+}
+
+// 解析控制请求中的名称和能力令牌
+fn parse_target(
+    name: Option<String>,
+    cid: Option<Cid>,
+    sub_path: Option<PathBuf>,
+    token: Option<String>,
+) -> Result<Target> {
+    Ok(Target {
+        name: name
+            .map(|name| name.parse::<Name>())
+            .transpose()
+            .map_err(Error::InvalidArgument)?,
+        cid,
+        sub_path,
+        token: token
+            .map(|token| token.parse::<Capability>())
+            .transpose()
+            .map_err(Error::InvalidArgument)?,
+    })
+}
+
+// 解析允许列表中的节点ID
+fn parse_peers(peers: &[String]) -> Result<HashSet<PeerId>> {
+    peers.iter().map(|peer| parse_peer(peer)).collect()
+}
+
+// 绑定控制套接字，清理上次异常退出遗留的套接字文件
+fn bind(socket: &Path) -> Result<UnixListener> {
+    if socket.exists() {
+        if std::os::unix::net::UnixStream::connect(socket).is_ok() {
+            return Err(Error::Control(format!(
+                "A daemon is already listening on {}",
+                socket.display()
+            )));
+        }
+        fs::remove_file(socket)?;
+    }
+    Ok(UnixListener::bind(socket)?)
+}
@@ -0,0 +1,649 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use futures::{future::BoxFuture, stream::FuturesUnordered, FutureExt, StreamExt};
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+
+use crate::{
+    access::Capability,
+    client::Client,
+    error::{Error, Result},
+    exchange::EXCHANGE_TIMEOUT,
+    partial::PartialFile,
+    store::{set_mode, ActiveRoot, BlockStore, Cid, Directory, Manifest, Node},
+};
+
+// 每个提供节点同时进行中的请求数
+const MAX_IN_FLIGHT_PER_PROVIDER: usize = 4;
+// 提供节点连续失败次数达到该值后不再使用
+const MAX_PROVIDER_FAILURES: usize = 3;
+// 请求耗时超过平均耗时的该倍数时，视为慢节点，由空闲节点重复请求
+const SLOW_FACTOR: u32 = 2;
+// 尚无耗时统计时判定慢请求的时间
+const DEFAULT_SLOW_THRESHOLD: Duration = Duration::from_secs(5);
+// 没有请求完成时，重新检查慢请求的间隔
+const REBALANCE_INTERVAL: Duration = Duration::from_secs(1);
+// 提供节点繁忙时暂停向其请求的初始时间，连续繁忙时加倍
+const BUSY_BACKOFF: Duration = Duration::from_millis(500);
+// 暂停时间的最大加倍次数
+const MAX_BUSY_DOUBLINGS: u32 = 4;
+// 提供节点连续繁忙次数达到该值后不再使用
+const MAX_BUSY_RESPONSES: u32 = 20;
+// 计算当前下载速率的时间窗口
+const RATE_WINDOW: Duration = Duration::from_secs(5);
+// 显示下载进度的最小间隔
+pub const PROGRESS_INTERVAL: Duration = Duration::from_millis(200);
+
+// 下载进度
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Progress {
+    // 已接收的字节数，包括续传前已完成和本地已有的数据块
+    pub received: u64,
+    // 文件大小或目录总大小
+    pub total: u64,
+    // 最近的下载速率（字节每秒）
+    pub rate: f64,
+    // 正在下载数据块的提供节点数
+    pub providers: usize,
+    // 按当前速率估计的剩余时间
+    pub eta: Option<Duration>,
+}
+
+impl fmt::Display for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let percent = match self.total {
+            0 => 100,
+            total => self.received * 100 / total,
+        };
+        write!(
+            f,
+            "{} / {} ({}%), {}/s, {} providers",
+            format_bytes(self.received as f64),
+            format_bytes(self.total as f64),
+            percent,
+            format_bytes(self.rate),
+            self.providers
+        )?;
+        if let Some(eta) = self.eta {
+            write!(f, ", ETA {}s", eta.as_secs())?;
+        }
+        Ok(())
+    }
+}
+
+// 以二进制单位显示字节数
+fn format_bytes(bytes: f64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+// 下载进度统计，通过watch通道发布最新进度
+struct Tracker {
+    sender: watch::Sender<Progress>,
+    // 已接收的字节数，在写入数据块时累加
+    received: Arc<AtomicU64>,
+    total: u64,
+    // 最近的（时间, 已接收字节数）采样，用于计算当前速率
+    samples: VecDeque<(Instant, u64)>,
+}
+
+impl Tracker {
+    fn report(&mut self, providers: usize) {
+        let now = Instant::now();
+        let received = self.received.load(Ordering::Relaxed);
+        self.samples.push_back((now, received));
+        while self.samples.len() > 2 && now.duration_since(self.samples[0].0) > RATE_WINDOW {
+            self.samples.pop_front();
+        }
+        let (start, start_received) = self.samples[0];
+        let elapsed = now.duration_since(start).as_secs_f64();
+        let rate = if elapsed > 0.0 {
+            received.saturating_sub(start_received) as f64 / elapsed
+        } else {
+            0.0
+        };
+        let eta = (rate > 0.0)
+            .then(|| Duration::from_secs_f64(self.total.saturating_sub(received) as f64 / rate));
+        // 没有订阅者时忽略
+        let _ = self.sender.send(Progress {
+            received,
+            total: self.total,
+            rate,
+            providers,
+            eta,
+        });
+    }
+}
+
+// 单个提供节点的下载状态
+#[derive(Debug, Default)]
+struct ProviderState {
+    // 进行中的请求数
+    in_flight: usize,
+    // 连续失败次数
+    failures: usize,
+    // 平均每个数据块的耗时
+    avg_block_time: Option<Duration>,
+    // 连续繁忙响应次数
+    busy: u32,
+    // 繁忙时暂停请求直到该时间
+    busy_until: Option<Instant>,
+}
+
+impl ProviderState {
+    fn is_usable(&self) -> bool {
+        self.failures < MAX_PROVIDER_FAILURES
+    }
+
+    fn is_backing_off(&self, now: Instant) -> bool {
+        self.busy_until.is_some_and(|until| now < until)
+    }
+
+    fn has_slot(&self) -> bool {
+        self.is_usable()
+            && self.in_flight < MAX_IN_FLIGHT_PER_PROVIDER
+            && !self.is_backing_off(Instant::now())
+    }
+
+    fn record_busy(&mut self) {
+        self.busy += 1;
+        if self.busy >= MAX_BUSY_RESPONSES {
+            self.failures = MAX_PROVIDER_FAILURES;
+        }
+        let backoff = BUSY_BACKOFF * 2u32.pow((self.busy - 1).min(MAX_BUSY_DOUBLINGS));
+        self.busy_until = Some(Instant::now() + backoff);
+    }
+
+    fn record_success(&mut self, elapsed: Duration) {
+        self.failures = 0;
+        self.busy = 0;
+        self.avg_block_time = Some(match self.avg_block_time {
+            Some(avg) => (avg * 3 + elapsed) / 4,
+            None => elapsed,
+        });
+    }
+}
+
+// 请求结果：提供节点、数据块、耗时、接收到的临时文件
+type BlockResult = (PeerId, Cid, Duration, Result<PathBuf>);
+
+// 多节点并行下载调度器，将数据块分配给不同的提供节点同时下载，
+// 节点失败时重新分配，节点较慢时由空闲节点重复请求
+pub struct Downloader {
+    client: Client,
+    store: BlockStore,
+    providers: HashMap<PeerId, ProviderState>,
+    // 随每个请求出示的能力令牌
+    token: Option<Capability>,
+    // 下载进度
+    tracker: Tracker,
+    // 正在获取的根清单，下载期间其下的内容不被垃圾回收
+    active: Vec<ActiveRoot>,
+}
+
+impl Downloader {
+    pub fn new(
+        client: Client,
+        store: BlockStore,
+        providers: HashSet<PeerId>,
+        token: Option<Capability>,
+        progress: watch::Sender<Progress>,
+    ) -> Self {
+        Self {
+            client,
+            store,
+            providers: providers
+                .into_iter()
+                .map(|p| (p, ProviderState::default()))
+                .collect(),
+            token,
+            tracker: Tracker {
+                sender: progress,
+                received: Default::default(),
+                total: 0,
+                samples: Default::default(),
+            },
+            active: Vec::new(),
+        }
+    }
+
+    // 下载结束前保留根清单下已存入的内容，不被垃圾回收
+    pub fn hold(&mut self, root: Cid) {
+        self.active.push(self.store.hold(root));
+    }
+
+    // 下载所有本地缺失的数据块，每个数据块经`Client`校验后存入本地存储
+    pub async fn fetch(&mut self, cids: &[Cid]) -> Result<()> {
+        self.fetch_with(cids, |_| Ok(())).await
+    }
+
+    // 将根清单下所有清单节点和数据块下载到本地存储，不写出文件
+    pub async fn fetch_tree(&mut self, root: Cid) -> Result<()> {
+        let mut stack = vec![root];
+        self.fetch(&stack).await?;
+        while let Some(cid) = stack.pop() {
+            match self.store.node(&cid)? {
+                Node::File(manifest) => self.fetch(&manifest.blocks).await?,
+                Node::Directory(directory) => {
+                    let cids: Vec<Cid> = directory.entries.iter().map(|e| e.cid).collect();
+                    self.fetch(&cids).await?;
+                    stack.extend(cids);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // 下载清单节点对应的文件或整个目录树到保存路径
+    pub async fn download(&mut self, root: Cid, node: &Node, output: &Path) -> Result<()> {
+        self.reset_progress(node.size());
+        match node {
+            Node::File(manifest) => self.download_file(root, manifest, output).await?,
+            Node::Directory(directory) => self.download_directory(directory, output).await?,
+        }
+        self.report_progress();
+        Ok(())
+    }
+
+    // 重新开始统计下载进度
+    pub fn reset_progress(&mut self, total: u64) {
+        self.tracker.total = total;
+        self.tracker.received.store(0, Ordering::Relaxed);
+        self.tracker.samples.clear();
+    }
+
+    // 不需要下载的内容计入已接收的字节数
+    pub fn skip(&mut self, bytes: u64) {
+        self.tracker.received.fetch_add(bytes, Ordering::Relaxed);
+        self.report_progress();
+    }
+
+    // 依次向可用的提供节点请求文件清单下各数据块的弱校验和
+    pub async fn request_checksums(&mut self, cid: Cid) -> Result<Vec<u32>> {
+        let peers: Vec<PeerId> = self
+            .providers
+            .iter()
+            .filter(|(_, state)| state.is_usable())
+            .map(|(peer, _)| *peer)
+            .collect();
+        for peer in peers {
+            match self
+                .client
+                .request_checksums(peer, cid, self.token.clone())
+                .await
+            {
+                Ok(checksums) => return Ok(checksums),
+                Err(Error::Shutdown) => return Err(Error::Shutdown),
+                Err(e) => debug!("Failed to get checksums of {} from {}: {}", cid, peer, e),
+            }
+        }
+        Err(Error::NoProviders(cid))
+    }
+
+    // 下载文件，中断后重新执行时从部分文件中断处继续，完成后恢复文件权限
+    pub async fn download_file(
+        &mut self,
+        root: Cid,
+        manifest: &Manifest,
+        output: &Path,
+    ) -> Result<()> {
+        let mut partial = PartialFile::open(output, root, manifest)?;
+        if partial.completed() > 0 {
+            info!(
+                "Resuming download of {} with {}/{} blocks completed",
+                output.display(),
+                partial.completed(),
+                manifest.blocks.len()
+            );
+        }
+        self.fetch_file(manifest, &mut partial).await?;
+        partial.finish(&manifest.hash)?;
+        set_mode(output, manifest.mode)?;
+        Ok(())
+    }
+
+    // 按目录清单在保存路径下重建整个目录树，每层目录的子清单并行获取
+    pub async fn download_directory(&mut self, directory: &Directory, output: &Path) -> Result<()> {
+        let mut stack = vec![(directory.clone(), output.to_path_buf())];
+        // 目录权限在其内容写入完成后再设置，避免只读目录无法写入
+        let mut created = Vec::new();
+        while let Some((directory, path)) = stack.pop() {
+            fs::create_dir_all(&path)?;
+            created.push((path.clone(), directory.mode));
+
+            let cids: Vec<Cid> = directory.entries.iter().map(|e| e.cid).collect();
+            self.fetch(&cids).await?;
+            for entry in &directory.entries {
+                if !entry.is_valid_name() {
+                    return Err(Error::InvalidData(format!(
+                        "Invalid entry name {:?} in directory",
+                        entry.name
+                    )));
+                }
+                let child = path.join(&entry.name);
+                match (self.store.node(&entry.cid)?, entry.is_dir) {
+                    (Node::Directory(sub), true) => stack.push((sub, child)),
+                    (Node::File(manifest), false) => {
+                        self.download_file(entry.cid, &manifest, &child).await?;
+                        debug!("File {} saved", child.display());
+                    }
+                    _ => {
+                        return Err(Error::InvalidData(format!(
+                            "Entry {} does not match its manifest {}",
+                            child.display(),
+                            entry.cid
+                        )))
+                    }
+                }
+            }
+        }
+        for (path, mode) in created.into_iter().rev() {
+            set_mode(&path, mode)?;
+        }
+        Ok(())
+    }
+
+    // 下载文件的数据块并写入部分文件，跳过部分文件中已完成的数据块
+    pub async fn fetch_file(
+        &mut self,
+        manifest: &Manifest,
+        partial: &mut PartialFile,
+    ) -> Result<()> {
+        // 同一数据块可能出现在文件的多个位置
+        let mut indexes: HashMap<Cid, Vec<usize>> = HashMap::new();
+        let received = self.tracker.received.clone();
+        for (index, cid) in manifest.blocks.iter().enumerate() {
+            if partial.is_complete(index) {
+                received.fetch_add(manifest.block_size(index), Ordering::Relaxed);
+            } else {
+                indexes.entry(*cid).or_default().push(index);
+            }
+        }
+
+        let store = self.store.clone();
+        let mut write_block = |cid: &Cid| -> io::Result<()> {
+            let data = store.get(cid)?;
+            for index in indexes.get(cid).into_iter().flatten() {
+                partial.write_block(*index, &data)?;
+                received.fetch_add(manifest.block_size(*index), Ordering::Relaxed);
+            }
+            Ok(())
+        };
+
+        // 本地存储中已有的数据块直接写入
+        let mut missing = Vec::new();
+        for cid in manifest
+            .blocks
+            .iter()
+            .filter(|cid| indexes.contains_key(cid))
+        {
+            if store.has(cid) {
+                match write_block(cid) {
+                    Ok(()) => continue,
+                    // 读取时发现损坏的数据块已从存储中删除，重新获取
+                    Err(e) if !store.has(cid) => debug!("Refetching block {}: {}", cid, e),
+                    Err(e) => return Err(e.into()),
+                }
+            }
+            missing.push(*cid);
+        }
+
+        self.fetch_with(&missing, write_block).await
+    }
+
+    // 下载数据块，每个数据块存入本地存储后调用`on_block`
+    async fn fetch_with<F>(&mut self, cids: &[Cid], mut on_block: F) -> Result<()>
+    where
+        F: FnMut(&Cid) -> io::Result<()>,
+    {
+        // 已调用过`on_block`的数据块。调用方检查之后其他下载可能已存入同一数据块，
+        // 这些数据块同样需要处理
+        let mut handled = HashSet::new();
+        let mut pending = VecDeque::new();
+        for cid in cids {
+            if !handled.insert(*cid) {
+                continue;
+            }
+            if self.store.has(cid) {
+                match on_block(cid) {
+                    Ok(()) => continue,
+                    Err(e) if !self.store.has(cid) => debug!("Refetching block {}: {}", cid, e),
+                    Err(e) => return Err(e.into()),
+                }
+            }
+            handled.remove(cid);
+            pending.push_back(*cid);
+        }
+        if self.client.exchange().is_enabled() {
+            let cids: Vec<Cid> = pending.into_iter().collect();
+            return self.exchange_with(&cids, on_block).await;
+        }
+        // 进行中的请求：数据块 -> (提供节点, 开始时间)
+        let mut in_flight: HashMap<Cid, Vec<(PeerId, Instant)>> = HashMap::new();
+        let mut requests: FuturesUnordered<BoxFuture<'static, BlockResult>> =
+            FuturesUnordered::new();
+        // 提供节点拒绝访问时，下载失败返回该错误
+        let mut forbidden = None;
+
+        loop {
+            // 为每个有空闲位置的提供节点分配待下载的数据块
+            while let Some(peer) = self.idle_provider() {
+                let cid = match pending.pop_front() {
+                    Some(cid) => cid,
+                    None => match self.steal_slow_request(&in_flight, &peer) {
+                        Some(cid) => cid,
+                        None => break,
+                    },
+                };
+                in_flight
+                    .entry(cid)
+                    .or_default()
+                    .push((peer, Instant::now()));
+                requests.push(self.request(peer, cid));
+            }
+
+            if requests.is_empty() {
+                if pending.is_empty() {
+                    return Ok(());
+                }
+                // 剩余的提供节点都繁忙时，等待其恢复后再请求
+                if let Some(until) = self.backoff_until() {
+                    tokio::time::sleep_until(until.into()).await;
+                    continue;
+                }
+                return Err(forbidden.unwrap_or(Error::BlocksUnavailable(pending.len())));
+            }
+
+            let (peer, cid, elapsed, result) =
+                match tokio::time::timeout(REBALANCE_INTERVAL, requests.next()).await {
+                    Ok(next) => next.expect("Requests not to be empty."),
+                    Err(_) => {
+                        self.report_progress();
+                        continue;
+                    }
+                };
+            let state = self
+                .providers
+                .get_mut(&peer)
+                .expect("Provider to be known.");
+            state.in_flight -= 1;
+            let attempts = in_flight.entry(cid).or_default();
+            attempts.retain(|(p, _)| *p != peer);
+            let requested_elsewhere = !attempts.is_empty();
+            if !requested_elsewhere {
+                in_flight.remove(&cid);
+            }
+
+            // 返回损坏内容或拒绝访问的提供节点不再使用
+            if let Err(Error::CorruptedBlock { .. } | Error::Forbidden { .. }) = &result {
+                state.failures = MAX_PROVIDER_FAILURES;
+            }
+
+            // 重复请求时，数据块可能已由其他节点返回，或已由其他下载存入
+            if self.store.has(&cid) {
+                if let Ok(tmp) = result {
+                    let _ = std::fs::remove_file(tmp);
+                    state.record_success(elapsed);
+                }
+                if handled.insert(cid) {
+                    on_block(&cid)?;
+                    self.report_progress();
+                }
+                continue;
+            }
+
+            match result.and_then(|tmp| Ok(self.store.insert_block(&tmp, &cid)?)) {
+                Ok(()) => {
+                    state.record_success(elapsed);
+                    debug!("Block {} fetched from {} in {:?}", cid, peer, elapsed);
+                    handled.insert(cid);
+                    on_block(&cid)?;
+                    self.report_progress();
+                }
+                Err(Error::Busy(_)) => {
+                    state.record_busy();
+                    debug!("Provider {} is busy, retrying block {} later", peer, cid);
+                    if !requested_elsewhere {
+                        pending.push_front(cid);
+                    }
+                }
+                Err(e) => {
+                    state.failures += 1;
+                    warn!("Failed to fetch block {} from {}: {}", cid, peer, e);
+                    // 没有其他节点在下载该数据块时，重新放回队列
+                    if !requested_elsewhere {
+                        pending.push_front(cid);
+                    }
+                    if let Error::Forbidden { .. } = e {
+                        forbidden = Some(e);
+                    }
+                }
+            }
+        }
+    }
+
+    // 通过数据块交换协议向所有提供节点发送想要列表，等待对方推送数据块。
+    // 所有提供节点都回复没有，或长时间没有收到数据块时失败
+    async fn exchange_with<F>(&mut self, cids: &[Cid], mut on_block: F) -> Result<()>
+    where
+        F: FnMut(&Cid) -> io::Result<()>,
+    {
+        if cids.is_empty() {
+            return Ok(());
+        }
+        let peers: HashSet<PeerId> = self.providers.keys().copied().collect();
+        let mut session = self
+            .client
+            .exchange()
+            .want(&peers, cids, self.token.clone());
+        let mut last_block = Instant::now();
+        while session.remaining() > 0 {
+            match tokio::time::timeout(REBALANCE_INTERVAL, session.next()).await {
+                Ok(Some(cid)) => {
+                    last_block = Instant::now();
+                    debug!("Block {} received by exchange", cid);
+                    on_block(&cid)?;
+                }
+                Ok(None) => return Err(Error::Shutdown),
+                Err(_) => {
+                    let stored = session.take_stored(&self.store);
+                    if !stored.is_empty() {
+                        last_block = Instant::now();
+                        for cid in &stored {
+                            debug!("Block {} stored by another download", cid);
+                            on_block(cid)?;
+                        }
+                    } else if session.is_stalled() || last_block.elapsed() > EXCHANGE_TIMEOUT {
+                        if let Some((peer, cid)) = session.forbidden() {
+                            return Err(Error::Forbidden { peer, cid });
+                        }
+                        return Err(Error::BlocksUnavailable(session.remaining()));
+                    }
+                }
+            }
+            self.tracker.report(self.providers.len());
+        }
+        Ok(())
+    }
+
+    // 发布下载进度
+    fn report_progress(&mut self) {
+        let active = self
+            .providers
+            .values()
+            .filter(|state| state.in_flight > 0)
+            .count();
+        self.tracker.report(active);
+    }
+
+    // 选择进行中请求最少、平均耗时最短的空闲提供节点
+    fn idle_provider(&self) -> Option<PeerId> {
+        self.providers
+            .iter()
+            .filter(|(_, state)| state.has_slot())
+            .min_by_key(|(_, state)| (state.in_flight, state.avg_block_time))
+            .map(|(peer, _)| *peer)
+    }
+
+    // 可用的提供节点都在繁忙暂停中时，返回最早恢复的时间
+    fn backoff_until(&self) -> Option<Instant> {
+        let now = Instant::now();
+        self.providers
+            .values()
+            .filter(|state| state.is_usable() && state.is_backing_off(now))
+            .filter_map(|state| state.busy_until)
+            .min()
+    }
+
+    // 找到一个耗时过长且未由该节点请求的数据块，由空闲节点重复请求
+    fn steal_slow_request(
+        &self,
+        in_flight: &HashMap<Cid, Vec<(PeerId, Instant)>>,
+        idle: &PeerId,
+    ) -> Option<Cid> {
+        // 每个数据块最多同时向两个节点请求
+        in_flight
+            .iter()
+            .filter(|(_, attempts)| attempts.len() == 1 && attempts[0].0 != *idle)
+            .filter(|(_, attempts)| {
+                let (peer, started) = attempts[0];
+                let threshold = self.providers[&peer]
+                    .avg_block_time
+                    .map_or(DEFAULT_SLOW_THRESHOLD, |avg| avg * SLOW_FACTOR);
+                started.elapsed() > threshold
+            })
+            .min_by_key(|(_, attempts)| attempts[0].1)
+            .map(|(cid, _)| *cid)
+    }
+
+    fn request(&mut self, peer: PeerId, cid: Cid) -> BoxFuture<'static, BlockResult> {
+        self.providers
+            .get_mut(&peer)
+            .expect("Provider to be known.")
+            .in_flight += 1;
+        let mut client = self.client.clone();
+        let token = self.token.clone();
+        async move {
+            let started = Instant::now();
+            let result = client.request_block(peer, cid, token).await;
+            (peer, cid, started.elapsed(), result)
+        }
+        .boxed()
+    }
+}
@@ -0,0 +1,122 @@
+use std::{error, fmt, io};
+
+use libp2p::{
+    kad::{record::store, AddProviderError, GetRecordError, PutRecordError},
+    request_response::OutboundFailure,
+    swarm::DialError,
+    Multiaddr, PeerId, TransportError,
+};
+
+use crate::store::Cid;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+// distributed-fs的错误类型
+#[derive(Debug)]
+pub enum Error {
+    // 本地文件读写失败
+    Io(io::Error),
+    // 监听本地地址失败
+    Listen(TransportError<io::Error>),
+    // 链接节点失败
+    Dial(DialError),
+    // 正在链接该节点
+    AlreadyDialing(PeerId),
+    // 节点地址中没有节点ID
+    InvalidPeerAddress(Multiaddr),
+    // 命令行参数无效
+    InvalidArgument(String),
+    // DHT本地记录存储失败
+    Store(store::Error),
+    // 宣称提供文件失败
+    StartProviding(AddProviderError),
+    // 存储DHT记录失败
+    PutRecord(PutRecordError),
+    // 获取DHT记录失败
+    GetRecord(GetRecordError),
+    // 名称、记录或目录项不存在
+    NotFound(String),
+    // 没有找到提供文件的节点
+    NoProviders(Cid),
+    // 请求数据块失败
+    Request(OutboundFailure),
+    // 节点已在黑名单中
+    Blacklisted(PeerId),
+    // 提供节点返回的数据块内容与内容标识不一致
+    CorruptedBlock { peer: PeerId, cid: Cid },
+    // 提供节点拒绝访问
+    Forbidden { peer: PeerId, cid: Cid },
+    // 提供节点繁忙
+    Busy(PeerId),
+    // 加密文件的接收者中没有本节点
+    NotRecipient,
+    // 所有提供节点都无法返回剩余的数据块
+    BlocksUnavailable(usize),
+    // 清单或记录内容无效
+    InvalidData(String),
+    // 守护进程控制接口错误
+    Control(String),
+    // 网络事件循环已停止
+    Shutdown,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "I/O error: {}", e),
+            Error::Listen(e) => write!(f, "Failed to listen: {}", e),
+            Error::Dial(e) => write!(f, "Failed to dial: {}", e),
+            Error::AlreadyDialing(peer) => write!(f, "Already dialing peer {}", peer),
+            Error::InvalidPeerAddress(addr) => {
+                write!(f, "Expect peer multiaddr {} to contain peer ID", addr)
+            }
+            Error::InvalidArgument(e) => write!(f, "Invalid argument: {}", e),
+            Error::Store(e) => write!(f, "Record store error: {:?}", e),
+            Error::StartProviding(e) => write!(f, "Failed to start providing: {:?}", e),
+            Error::PutRecord(e) => write!(f, "Failed to put record: {:?}", e),
+            Error::GetRecord(e) => write!(f, "Failed to get record: {:?}", e),
+            Error::NotFound(what) => write!(f, "{} not found", what),
+            Error::NoProviders(cid) => write!(f, "Could not find provider for file {}", cid),
+            Error::Request(e) => write!(f, "Request failed: {}", e),
+            Error::Blacklisted(peer) => write!(f, "Peer {} is blacklisted", peer),
+            Error::CorruptedBlock { peer, cid } => {
+                write!(f, "Provider {} served corrupted block {}", peer, cid)
+            }
+            Error::Forbidden { peer, cid } => {
+                write!(f, "Provider {} denied access to {}", peer, cid)
+            }
+            Error::Busy(peer) => write!(f, "Provider {} is busy", peer),
+            Error::NotRecipient => write!(f, "This node is not a recipient of the encrypted file"),
+            Error::BlocksUnavailable(n) => {
+                write!(f, "None of the providers returned {} remaining blocks", n)
+            }
+            Error::InvalidData(e) => write!(f, "Invalid data: {}", e),
+            Error::Control(e) => write!(f, "{}", e),
+            Error::Shutdown => write!(f, "Network event loop has stopped"),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::Listen(e) => Some(e),
+            Error::Dial(e) => Some(e),
+            Error::Request(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<store::Error> for Error {
+    fn from(e: store::Error) -> Self {
+        Error::Store(e)
+    }
+}
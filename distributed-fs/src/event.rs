@@ -1,32 +1,92 @@
 use std::{
     collections::{HashMap, HashSet},
-    error::Error,
+    num::NonZeroUsize,
+    path::PathBuf,
+    time::{Duration, Instant},
 };
 
 use futures::{io, StreamExt};
 use libp2p::{
     core::either::EitherError,
-    kad::{GetProvidersOk, KademliaEvent, QueryId, QueryResult},
+    kad::{
+        record::{store::RecordStore, Key},
+        GetClosestPeersError, GetClosestPeersOk, GetProvidersError, GetProvidersOk, GetRecordError,
+        GetRecordOk, InboundRequest, KademliaEvent, QueryId, QueryResult, Quorum, Record,
+    },
     multiaddr::Protocol,
     request_response::{RequestId, RequestResponseEvent, RequestResponseMessage, ResponseChannel},
     swarm::{ConnectionHandlerUpgrErr, SwarmEvent},
     PeerId, Swarm,
 };
-use tokio::sync::{mpsc, oneshot};
+use tokio::sync::{
+    mpsc::{self, error::TrySendError},
+    oneshot,
+};
 
-use crate::client::Command;
+use crate::{
+    catalog::CatalogPage,
+    client::{Advertised, Command, NetworkStatus},
+    error::{Error, Result},
+    limit::{Inbound, InboundLimiter, Limits},
+    name::{Name, NameRecord},
+    search::{parse_keyword_key, KeywordRecord},
+    store::Cid,
+};
 
 use super::{
     behaviour::{ComposedBehaviour, ComposedEvent},
-    protocol::{FileRequest, FileResponse},
+    protocol::{ExchangeMessage, FileRequest, FileResponse, ReplicaRequest, ReplicaResponse},
 };
 
+// 命令执行结果的发送端
+type ResultSender<T> = oneshot::Sender<Result<T>>;
+// 组合网络行为中各协议连接处理的错误
+type HandlerError = EitherError<
+    EitherError<
+        EitherError<ConnectionHandlerUpgrErr<io::Error>, io::Error>,
+        ConnectionHandlerUpgrErr<io::Error>,
+    >,
+    ConnectionHandlerUpgrErr<io::Error>,
+>;
+
+// 检查是否有需要重新公布的提供者记录的间隔
+const REPUBLISH_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+// 获取DHT记录时向多个节点查询，从中选出最新的版本
+const GET_RECORD_QUORUM: usize = 3;
+// 公布失败后重试的间隔
+const REPUBLISH_RETRY_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+// 本节点提供的文件在DHT上的公布状态
+struct Advertisement {
+    // 上次成功公布的时间
+    published: Option<Instant>,
+    // 下次公布的时间
+    next_publish: Instant,
+    // 连续公布失败的次数
+    failures: u32,
+}
+
 #[derive(Debug)]
 pub enum Event {
     InboundRequest {
-        request: String,
+        // 请求节点
+        peer: PeerId,
+        // 请求中的能力令牌较大，装箱以免增大其他事件
+        request: Box<FileRequest>,
         channel: ResponseChannel<FileResponse>,
     },
+    ReplicaRequest {
+        // 请求节点
+        peer: PeerId,
+        request: ReplicaRequest,
+        channel: ResponseChannel<ReplicaResponse>,
+    },
+    ExchangeMessage {
+        // 发送节点
+        peer: PeerId,
+        message: ExchangeMessage,
+        channel: ResponseChannel<()>,
+    },
 }
 
 // 事件处理
@@ -38,22 +98,67 @@ pub struct EventLoop {
     // 事件通道发送端
     event_sender: mpsc::Sender<Event>,
     // 缓存等待链接节点的请求
-    pending_dial: HashMap<PeerId, oneshot::Sender<Result<(), Box<dyn Error + Send>>>>,
-    // 缓存节点提供共享文件的请求
-    pending_start_providing: HashMap<QueryId, oneshot::Sender<()>>,
+    pending_dial: HashMap<PeerId, ResultSender<()>>,
+    // 缓存节点提供共享文件的请求，定期重新公布时没有发送端
+    pending_start_providing: HashMap<QueryId, (Cid, Option<ResultSender<()>>)>,
     // 缓存获取提供共享文件节点的请求
-    pending_get_providers: HashMap<QueryId, oneshot::Sender<HashSet<PeerId>>>,
-    // 缓存获取共享文件内容的请求
-    pending_request_file:
-        HashMap<RequestId, oneshot::Sender<Result<String, Box<dyn Error + Send>>>>,
+    pending_get_providers: HashMap<QueryId, ResultSender<HashSet<PeerId>>>,
+    // 缓存获取最近节点的请求
+    pending_get_closest_peers: HashMap<QueryId, ResultSender<Vec<PeerId>>>,
+    // 缓存存储DHT记录的请求
+    pending_put_record: HashMap<QueryId, ResultSender<()>>,
+    // 缓存获取DHT记录的请求
+    pending_get_record: HashMap<QueryId, ResultSender<Vec<Vec<u8>>>>,
+    // 返回过损坏内容的节点
+    blacklist: HashSet<PeerId>,
+    // 缓存获取数据块内容的请求
+    pending_request_block: HashMap<RequestId, (Cid, ResultSender<PathBuf>)>,
+    // 缓存目录请求
+    pending_request_catalog: HashMap<RequestId, ResultSender<CatalogPage>>,
+    // 缓存弱校验和请求
+    pending_request_checksums: HashMap<RequestId, (Cid, ResultSender<Vec<u32>>)>,
+    // 缓存副本请求
+    pending_request_replica: HashMap<RequestId, ResultSender<ReplicaResponse>>,
+    // 缓存等待确认的数据块交换消息
+    pending_send_exchange: HashMap<RequestId, ResultSender<()>>,
+    // 本节点提供的文件
+    advertised: HashMap<Cid, Advertisement>,
+    // 重新公布提供者记录的间隔
+    republish_interval: Duration,
+    // 其他节点上提供者记录的有效期
+    provider_record_ttl: Duration,
+    // 入站数据块请求的准入控制
+    limiter: InboundLimiter,
 }
 
 impl EventLoop {
     pub fn new(
-        swarm: Swarm<ComposedBehaviour>,
+        mut swarm: Swarm<ComposedBehaviour>,
         command_receiver: mpsc::Receiver<Command>,
         event_sender: mpsc::Sender<Event>,
+        republish_interval: Duration,
+        provider_record_ttl: Duration,
+        limits: Limits,
     ) -> Self {
+        // 从数据目录恢复上次运行时提供的文件，网络连接建立后重新公布
+        let next_publish = Instant::now() + REPUBLISH_CHECK_INTERVAL;
+        let advertised = swarm
+            .behaviour_mut()
+            .kademlia
+            .store_mut()
+            .provided()
+            .filter_map(|r| Cid::from_bytes(&r.key.to_vec()))
+            .map(|cid| {
+                (
+                    cid,
+                    Advertisement {
+                        published: None,
+                        next_publish,
+                        failures: 0,
+                    },
+                )
+            })
+            .collect();
         Self {
             swarm,
             command_receiver,
@@ -61,15 +166,32 @@ impl EventLoop {
             pending_dial: Default::default(),
             pending_start_providing: Default::default(),
             pending_get_providers: Default::default(),
-            pending_request_file: Default::default(),
+            pending_get_closest_peers: Default::default(),
+            pending_put_record: Default::default(),
+            pending_get_record: Default::default(),
+            blacklist: Default::default(),
+            pending_request_block: Default::default(),
+            pending_request_catalog: Default::default(),
+            pending_request_checksums: Default::default(),
+            pending_request_replica: Default::default(),
+            pending_send_exchange: Default::default(),
+            advertised,
+            republish_interval,
+            provider_record_ttl,
+            limiter: InboundLimiter::new(limits),
         }
     }
 
     pub async fn run(mut self) {
+        let mut republish = tokio::time::interval(REPUBLISH_CHECK_INTERVAL);
         // 异步轮询事件
         loop {
             tokio::select! {
                 event = self.swarm.next() => self.handle_event(event.expect("Swarm stream to be infinite.")).await,
+                _ = republish.tick() => {
+                    self.republish_due();
+                    self.limiter.prune();
+                }
                 command = self.command_receiver.recv() => match command {
                     Some(c) => self.handle_command(c).await,
                     None=>  return,
@@ -79,68 +201,226 @@ impl EventLoop {
     }
 
     // 异步处理网络行为事件
-    async fn handle_event(
-        &mut self,
-        event: SwarmEvent<ComposedEvent,EitherError<ConnectionHandlerUpgrErr<io::Error>, io::Error>>,
-    ) {
+    async fn handle_event(&mut self, event: SwarmEvent<ComposedEvent, HandlerError>) {
         match event {
             // 节点提供共享文件事件
             SwarmEvent::Behaviour(ComposedEvent::Kademlia(
                 KademliaEvent::OutboundQueryCompleted {
                     id,
-                    result: QueryResult::StartProviding(_),
+                    result: QueryResult::StartProviding(result),
                     ..
                 },
             )) => {
-                // 从缓存中节点提供共享文件的请求
-                let sender: oneshot::Sender<()> = self
-                    .pending_start_providing
-                    .remove(&id)
-                    .expect("Completed query to be previously pending.");
-
-                // 发送命令执行成功状态    
-                let _ = sender.send(());
+                // 从缓存中删除节点提供共享文件的请求，更新公布状态并发送命令执行状态
+                if let Some((cid, sender)) = self.pending_start_providing.remove(&id) {
+                    self.publish_completed(cid, result.is_ok());
+                    if let Some(sender) = sender {
+                        let _ = sender.send(result.map(|_| ()).map_err(Error::StartProviding));
+                    }
+                }
             }
             // 获取提供共享文件的节点事件
             SwarmEvent::Behaviour(ComposedEvent::Kademlia(
                 KademliaEvent::OutboundQueryCompleted {
                     id,
-                    result: QueryResult::GetProviders(Ok(GetProvidersOk { providers, .. })),
+                    result: QueryResult::GetProviders(result),
                     ..
                 },
             )) => {
-                // 从缓存中删除获取提供共享文件节点的请求，并发送提供的节点
-                let _ = self
-                    .pending_get_providers
-                    .remove(&id)
-                    .expect("Completed query to be previously pending.")
-                    .send(providers);
+                // 查询超时时使用已找到的提供节点
+                let providers = match result {
+                    Ok(GetProvidersOk { providers, .. }) => providers,
+                    Err(GetProvidersError::Timeout { providers, .. }) => providers,
+                };
+                // 从缓存中删除获取提供共享文件节点的请求，并发送黑名单之外的提供节点
+                let providers = providers
+                    .into_iter()
+                    .filter(|p| !self.blacklist.contains(p))
+                    .collect();
+                if let Some(sender) = self.pending_get_providers.remove(&id) {
+                    let _ = sender.send(Ok(providers));
+                }
+            }
+            // 获取最近节点事件
+            SwarmEvent::Behaviour(ComposedEvent::Kademlia(
+                KademliaEvent::OutboundQueryCompleted {
+                    id,
+                    result: QueryResult::GetClosestPeers(result),
+                    ..
+                },
+            )) => {
+                // 查询超时时使用已找到的节点
+                let peers = match result {
+                    Ok(GetClosestPeersOk { peers, .. }) => peers,
+                    Err(GetClosestPeersError::Timeout { peers, .. }) => peers,
+                };
+                let peers = peers
+                    .into_iter()
+                    .filter(|p| !self.blacklist.contains(p))
+                    .collect();
+                if let Some(sender) = self.pending_get_closest_peers.remove(&id) {
+                    let _ = sender.send(Ok(peers));
+                }
+            }
+            // 存储DHT记录事件
+            SwarmEvent::Behaviour(ComposedEvent::Kademlia(
+                KademliaEvent::OutboundQueryCompleted {
+                    id,
+                    result: QueryResult::PutRecord(result),
+                    ..
+                },
+            )) => {
+                if let Some(sender) = self.pending_put_record.remove(&id) {
+                    let _ = sender.send(result.map(|_| ()).map_err(Error::PutRecord));
+                }
+            }
+            // 获取DHT记录事件
+            SwarmEvent::Behaviour(ComposedEvent::Kademlia(
+                KademliaEvent::OutboundQueryCompleted {
+                    id,
+                    result: QueryResult::GetRecord(result),
+                    ..
+                },
+            )) => {
+                if let Some(sender) = self.pending_get_record.remove(&id) {
+                    // 网络中的节点少于查询数量时，使用已找到的记录
+                    let records = match result {
+                        Ok(GetRecordOk { records, .. }) => Ok(records),
+                        Err(GetRecordError::QuorumFailed { records, .. })
+                            if !records.is_empty() =>
+                        {
+                            Ok(records)
+                        }
+                        Err(e) => Err(Error::GetRecord(e)),
+                    };
+                    let _ = sender.send(records.and_then(|records| {
+                        if records.is_empty() {
+                            return Err(Error::NotFound("Record".to_string()));
+                        }
+                        Ok(records.into_iter().map(|r| r.record.value).collect())
+                    }));
+                }
+            }
+            // 其他节点存储DHT记录事件，只保存签名有效且不比本地旧的名称记录和关键词索引记录
+            SwarmEvent::Behaviour(ComposedEvent::Kademlia(KademliaEvent::InboundRequest {
+                request:
+                    InboundRequest::PutRecord {
+                        source,
+                        record: Some(record),
+                        ..
+                    },
+            })) => self.store_inbound_record(source, record),
+            // 其他节点宣称提供文件事件
+            SwarmEvent::Behaviour(ComposedEvent::Kademlia(KademliaEvent::InboundRequest {
+                request:
+                    InboundRequest::AddProvider {
+                        record: Some(record),
+                    },
+            })) => {
+                if let Err(e) = self
+                    .swarm
+                    .behaviour_mut()
+                    .kademlia
+                    .store_mut()
+                    .add_provider(record)
+                {
+                    debug!("Provider record not stored: {:?}", e);
+                }
             }
             SwarmEvent::Behaviour(ComposedEvent::Kademlia(_)) => {}
             // 请求文件内容事件
             SwarmEvent::Behaviour(ComposedEvent::RequestResponse(
-                RequestResponseEvent::Message { message, .. },
+                RequestResponseEvent::Message { peer, message },
             )) => match message {
                 RequestResponseMessage::Request {
-                    request, channel, ..
+                    request_id,
+                    request,
+                    channel,
                 } => {
-                    self.event_sender
-                        .send(Event::InboundRequest {
-                            request: request.0,
+                    if self.limiter.admit(peer) {
+                        self.limiter.push(Inbound {
+                            request_id,
+                            peer,
+                            request,
                             channel,
-                        })
-                        .await
-                        .expect("Event receiver not to be dropped.");
+                        });
+                        self.dispatch_inbound();
+                    } else {
+                        debug!("Too many requests, replying busy to {}", peer);
+                        let _ = self
+                            .swarm
+                            .behaviour_mut()
+                            .request_response
+                            .send_response(channel, FileResponse::Busy);
+                    }
                 }
                 RequestResponseMessage::Response {
                     request_id,
                     response,
                 } => {
-                    let _ = self
-                        .pending_request_file
-                        .remove(&request_id)
-                        .expect("Request to still be pending.")
-                        .send(Ok(response.0));
+                    // 目录请求的响应
+                    if let Some(sender) = self.pending_request_catalog.remove(&request_id) {
+                        let unexpected = Error::InvalidData(format!(
+                            "Unexpected response to catalog request from {}",
+                            peer
+                        ));
+                        let _ = sender.send(match response {
+                            FileResponse::Catalog(page) => Ok(page),
+                            FileResponse::Busy => Err(Error::Busy(peer)),
+                            FileResponse::Content { path, .. } => {
+                                let _ = std::fs::remove_file(path);
+                                Err(unexpected)
+                            }
+                            FileResponse::Forbidden | FileResponse::Checksums(_) => Err(unexpected),
+                        });
+                        return;
+                    }
+                    // 弱校验和请求的响应
+                    if let Some((cid, sender)) = self.pending_request_checksums.remove(&request_id)
+                    {
+                        let _ = sender.send(match response {
+                            FileResponse::Checksums(checksums) => Ok(checksums),
+                            FileResponse::Forbidden => Err(Error::Forbidden { peer, cid }),
+                            FileResponse::Busy => Err(Error::Busy(peer)),
+                            FileResponse::Content { path, .. } => {
+                                let _ = std::fs::remove_file(path);
+                                Err(Error::InvalidData(format!(
+                                    "Unexpected response to checksum request from {}",
+                                    peer
+                                )))
+                            }
+                            FileResponse::Catalog(_) => Err(Error::InvalidData(format!(
+                                "Unexpected response to checksum request from {}",
+                                peer
+                            ))),
+                        });
+                        return;
+                    }
+                    match (self.pending_request_block.remove(&request_id), response) {
+                        (Some((_, sender)), FileResponse::Content { path, .. }) => {
+                            let _ = sender.send(Ok(path));
+                        }
+                        (Some((cid, sender)), FileResponse::Forbidden) => {
+                            let _ = sender.send(Err(Error::Forbidden { peer, cid }));
+                        }
+                        (Some((_, sender)), FileResponse::Busy) => {
+                            let _ = sender.send(Err(Error::Busy(peer)));
+                        }
+                        (
+                            Some((_, sender)),
+                            FileResponse::Catalog(_) | FileResponse::Checksums(_),
+                        ) => {
+                            let _ = sender.send(Err(Error::InvalidData(format!(
+                                "Unexpected response to block request from {}",
+                                peer
+                            ))));
+                        }
+                        (None, FileResponse::Content { path, .. }) => {
+                            // 请求方已放弃的响应，删除接收到的临时文件
+                            let _ = std::fs::remove_file(path);
+                        }
+                        (None, _) => {}
+                    }
                 }
             },
             SwarmEvent::Behaviour(ComposedEvent::RequestResponse(
@@ -148,13 +428,125 @@ impl EventLoop {
                     request_id, error, ..
                 },
             )) => {
-                let _ = self
-                    .pending_request_file
-                    .remove(&request_id)
-                    .expect("Request to still be pending.")
-                    .send(Err(Box::new(error)));
+                if let Some((_, sender)) = self.pending_request_block.remove(&request_id) {
+                    let _ = sender.send(Err(Error::Request(error)));
+                } else if let Some(sender) = self.pending_request_catalog.remove(&request_id) {
+                    let _ = sender.send(Err(Error::Request(error)));
+                } else if let Some((_, sender)) = self.pending_request_checksums.remove(&request_id)
+                {
+                    let _ = sender.send(Err(Error::Request(error)));
+                }
+            }
+            SwarmEvent::Behaviour(ComposedEvent::RequestResponse(
+                RequestResponseEvent::InboundFailure {
+                    peer,
+                    request_id,
+                    error,
+                },
+            )) => {
+                warn!("Failed to respond to {}: {}", peer, error);
+                self.limiter.complete(&request_id);
+                self.dispatch_inbound();
             }
             SwarmEvent::Behaviour(ComposedEvent::RequestResponse(
+                RequestResponseEvent::ResponseSent { request_id, .. },
+            )) => {
+                self.limiter.complete(&request_id);
+                self.dispatch_inbound();
+            }
+            // 副本请求事件
+            SwarmEvent::Behaviour(ComposedEvent::Replication(RequestResponseEvent::Message {
+                peer,
+                message,
+            })) => match message {
+                RequestResponseMessage::Request {
+                    request, channel, ..
+                } => {
+                    let event = Event::ReplicaRequest {
+                        peer,
+                        request,
+                        channel,
+                    };
+                    if let Some(Event::ReplicaRequest { channel, .. }) = self.forward(event) {
+                        debug!("Too many events, declining replica request from {}", peer);
+                        let _ = self
+                            .swarm
+                            .behaviour_mut()
+                            .replication
+                            .send_response(channel, ReplicaResponse::Declined);
+                    }
+                }
+                RequestResponseMessage::Response {
+                    request_id,
+                    response,
+                } => {
+                    if let Some(sender) = self.pending_request_replica.remove(&request_id) {
+                        let _ = sender.send(Ok(response));
+                    }
+                }
+            },
+            SwarmEvent::Behaviour(ComposedEvent::Replication(
+                RequestResponseEvent::OutboundFailure {
+                    request_id, error, ..
+                },
+            )) => {
+                if let Some(sender) = self.pending_request_replica.remove(&request_id) {
+                    let _ = sender.send(Err(Error::Request(error)));
+                }
+            }
+            SwarmEvent::Behaviour(ComposedEvent::Replication(
+                RequestResponseEvent::InboundFailure { peer, error, .. },
+            )) => {
+                debug!(
+                    "Failed to respond to replica request from {}: {}",
+                    peer, error
+                );
+            }
+            SwarmEvent::Behaviour(ComposedEvent::Replication(
+                RequestResponseEvent::ResponseSent { .. },
+            )) => {}
+            // 数据块交换事件
+            SwarmEvent::Behaviour(ComposedEvent::Exchange(RequestResponseEvent::Message {
+                peer,
+                message,
+            })) => match message {
+                RequestResponseMessage::Request {
+                    request, channel, ..
+                } => {
+                    let event = Event::ExchangeMessage {
+                        peer,
+                        message: request,
+                        channel,
+                    };
+                    // 丢弃消息时对方发送失败，之后重新发送想要列表
+                    if self.forward(event).is_some() {
+                        debug!("Too many events, dropping exchange message from {}", peer);
+                    }
+                }
+                RequestResponseMessage::Response { request_id, .. } => {
+                    if let Some(sender) = self.pending_send_exchange.remove(&request_id) {
+                        let _ = sender.send(Ok(()));
+                    }
+                }
+            },
+            SwarmEvent::Behaviour(ComposedEvent::Exchange(
+                RequestResponseEvent::OutboundFailure {
+                    request_id, error, ..
+                },
+            )) => {
+                if let Some(sender) = self.pending_send_exchange.remove(&request_id) {
+                    let _ = sender.send(Err(Error::Request(error)));
+                }
+            }
+            SwarmEvent::Behaviour(ComposedEvent::Exchange(
+                RequestResponseEvent::InboundFailure { peer, error, .. },
+            )) => {
+                debug!(
+                    "Failed to acknowledge exchange message from {}: {}",
+                    peer, error
+                );
+            }
+            SwarmEvent::Behaviour(ComposedEvent::Exchange(
                 RequestResponseEvent::ResponseSent { .. },
             )) => {}
             // 本地监听事件
@@ -179,13 +571,26 @@ impl EventLoop {
             SwarmEvent::OutgoingConnectionError { peer_id, error, .. } => {
                 if let Some(peer_id) = peer_id {
                     if let Some(sender) = self.pending_dial.remove(&peer_id) {
-                        let _ = sender.send(Err(Box::new(error)));
+                        let _ = sender.send(Err(Error::Dial(error)));
+                        return;
                     }
                 }
+                warn!("Outgoing connection error: {}", error);
+            }
+            SwarmEvent::IncomingConnectionError { error, .. } => {
+                debug!("Incoming connection error: {}", error);
             }
-            SwarmEvent::IncomingConnectionError { .. } => {}
             SwarmEvent::Dialing(peer_id) => println!("Dialing {}", peer_id),
-            e => panic!("{:?}", e),
+            SwarmEvent::BannedPeer { peer_id, .. } => debug!("Banned peer {} connected", peer_id),
+            SwarmEvent::ExpiredListenAddr { address, .. } => {
+                info!("Local node is no longer listening on {}", address);
+            }
+            SwarmEvent::ListenerClosed {
+                addresses, reason, ..
+            } => {
+                warn!("Listener on {:?} closed: {:?}", addresses, reason);
+            }
+            SwarmEvent::ListenerError { error, .. } => warn!("Listener error: {}", error),
         }
     }
 
@@ -196,7 +601,7 @@ impl EventLoop {
             Command::StartListening { addr, sender } => {
                 let _ = match self.swarm.listen_on(addr) {
                     Ok(_) => sender.send(Ok(())),
-                    Err(e) => sender.send(Err(Box::new(e))),
+                    Err(e) => sender.send(Err(Error::Listen(e))),
                 };
             }
             // 节点加入KAD网络，链接指定节点，插入缓存
@@ -206,7 +611,7 @@ impl EventLoop {
                 sender,
             } => {
                 if self.pending_dial.contains_key(&peer_id) {
-                    todo!("Already dialing peer.");
+                    let _ = sender.send(Err(Error::AlreadyDialing(peer_id)));
                 } else {
                     self.swarm
                         .behaviour_mut()
@@ -220,51 +625,459 @@ impl EventLoop {
                             self.pending_dial.insert(peer_id, sender);
                         }
                         Err(e) => {
-                            let _ = sender.send(Err(Box::new(e)));
+                            let _ = sender.send(Err(Error::Dial(e)));
                         }
                     }
                 }
             }
             // 节点提供共享文件，插入缓存
-            Command::StartProviding { file_name, sender } => {
-                let query_id = self
-                    .swarm
+            Command::StartProviding { cid, sender } => self.publish(cid, Some(sender)),
+            // 停止提供共享文件
+            Command::StopProviding { cid } => {
+                self.advertised.remove(&cid);
+                self.swarm
                     .behaviour_mut()
                     .kademlia
-                    .start_providing(file_name.into_bytes().into())
-                    .expect("No store error.");
-                self.pending_start_providing.insert(query_id, sender);
+                    .stop_providing(&Key::new(&cid.as_bytes()));
             }
             // 获取提供共享文件的节点，插入缓存
-            Command::GetProviders { file_name, sender } => {
+            Command::GetProviders { cid, sender } => {
                 let query_id = self
                     .swarm
                     .behaviour_mut()
                     .kademlia
-                    .get_providers(file_name.into_bytes().into());
+                    .get_providers(Key::new(&cid.as_bytes()));
                 self.pending_get_providers.insert(query_id, sender);
             }
-            // 请求共享文件，插入缓存
-            Command::RequestFile {
-                file_name,
+            // 获取最近节点，插入缓存
+            Command::GetClosestPeers { cid, sender } => {
+                let query_id = self
+                    .swarm
+                    .behaviour_mut()
+                    .kademlia
+                    .get_closest_peers(cid.as_bytes().to_vec());
+                self.pending_get_closest_peers.insert(query_id, sender);
+            }
+            // 存储DHT记录，插入缓存
+            Command::PutRecord { key, value, sender } => {
+                let record = Record {
+                    key: Key::new(&key),
+                    value,
+                    publisher: None,
+                    expires: None,
+                };
+                match self
+                    .swarm
+                    .behaviour_mut()
+                    .kademlia
+                    .put_record(record, Quorum::One)
+                {
+                    Ok(query_id) => {
+                        self.pending_put_record.insert(query_id, sender);
+                    }
+                    Err(e) => {
+                        let _ = sender.send(Err(e.into()));
+                    }
+                }
+            }
+            // 删除本地存储的DHT记录
+            Command::RemoveRecord { key } => {
+                self.swarm
+                    .behaviour_mut()
+                    .kademlia
+                    .remove_record(&Key::new(&key));
+            }
+            // 获取DHT记录，插入缓存
+            Command::GetRecord { key, sender } => {
+                let query_id = self.swarm.behaviour_mut().kademlia.get_record(
+                    Key::new(&key),
+                    Quorum::N(
+                        NonZeroUsize::new(GET_RECORD_QUORUM).expect("Quorum not to be zero."),
+                    ),
+                );
+                self.pending_get_record.insert(query_id, sender);
+            }
+            // 请求数据块，插入缓存
+            Command::RequestBlock {
+                cid,
+                token,
                 peer,
                 sender,
+            } => {
+                if self.blacklist.contains(&peer) {
+                    let _ = sender.send(Err(Error::Blacklisted(peer)));
+                    return;
+                }
+                let request_id = self.swarm.behaviour_mut().request_response.send_request(
+                    &peer,
+                    FileRequest::Block {
+                        cid,
+                        token: token.map(Box::new),
+                        peer: Some(peer),
+                    },
+                );
+                self.pending_request_block.insert(request_id, (cid, sender));
+            }
+            // 将节点加入黑名单
+            Command::BlacklistPeer { peer } => {
+                if self.blacklist.insert(peer) {
+                    warn!("Peer {} blacklisted", peer);
+                }
+            }
+            // 返回共享文件内容
+            Command::RespondFile {
+                peer,
+                file,
+                channel,
+            } => {
+                if self
+                    .swarm
+                    .behaviour_mut()
+                    .request_response
+                    .send_response(
+                        channel,
+                        FileResponse::Content {
+                            path: file,
+                            peer: Some(peer),
+                        },
+                    )
+                    .is_err()
+                {
+                    debug!("Connection closed before the response was sent.");
+                }
+            }
+            // 拒绝访问
+            Command::RespondForbidden { channel } => {
+                if self
+                    .swarm
+                    .behaviour_mut()
+                    .request_response
+                    .send_response(channel, FileResponse::Forbidden)
+                    .is_err()
+                {
+                    debug!("Connection closed before the response was sent.");
+                }
+            }
+            // 请求节点目录，插入缓存
+            Command::RequestCatalog {
+                peer,
+                request,
+                sender,
             } => {
                 let request_id = self
                     .swarm
                     .behaviour_mut()
                     .request_response
-                    .send_request(&peer, FileRequest(file_name));
-                self.pending_request_file.insert(request_id, sender);
+                    .send_request(&peer, FileRequest::Catalog(request));
+                self.pending_request_catalog.insert(request_id, sender);
             }
-            // 返回共享文件内容
-            Command::RespondFile { file, channel } => {
-                self.swarm
+            // 请求弱校验和，插入缓存
+            Command::RequestChecksums {
+                cid,
+                token,
+                peer,
+                sender,
+            } => {
+                if self.blacklist.contains(&peer) {
+                    let _ = sender.send(Err(Error::Blacklisted(peer)));
+                    return;
+                }
+                let request_id = self.swarm.behaviour_mut().request_response.send_request(
+                    &peer,
+                    FileRequest::Checksums {
+                        cid,
+                        token: token.map(Box::new),
+                    },
+                );
+                self.pending_request_checksums
+                    .insert(request_id, (cid, sender));
+            }
+            // 返回弱校验和
+            Command::RespondChecksums { checksums, channel } => {
+                if self
+                    .swarm
+                    .behaviour_mut()
+                    .request_response
+                    .send_response(channel, FileResponse::Checksums(checksums))
+                    .is_err()
+                {
+                    debug!("Connection closed before the response was sent.");
+                }
+            }
+            // 返回目录
+            Command::RespondCatalog { page, channel } => {
+                if self
+                    .swarm
+                    .behaviour_mut()
+                    .request_response
+                    .send_response(channel, FileResponse::Catalog(page))
+                    .is_err()
+                {
+                    debug!("Connection closed before the response was sent.");
+                }
+            }
+            // 请求节点保存副本，插入缓存
+            Command::RequestReplica {
+                peer,
+                request,
+                sender,
+            } => {
+                let request_id = self
+                    .swarm
+                    .behaviour_mut()
+                    .replication
+                    .send_request(&peer, request);
+                self.pending_request_replica.insert(request_id, sender);
+            }
+            // 回复副本请求
+            Command::RespondReplica { response, channel } => {
+                if self
+                    .swarm
+                    .behaviour_mut()
+                    .replication
+                    .send_response(channel, response)
+                    .is_err()
+                {
+                    debug!("Connection closed before the response was sent.");
+                }
+            }
+            // 发送数据块交换消息，插入缓存
+            Command::SendExchange {
+                peer,
+                message,
+                sender,
+            } => {
+                let request_id = self
+                    .swarm
+                    .behaviour_mut()
+                    .exchange
+                    .send_request(&peer, message);
+                self.pending_send_exchange.insert(request_id, sender);
+            }
+            // 确认数据块交换消息
+            Command::RespondExchange { channel } => {
+                if self
+                    .swarm
+                    .behaviour_mut()
+                    .exchange
+                    .send_response(channel, ())
+                    .is_err()
+                {
+                    debug!("Connection closed before the acknowledgement was sent.");
+                }
+            }
+            // 返回本地节点网络状态
+            Command::NetworkStatus { sender } => {
+                let _ = sender.send(NetworkStatus {
+                    peer_id: self.swarm.local_peer_id().to_string(),
+                    listeners: self.swarm.listeners().map(|a| a.to_string()).collect(),
+                    connected_peers: self
+                        .swarm
+                        .connected_peers()
+                        .map(|p| p.to_string())
+                        .collect(),
+                });
+            }
+            // 返回本节点提供的文件
+            Command::Advertised { sender } => {
+                let now = Instant::now();
+                let _ = sender.send(
+                    self.advertised
+                        .iter()
+                        .map(|(cid, ad)| Advertised {
+                            cid: *cid,
+                            published: ad.published.map(|t| now - t),
+                            next_publish: ad.next_publish.saturating_duration_since(now),
+                            failures: ad.failures,
+                            expired: self.is_expired(ad, now),
+                        })
+                        .collect(),
+                );
+            }
+        }
+    }
+
+    // 将有空闲传输位置的排队请求交给服务任务，事件通道已满时回复繁忙
+    fn dispatch_inbound(&mut self) {
+        while let Some(inbound) = self.limiter.next() {
+            let event = Event::InboundRequest {
+                peer: inbound.peer,
+                request: Box::new(inbound.request),
+                channel: inbound.channel,
+            };
+            if let Some(Event::InboundRequest { peer, channel, .. }) = self.forward(event) {
+                debug!("Too many events, replying busy to {}", peer);
+                let _ = self
+                    .swarm
                     .behaviour_mut()
                     .request_response
-                    .send_response(channel, FileResponse(file))
-                    .expect("Connection to peer to be still open.");
+                    .send_response(channel, FileResponse::Busy);
+            }
+        }
+    }
+
+    // 将事件交给服务任务。服务任务会向事件循环发送命令，事件循环不等待服务任务，
+    // 事件通道已满时返回该事件，由调用方回复繁忙或丢弃
+    fn forward(&self, event: Event) -> Option<Event> {
+        match self.event_sender.try_send(event) {
+            Ok(()) => None,
+            Err(TrySendError::Full(event)) => Some(event),
+            Err(TrySendError::Closed(_)) => {
+                debug!("No one is serving requests, dropping event.");
+                None
             }
         }
     }
-}
\ No newline at end of file
+
+    // 在DHT上宣称提供文件，并记录公布状态
+    fn publish(&mut self, cid: Cid, sender: Option<ResultSender<()>>) {
+        let now = Instant::now();
+        let ad = self.advertised.entry(cid).or_insert(Advertisement {
+            published: None,
+            next_publish: now,
+            failures: 0,
+        });
+        // 查询完成前不重复公布
+        ad.next_publish = now + self.republish_interval;
+        match self
+            .swarm
+            .behaviour_mut()
+            .kademlia
+            .start_providing(Key::new(&cid.as_bytes()))
+        {
+            Ok(query_id) => {
+                self.pending_start_providing.insert(query_id, (cid, sender));
+            }
+            Err(e) => {
+                self.publish_completed(cid, false);
+                match sender {
+                    Some(sender) => {
+                        let _ = sender.send(Err(e.into()));
+                    }
+                    None => warn!("Failed to store provider record of {}: {:?}", cid, e),
+                }
+            }
+        }
+    }
+
+    // 更新公布状态，失败时缩短重试间隔
+    fn publish_completed(&mut self, cid: Cid, success: bool) {
+        let now = Instant::now();
+        let expired = match self.advertised.get(&cid) {
+            Some(ad) => self.is_expired(ad, now),
+            // 已停止提供
+            None => return,
+        };
+        let ad = self
+            .advertised
+            .get_mut(&cid)
+            .expect("Advertisement to exist.");
+        if success {
+            ad.published = Some(now);
+            ad.next_publish = now + self.republish_interval;
+            ad.failures = 0;
+            debug!("Provider record of {} published", cid);
+        } else {
+            ad.failures += 1;
+            ad.next_publish = now + REPUBLISH_RETRY_INTERVAL.min(self.republish_interval);
+            if expired {
+                warn!(
+                    "Provider record of {} has expired on the network, {} attempts to republish failed",
+                    cid, ad.failures
+                );
+            } else {
+                warn!("Failed to publish provider record of {}", cid);
+            }
+        }
+    }
+
+    // 重新公布到期的提供者记录
+    fn republish_due(&mut self) {
+        let now = Instant::now();
+        let due: Vec<Cid> = self
+            .advertised
+            .iter()
+            .filter(|(_, ad)| ad.next_publish <= now)
+            .map(|(cid, _)| *cid)
+            .collect();
+        for cid in due {
+            debug!("Republishing provider record of {}", cid);
+            self.publish(cid, None);
+        }
+    }
+
+    // 上次成功公布后超过有效期，其他节点上的提供者记录已过期
+    fn is_expired(&self, ad: &Advertisement, now: Instant) -> bool {
+        ad.published
+            .is_some_and(|t| now.duration_since(t) > self.provider_record_ttl)
+    }
+
+    // 校验其他节点存储的记录，只保存签名有效的名称记录和关键词索引记录，且不以旧版本覆盖新版本
+    fn store_inbound_record(&mut self, source: PeerId, record: Record) {
+        if let Some((keyword, publisher)) = parse_keyword_key(record.key.as_ref()) {
+            return self.store_keyword_record(source, record, &keyword, &publisher);
+        }
+        let name = match Name::from_key(record.key.as_ref()) {
+            Some(name) => name,
+            None => {
+                debug!("Rejecting record from {}: not a name record", source);
+                return;
+            }
+        };
+        let new = match NameRecord::decode_verified(&name, &record.value) {
+            Ok(new) => new,
+            Err(e) => {
+                warn!("Rejecting name record from {}: {}", source, e);
+                return;
+            }
+        };
+        let store = self.swarm.behaviour_mut().kademlia.store_mut();
+        if let Some(existing) = store.get(&record.key) {
+            if let Ok(existing) = NameRecord::decode_verified(&name, &existing.value) {
+                if existing.sequence > new.sequence {
+                    debug!(
+                        "Ignoring stale version {} of {} from {}",
+                        new.sequence, name, source
+                    );
+                    return;
+                }
+            }
+        }
+        if let Err(e) = store.put(record) {
+            debug!("Name record not stored: {:?}", e);
+        }
+    }
+
+    fn store_keyword_record(
+        &mut self,
+        source: PeerId,
+        record: Record,
+        keyword: &str,
+        publisher: &PeerId,
+    ) {
+        let new = match KeywordRecord::decode_verified(keyword, publisher, &record.value) {
+            Ok(new) => new,
+            Err(e) => {
+                warn!("Rejecting keyword record from {}: {}", source, e);
+                return;
+            }
+        };
+        let store = self.swarm.behaviour_mut().kademlia.store_mut();
+        if let Some(existing) = store.get(&record.key) {
+            if let Ok(existing) =
+                KeywordRecord::decode_verified(keyword, publisher, &existing.value)
+            {
+                if existing.sequence > new.sequence {
+                    debug!(
+                        "Ignoring stale version {} of keyword {} by {} from {}",
+                        new.sequence, keyword, publisher, source
+                    );
+                    return;
+                }
+            }
+        }
+        if let Err(e) = store.put(record) {
+            debug!("Keyword record not stored: {:?}", e);
+        }
+    }
+}
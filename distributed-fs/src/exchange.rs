@@ -0,0 +1,914 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use libp2p::{request_response::ResponseChannel, PeerId};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, Notify};
+
+use crate::{
+    access::{AccessPolicy, Capability},
+    client::Client,
+    protocol::{ExchangeMessage, Want},
+    store::{BlockStore, Cid},
+};
+
+// 每条消息中数据块的最大数量
+const MAX_BLOCKS_PER_MESSAGE: usize = 4;
+// 每条消息中数据块的最大总字节数，超过该大小的单个数据块也会单独发送
+const MAX_MESSAGE_BLOCK_BYTES: u64 = 1024 * 1024;
+// 超过该大小的数据块不通过交换协议发送，视为没有
+const MAX_EXCHANGE_BLOCK_SIZE: u64 = 3 * 1024 * 1024;
+// 每条消息中想要、取消、拥有和没有列表各自的最大条目数
+const MAX_ENTRIES_PER_MESSAGE: usize = 1024;
+// 为每个节点记住的想要条目数上限，超出的条目回复没有
+const MAX_PEER_WANTS: usize = 4096;
+// 同时向每个节点请求的数据块数
+const MAX_REQUESTED_PER_PEER: usize = 32;
+// 同时向其发送数据块的节点数，争用时按债务比例从低到高选择
+const MAX_SENDING_PEERS: usize = 4;
+// 没有消息触发时检查本地新获得数据块的间隔
+const SCAN_INTERVAL: Duration = Duration::from_secs(1);
+// 下载时超过该时间没有收到任何数据块则失败
+pub const EXCHANGE_TIMEOUT: Duration = Duration::from_secs(30);
+
+// 对方想要的数据块
+#[derive(Debug, Clone, Copy)]
+struct PeerWant {
+    cid: Cid,
+    have_only: bool,
+    // 已回复没有，获得后再推送
+    told: bool,
+    // 本地数据块的大小，没有或超过交换大小上限时为空
+    size: Option<u64>,
+    // 上次检查本地数据块时的存储版本，尚未检查时为空
+    checked: Option<u64>,
+}
+
+impl PeerWant {
+    // 尚未检查，或上次检查时没有而存储此后有变化
+    fn needs_check(&self, generation: u64) -> bool {
+        self.checked.is_none() || (self.size.is_none() && self.checked != Some(generation))
+    }
+}
+
+// 待发送给节点的控制条目
+#[derive(Debug, Default)]
+struct Outbox {
+    wants: Vec<Want>,
+    cancels: Vec<Cid>,
+    haves: Vec<Cid>,
+    dont_haves: Vec<Cid>,
+    forbidden: Vec<Cid>,
+    // 新会话的能力令牌，随下一条消息发送
+    token: Option<Capability>,
+}
+
+// 与单个节点的账本：双方交换的字节数、对方的想要列表和待发送的消息
+#[derive(Debug, Default)]
+struct Ledger {
+    // 发送给对方的数据块字节数
+    sent: u64,
+    // 从对方收到的本节点想要的数据块字节数
+    received: u64,
+    // 对方想要的数据块，按优先顺序排列
+    wants: Vec<PeerWant>,
+    // 对方最近出示的能力令牌
+    token: Option<Capability>,
+    // 本节点向对方请求中的数据块数
+    requested: usize,
+    outbox: Outbox,
+    // 有消息等待对方确认，确认前不发送下一条
+    sending: bool,
+    // 等待确认的消息中包含数据块
+    sending_blocks: bool,
+}
+
+impl Ledger {
+    // 债务比例：发送给对方的字节数与从对方收到的字节数之比，越低越优先
+    fn debt_ratio(&self) -> f64 {
+        self.sent as f64 / (self.received + 1) as f64
+    }
+}
+
+// 账本摘要
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LedgerStatus {
+    pub peer: String,
+    pub sent: u64,
+    pub received: u64,
+    // 对方想要列表中的条目数
+    pub wants: usize,
+}
+
+// 本节点想要的数据块
+#[derive(Debug)]
+struct Wanted {
+    // 优先级，越小越先请求
+    priority: u64,
+    // 等待该数据块的会话
+    sessions: Vec<(u64, mpsc::UnboundedSender<Cid>)>,
+    // 可以向其请求的节点
+    peers: HashSet<PeerId>,
+    // 已发送过想要条目的节点，收到后向其取消
+    asked: HashSet<PeerId>,
+    // 正在向其请求内容的节点
+    requested: Option<PeerId>,
+    // 回复拥有的节点
+    haves: HashSet<PeerId>,
+    // 回复没有的节点
+    dont_haves: HashSet<PeerId>,
+    // 拒绝访问的节点
+    forbidden: Option<PeerId>,
+}
+
+#[derive(Default)]
+struct State {
+    ledgers: HashMap<PeerId, Ledger>,
+    wants: HashMap<Cid, Wanted>,
+    // 尚未向任何节点请求内容的数据块，按优先级排列
+    queue: BTreeMap<u64, Cid>,
+    next_priority: u64,
+    next_session: u64,
+}
+
+impl State {
+    fn ledger(&mut self, peer: PeerId) -> &mut Ledger {
+        self.ledgers.entry(peer).or_default()
+    }
+
+    // 为没有消息在途的节点组装消息，按债务比例从低到高选择发送数据块的节点。
+    // 只使用已检查的本地数据块大小，尚未检查的想要条目留待下次
+    fn messages(&mut self, policy: &AccessPolicy) -> Vec<(PeerId, ExchangeMessage, Vec<Cid>)> {
+        let mut sending_blocks = self
+            .ledgers
+            .values()
+            .filter(|ledger| ledger.sending_blocks)
+            .count();
+        let mut peers: Vec<(PeerId, f64)> = self
+            .ledgers
+            .iter()
+            .filter(|(_, ledger)| !ledger.sending)
+            .map(|(peer, ledger)| (*peer, ledger.debt_ratio()))
+            .collect();
+        peers.sort_by(|a, b| a.1.total_cmp(&b.1));
+
+        let mut messages = Vec::new();
+        for (peer, _) in peers {
+            let ledger = self.ledger(peer);
+            let outbox = &mut ledger.outbox;
+            let mut blocks = Vec::new();
+            let mut bytes = 0;
+            let may_send_blocks = sending_blocks < MAX_SENDING_PEERS;
+            let token = ledger.token.clone();
+            ledger.wants.retain_mut(|want| {
+                if !policy.is_allowed(&peer, &want.cid, token.as_ref()) {
+                    outbox.forbidden.push(want.cid);
+                    return false;
+                }
+                if want.checked.is_none() {
+                    return true;
+                }
+                let Some(size) = want.size else {
+                    if !want.told {
+                        outbox.dont_haves.push(want.cid);
+                        want.told = true;
+                    }
+                    return true;
+                };
+                if want.have_only {
+                    outbox.haves.push(want.cid);
+                    return false;
+                }
+                let fits = blocks.is_empty()
+                    || (blocks.len() < MAX_BLOCKS_PER_MESSAGE
+                        && bytes + size <= MAX_MESSAGE_BLOCK_BYTES);
+                if may_send_blocks && fits {
+                    blocks.push(want.cid);
+                    bytes += size;
+                    return false;
+                }
+                true
+            });
+
+            let mut message = ExchangeMessage {
+                wants: drain_front(&mut outbox.wants),
+                cancels: drain_front(&mut outbox.cancels),
+                haves: drain_front(&mut outbox.haves),
+                dont_haves: drain_front(&mut outbox.dont_haves),
+                forbidden: drain_front(&mut outbox.forbidden),
+                ..Default::default()
+            };
+            if let Some(token) = outbox.token.take() {
+                message.token = token.encode();
+            }
+            if message.is_empty() && blocks.is_empty() {
+                continue;
+            }
+            ledger.sending = true;
+            ledger.sending_blocks = !blocks.is_empty();
+            if ledger.sending_blocks {
+                sending_blocks += 1;
+            }
+            messages.push((peer, message, blocks));
+        }
+        messages
+    }
+
+    // 为排队的数据块选择请求节点：优先选择回复拥有的节点，其次是未回复没有的节点中
+    // 请求数最少的。同时向其他候选节点询问是否拥有，请求节点回复没有时改向它们请求
+    fn assign(&mut self) {
+        let queued: Vec<(u64, Cid)> = self.queue.iter().map(|(p, c)| (*p, *c)).collect();
+        for (priority, cid) in queued {
+            let wanted = self.wants.get(&cid).expect("Queued block to be wanted.");
+            let ledgers = &self.ledgers;
+            let requested = |peer: &PeerId| ledgers.get(peer).map_or(0, |l| l.requested);
+            let peer = wanted
+                .peers
+                .iter()
+                .filter(|peer| !wanted.dont_haves.contains(peer))
+                .filter(|peer| requested(peer) < MAX_REQUESTED_PER_PEER)
+                .min_by_key(|peer| (!wanted.haves.contains(peer), requested(peer)))
+                .copied();
+            let peer = match peer {
+                Some(peer) => peer,
+                None => continue,
+            };
+            let others: Vec<PeerId> = wanted
+                .peers
+                .iter()
+                .filter(|p| **p != peer && !wanted.asked.contains(p))
+                .copied()
+                .collect();
+
+            self.queue.remove(&priority);
+            let wanted = self
+                .wants
+                .get_mut(&cid)
+                .expect("Queued block to be wanted.");
+            wanted.requested = Some(peer);
+            wanted.asked.insert(peer);
+            wanted.asked.extend(&others);
+            let ledger = self.ledger(peer);
+            ledger.requested += 1;
+            ledger.outbox.wants.push(Want {
+                cid,
+                have_only: false,
+            });
+            for other in others {
+                self.ledger(other).outbox.wants.push(Want {
+                    cid,
+                    have_only: true,
+                });
+            }
+        }
+    }
+
+    // 不再向节点请求该数据块，重新排队
+    fn unrequest(&mut self, cid: &Cid, peer: &PeerId) {
+        let wanted = match self.wants.get_mut(cid) {
+            Some(wanted) if wanted.requested == Some(*peer) => wanted,
+            _ => return,
+        };
+        wanted.requested = None;
+        self.queue.insert(wanted.priority, *cid);
+        let ledger = self.ledger(*peer);
+        ledger.requested = ledger.requested.saturating_sub(1);
+    }
+
+    // 不再想要该数据块，向询问过的节点取消
+    fn remove_want(&mut self, cid: &Cid) -> Option<Wanted> {
+        let wanted = self.wants.remove(cid)?;
+        self.queue.remove(&wanted.priority);
+        if let Some(peer) = wanted.requested {
+            let ledger = self.ledger(peer);
+            ledger.requested = ledger.requested.saturating_sub(1);
+        }
+        for peer in &wanted.asked {
+            self.ledger(*peer).outbox.cancels.push(*cid);
+        }
+        Some(wanted)
+    }
+
+    // 向节点发送消息失败，视为其没有本节点想要的数据块，并清除其想要列表
+    fn fail(&mut self, peer: PeerId) {
+        let cids: Vec<Cid> = self
+            .wants
+            .iter_mut()
+            .filter(|(_, wanted)| wanted.peers.contains(&peer))
+            .map(|(cid, wanted)| {
+                wanted.dont_haves.insert(peer);
+                wanted.haves.remove(&peer);
+                wanted.asked.remove(&peer);
+                *cid
+            })
+            .collect();
+        for cid in cids {
+            self.unrequest(&cid, &peer);
+        }
+        let ledger = self.ledger(peer);
+        ledger.wants.clear();
+        ledger.requested = 0;
+        ledger.outbox = Outbox::default();
+        // 重新排队的数据块改向其他节点请求
+        self.assign();
+    }
+}
+
+// 数据块交换：节点之间交换想要列表和拥有列表，拥有对方想要的数据块时推送给对方，
+// 之后获得的数据块也会推送。每个节点的账本记录双方交换的字节数，
+// 同时想要数据块的节点较多时，优先发送给债务比例低、即回报较多的节点
+#[derive(Clone)]
+pub struct Exchange {
+    state: Arc<Mutex<State>>,
+    // 状态变化后唤醒发送任务
+    wake: Arc<Notify>,
+    // 下载时是否通过交换协议获取数据块，不影响响应其他节点
+    enabled: bool,
+}
+
+impl Exchange {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            state: Default::default(),
+            wake: Default::default(),
+            enabled,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    // 向`peers`请求数据块，返回接收数据块的会话。会话被丢弃时取消其余的数据块
+    pub fn want(
+        &self,
+        peers: &HashSet<PeerId>,
+        cids: &[Cid],
+        token: Option<Capability>,
+    ) -> Session {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let mut state = self.state();
+        let id = state.next_session;
+        state.next_session += 1;
+        for cid in cids {
+            let priority = state.next_priority;
+            let wanted = state.wants.entry(*cid).or_insert_with(|| Wanted {
+                priority,
+                sessions: Vec::new(),
+                peers: HashSet::new(),
+                asked: HashSet::new(),
+                requested: None,
+                haves: HashSet::new(),
+                dont_haves: HashSet::new(),
+                forbidden: None,
+            });
+            wanted.sessions.push((id, sender.clone()));
+            wanted.peers.extend(peers);
+            if wanted.priority == priority {
+                state.next_priority += 1;
+                state.queue.insert(priority, *cid);
+            }
+        }
+        if token.is_some() {
+            for peer in peers {
+                state.ledger(*peer).outbox.token = token.clone();
+            }
+        }
+        state.assign();
+        drop(state);
+        self.wake.notify_one();
+        Session {
+            id,
+            exchange: self.clone(),
+            remaining: cids.iter().copied().collect(),
+            receiver,
+        }
+    }
+
+    // 各节点的账本摘要，按发送的字节数从多到少排列
+    pub fn ledgers(&self) -> Vec<LedgerStatus> {
+        let mut ledgers: Vec<LedgerStatus> = self
+            .state()
+            .ledgers
+            .iter()
+            .map(|(peer, ledger)| LedgerStatus {
+                peer: peer.to_string(),
+                sent: ledger.sent,
+                received: ledger.received,
+                wants: ledger.wants.len(),
+            })
+            .collect();
+        ledgers.sort_by(|a, b| b.sent.cmp(&a.sent).then(b.received.cmp(&a.received)));
+        ledgers
+    }
+
+    // 处理节点发来的消息：更新其想要列表，记录其拥有和没有的数据块，
+    // 校验并保存本节点想要的数据块后通知等待的会话
+    fn receive(
+        &self,
+        store: &BlockStore,
+        policy: &AccessPolicy,
+        peer: PeerId,
+        message: ExchangeMessage,
+    ) {
+        // 在锁外计算内容标识并写入存储，只保存本节点想要的数据块
+        let wanted: HashSet<Cid> = self.state().wants.keys().copied().collect();
+        let mut blocks = Vec::new();
+        for data in &message.blocks {
+            let cid = Cid::of(data);
+            if !wanted.contains(&cid) {
+                debug!("Ignoring unwanted block {} from {}", cid, peer);
+                continue;
+            }
+            match store.put(data) {
+                Ok(_) => blocks.push((cid, data.len() as u64)),
+                Err(e) => warn!("Failed to store block {}: {}", cid, e),
+            }
+        }
+
+        let mut state = self.state();
+        let ledger = state.ledger(peer);
+        if !message.token.is_empty() {
+            match Capability::decode(&message.token) {
+                Ok(token) => ledger.token = Some(token),
+                Err(e) => debug!("Ignoring capability from {}: {}", peer, e),
+            }
+        }
+        if !message.cancels.is_empty() {
+            let cancels: HashSet<&Cid> = message.cancels.iter().collect();
+            ledger.wants.retain(|want| !cancels.contains(&want.cid));
+        }
+        for want in message.wants {
+            if let Some(existing) = ledger.wants.iter_mut().find(|w| w.cid == want.cid) {
+                existing.have_only &= want.have_only;
+            } else if !policy.is_allowed(&peer, &want.cid, ledger.token.as_ref()) {
+                ledger.outbox.forbidden.push(want.cid);
+            } else if ledger.wants.len() < MAX_PEER_WANTS {
+                ledger.wants.push(PeerWant {
+                    cid: want.cid,
+                    have_only: want.have_only,
+                    told: false,
+                    size: None,
+                    checked: None,
+                });
+            } else {
+                ledger.outbox.dont_haves.push(want.cid);
+            }
+        }
+
+        for cid in &message.dont_haves {
+            if let Some(wanted) = state.wants.get_mut(cid) {
+                wanted.dont_haves.insert(peer);
+                wanted.haves.remove(&peer);
+                state.unrequest(cid, &peer);
+            }
+        }
+        // 拒绝访问的节点同样不再请求，所有节点都没有时下载以拒绝访问失败
+        for cid in &message.forbidden {
+            if let Some(wanted) = state.wants.get_mut(cid) {
+                wanted.dont_haves.insert(peer);
+                wanted.haves.remove(&peer);
+                wanted.forbidden = Some(peer);
+                state.unrequest(cid, &peer);
+            }
+        }
+        for cid in &message.haves {
+            match state.wants.get_mut(cid) {
+                Some(wanted) => {
+                    wanted.haves.insert(peer);
+                    wanted.dont_haves.remove(&peer);
+                }
+                None => state.ledger(peer).outbox.cancels.push(*cid),
+            }
+        }
+        for (cid, size) in blocks {
+            let wanted = match state.remove_want(&cid) {
+                Some(wanted) => wanted,
+                None => continue,
+            };
+            state.ledger(peer).received += size;
+            for (_, sender) in wanted.sessions {
+                let _ = sender.send(cid);
+            }
+        }
+        state.assign();
+        drop(state);
+        self.wake.notify_one();
+    }
+
+    // 会话结束，取消只有该会话想要的数据块
+    fn close(&self, session: u64, cids: &HashSet<Cid>) {
+        let mut state = self.state();
+        for cid in cids {
+            let wanted = match state.wants.get_mut(cid) {
+                Some(wanted) => wanted,
+                None => continue,
+            };
+            wanted.sessions.retain(|(id, _)| *id != session);
+            if wanted.sessions.is_empty() {
+                state.remove_want(cid);
+            }
+        }
+        drop(state);
+        self.wake.notify_one();
+    }
+
+    // 所有剩余的数据块都没有可以请求的节点
+    fn is_stalled(&self, cids: &HashSet<Cid>) -> bool {
+        let state = self.state();
+        cids.iter().all(|cid| match state.wants.get(cid) {
+            Some(wanted) => {
+                wanted.requested.is_none()
+                    && wanted.peers.iter().all(|p| wanted.dont_haves.contains(p))
+            }
+            None => false,
+        })
+    }
+
+    // 剩余的数据块中被提供节点拒绝访问的一个
+    fn forbidden(&self, cids: &HashSet<Cid>) -> Option<(PeerId, Cid)> {
+        let state = self.state();
+        cids.iter().find_map(|cid| {
+            let peer = state.wants.get(cid)?.forbidden?;
+            Some((peer, *cid))
+        })
+    }
+
+    // 向节点发送消息，每个节点同时只有一条消息等待确认。状态变化或定期检查时，
+    // 为没有消息在途的节点组装控制条目，并在债务比例最低的节点中选择发送数据块
+    pub async fn run(self, client: Client, store: BlockStore, policy: AccessPolicy) {
+        let mut scan = tokio::time::interval(SCAN_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = self.wake.notified() => {}
+                _ = scan.tick() => {}
+            }
+            for (peer, message, blocks) in self.outgoing(&store, &policy).await {
+                tokio::spawn(self.clone().send(
+                    client.clone(),
+                    store.clone(),
+                    peer,
+                    message,
+                    blocks,
+                ));
+            }
+        }
+    }
+
+    // 在锁外检查对方想要的数据块在本地是否存在，存储没有变化时沿用上次的结果
+    async fn outgoing(
+        &self,
+        store: &BlockStore,
+        policy: &AccessPolicy,
+    ) -> Vec<(PeerId, ExchangeMessage, Vec<Cid>)> {
+        let generation = store.generation();
+        let unchecked: HashSet<Cid> = self
+            .state()
+            .ledgers
+            .values()
+            .flat_map(|ledger| &ledger.wants)
+            .filter(|want| want.needs_check(generation))
+            .map(|want| want.cid)
+            .collect();
+        if !unchecked.is_empty() {
+            let store = store.clone();
+            let sizes = tokio::task::spawn_blocking(move || {
+                unchecked
+                    .into_iter()
+                    .map(|cid| {
+                        let size = fs::metadata(store.block_path(&cid))
+                            .ok()
+                            .map(|metadata| metadata.len())
+                            .filter(|size| *size <= MAX_EXCHANGE_BLOCK_SIZE);
+                        (cid, size)
+                    })
+                    .collect::<HashMap<Cid, Option<u64>>>()
+            })
+            .await
+            .unwrap_or_default();
+            let mut state = self.state();
+            for want in state.ledgers.values_mut().flat_map(|l| &mut l.wants) {
+                if let Some(size) = sizes.get(&want.cid) {
+                    want.size = *size;
+                    want.checked = Some(generation);
+                }
+            }
+        }
+        self.state().messages(policy)
+    }
+
+    // 读取数据块内容并发送消息，对方确认后计入账本
+    async fn send(
+        self,
+        mut client: Client,
+        store: BlockStore,
+        peer: PeerId,
+        mut message: ExchangeMessage,
+        blocks: Vec<Cid>,
+    ) {
+        for cid in blocks {
+            match store.get(&cid) {
+                Ok(data) => {
+                    store.touch(&cid);
+                    message.blocks.push(data);
+                }
+                Err(e) => {
+                    debug!("Failed to read block {}: {}", cid, e);
+                    message.dont_haves.push(cid);
+                }
+            }
+        }
+        let bytes: usize = message.blocks.iter().map(Vec::len).sum();
+        client.throttle_upload(peer, bytes).await;
+        let result = client.send_exchange(peer, message).await;
+
+        let mut state = self.state();
+        let ledger = state.ledger(peer);
+        ledger.sending = false;
+        ledger.sending_blocks = false;
+        match result {
+            Ok(()) => {
+                ledger.sent += bytes as u64;
+                if bytes > 0 {
+                    debug!("Sent {} bytes of blocks to {}", bytes, peer);
+                }
+            }
+            Err(e) => {
+                debug!("Failed to send exchange message to {}: {}", peer, e);
+                state.fail(peer);
+            }
+        }
+        drop(state);
+        self.wake.notify_one();
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().expect("Lock not to be poisoned.")
+    }
+}
+
+// 取出最多`MAX_ENTRIES_PER_MESSAGE`个条目，其余留待下一条消息
+fn drain_front<T>(entries: &mut Vec<T>) -> Vec<T> {
+    let count = entries.len().min(MAX_ENTRIES_PER_MESSAGE);
+    entries.drain(..count).collect()
+}
+
+// 处理节点发来的消息，接收数据块前申请下载带宽。处理完后才确认，
+// 对方在确认前不会发送下一条消息
+pub async fn receive(
+    mut client: Client,
+    store: BlockStore,
+    policy: AccessPolicy,
+    peer: PeerId,
+    message: ExchangeMessage,
+    channel: ResponseChannel<()>,
+) {
+    let bytes = message.blocks.iter().map(Vec::len).sum();
+    client.throttle_download(peer, bytes).await;
+    let exchange = client.exchange().clone();
+    let result =
+        tokio::task::spawn_blocking(move || exchange.receive(&store, &policy, peer, message)).await;
+    if let Err(e) = result {
+        warn!("Exchange task failed: {}", e);
+        return;
+    }
+    let _ = client.respond_exchange(channel).await;
+}
+
+// 一次下载想要的数据块，按到达顺序返回
+pub struct Session {
+    id: u64,
+    exchange: Exchange,
+    // 尚未收到的数据块
+    remaining: HashSet<Cid>,
+    receiver: mpsc::UnboundedReceiver<Cid>,
+}
+
+impl Session {
+    // 等待下一个数据块存入本地存储
+    pub async fn next(&mut self) -> Option<Cid> {
+        while let Some(cid) = self.receiver.recv().await {
+            if self.remaining.remove(&cid) {
+                return Some(cid);
+            }
+        }
+        None
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.remaining.len()
+    }
+
+    // 取出已由其他下载存入本地存储的剩余数据块，并取消对它们的想要
+    pub fn take_stored(&mut self, store: &BlockStore) -> Vec<Cid> {
+        let stored: HashSet<Cid> = self
+            .remaining
+            .iter()
+            .filter(|cid| store.has(cid))
+            .copied()
+            .collect();
+        if !stored.is_empty() {
+            self.remaining.retain(|cid| !stored.contains(cid));
+            self.exchange.close(self.id, &stored);
+        }
+        stored.into_iter().collect()
+    }
+
+    // 所有候选节点都回复没有剩余的数据块
+    pub fn is_stalled(&self) -> bool {
+        self.exchange.is_stalled(&self.remaining)
+    }
+
+    // 被提供节点拒绝访问的剩余数据块及拒绝的节点
+    pub fn forbidden(&self) -> Option<(PeerId, Cid)> {
+        self.exchange.forbidden(&self.remaining)
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        self.exchange.close(self.id, &self.remaining);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{import_bytes, open_policy, temp_store};
+
+    fn want(cid: Cid, size: Option<u64>) -> PeerWant {
+        PeerWant {
+            cid,
+            have_only: false,
+            told: false,
+            size,
+            checked: Some(0),
+        }
+    }
+
+    #[test]
+    fn sends_blocks_to_lowest_debt_ratio_peers() {
+        let cid = Cid::of(b"block");
+        let mut state = State::default();
+        let mut peers = Vec::new();
+        for i in 0..MAX_SENDING_PEERS as u64 + 2 {
+            let peer = PeerId::random();
+            let ledger = state.ledger(peer);
+            // 债务比例随序号递增
+            ledger.sent = i * 1000;
+            ledger.received = 1000;
+            ledger.wants.push(want(cid, Some(5)));
+            peers.push(peer);
+        }
+
+        let messages = state.messages(&open_policy());
+        let served: HashSet<PeerId> = messages
+            .iter()
+            .filter(|(_, _, blocks)| blocks == &[cid])
+            .map(|(peer, _, _)| *peer)
+            .collect();
+        assert_eq!(served, peers[..MAX_SENDING_PEERS].iter().copied().collect());
+        assert_eq!(messages.len(), MAX_SENDING_PEERS);
+        for peer in &peers[MAX_SENDING_PEERS..] {
+            assert_eq!(state.ledgers[peer].wants.len(), 1);
+        }
+
+        // 有节点确认后，空出的名额给剩余节点中债务比例最低的
+        let ledger = state.ledger(peers[0]);
+        ledger.sending = false;
+        ledger.sending_blocks = false;
+        let messages = state.messages(&open_policy());
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].0, peers[MAX_SENDING_PEERS]);
+    }
+
+    #[test]
+    fn tells_missing_blocks_once() {
+        let cid = Cid::of(b"missing");
+        let peer = PeerId::random();
+        let mut state = State::default();
+        state.ledger(peer).wants.push(want(cid, None));
+
+        let messages = state.messages(&open_policy());
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].1.dont_haves, vec![cid]);
+        assert!(messages[0].2.is_empty());
+
+        state.ledger(peer).sending = false;
+        assert!(state.messages(&open_policy()).is_empty());
+        assert_eq!(state.ledgers[&peer].wants.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn rechecks_wants_when_store_changes() {
+        let (_dir, store) = temp_store();
+        let exchange = Exchange::new(true);
+        let peer = PeerId::random();
+        let data = b"later".to_vec();
+        let cid = Cid::of(&data);
+        exchange.state().ledger(peer).wants.push(PeerWant {
+            cid,
+            have_only: false,
+            told: false,
+            size: None,
+            checked: None,
+        });
+
+        let messages = exchange.outgoing(&store, &open_policy()).await;
+        assert_eq!(messages[0].1.dont_haves, vec![cid]);
+        exchange.state().ledger(peer).sending = false;
+        assert!(exchange.outgoing(&store, &open_policy()).await.is_empty());
+
+        store.put(&data).unwrap();
+        let messages = exchange.outgoing(&store, &open_policy()).await;
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].2, vec![cid]);
+    }
+
+    #[test]
+    fn takes_blocks_stored_by_other_downloads() {
+        let (_dir, store) = temp_store();
+        let exchange = Exchange::new(true);
+        let peers = HashSet::from([PeerId::random()]);
+        let data = b"stored".to_vec();
+        let stored = Cid::of(&data);
+        let other = Cid::of(b"other");
+        let mut session = exchange.want(&peers, &[stored, other], None);
+        assert!(session.take_stored(&store).is_empty());
+
+        store.put(&data).unwrap();
+        assert_eq!(session.take_stored(&store), vec![stored]);
+        assert_eq!(session.remaining(), 1);
+        assert!(!exchange.state().wants.contains_key(&stored));
+        assert!(exchange.state().wants.contains_key(&other));
+    }
+
+    #[test]
+    fn carries_overflowing_entries_to_next_message() {
+        let peer = PeerId::random();
+        let mut state = State::default();
+        for i in 0..MAX_ENTRIES_PER_MESSAGE + 1 {
+            let cid = Cid::of(&i.to_be_bytes());
+            state.ledger(peer).wants.push(want(cid, None));
+        }
+
+        let messages = state.messages(&open_policy());
+        assert_eq!(messages[0].1.dont_haves.len(), MAX_ENTRIES_PER_MESSAGE);
+        state.ledger(peer).sending = false;
+        let messages = state.messages(&open_policy());
+        assert_eq!(messages[0].1.dont_haves.len(), 1);
+    }
+
+    #[test]
+    fn reassigns_blocks_of_failed_peer() {
+        let exchange = Exchange::new(true);
+        let peers = HashSet::from([PeerId::random(), PeerId::random()]);
+        let cid = Cid::of(b"block");
+        let _session = exchange.want(&peers, &[cid], None);
+
+        let mut state = exchange.state();
+        let failed = state.wants[&cid].requested.unwrap();
+        state.fail(failed);
+        let requested = state.wants[&cid].requested;
+        assert!(requested.is_some_and(|peer| peer != failed));
+    }
+
+    #[test]
+    fn answers_forbidden_wants() {
+        let (dir, store) = temp_store();
+        let (root, manifest) = import_bytes(&store, dir.path(), "private", b"private");
+        let policy = open_policy();
+        let allowed = PeerId::random();
+        let cids = manifest.blocks.iter().copied().chain([root]).collect();
+        policy.set("private", root, cids, Some(HashSet::from([allowed])));
+
+        let exchange = Exchange::new(true);
+        let stranger = PeerId::random();
+        let message = ExchangeMessage {
+            wants: vec![Want {
+                cid: root,
+                have_only: false,
+            }],
+            ..Default::default()
+        };
+        exchange.receive(&store, &policy, stranger, message);
+        let messages = exchange.state().messages(&policy);
+        assert_eq!(messages[0].1.forbidden, vec![root]);
+        assert!(messages[0].1.dont_haves.is_empty());
+
+        // 请求方记录拒绝访问的节点，会话以拒绝访问失败
+        let requester = Exchange::new(true);
+        let session = requester.want(&HashSet::from([allowed]), &[root], None);
+        let reply = ExchangeMessage {
+            forbidden: vec![root],
+            ..Default::default()
+        };
+        requester.receive(&store, &policy, allowed, reply);
+        assert!(session.is_stalled());
+        assert_eq!(session.forbidden(), Some((allowed, root)));
+    }
+}
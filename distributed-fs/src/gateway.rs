@@ -0,0 +1,483 @@
+use std::{io, net::SocketAddr, path::PathBuf};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::watch,
+};
+
+use crate::{
+    client::Client,
+    download::{Downloader, Progress},
+    error::{Error, Result},
+    locate,
+    name::Name,
+    store::{BlockStore, Cid, Manifest, Node, CHUNK_SIZE},
+    Target,
+};
+
+// 网关请求路径的前缀
+const PATH_PREFIX: &str = "/dfs/";
+// 请求头的最大字节数
+const MAX_HEADER_SIZE: usize = 8 * 1024;
+// 请求头的最大数量
+const MAX_HEADERS: usize = 32;
+// 每批获取的数据块数，同一批数据块从多个提供节点并行获取
+const FETCH_BATCH: usize = 16;
+// 识别内容类型时检查的字节数
+const SNIFF_LEN: usize = 512;
+
+// 内容开头的特征字节和对应的内容类型
+const SIGNATURES: &[(&[u8], &str)] = &[
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"\xff\xd8\xff", "image/jpeg"),
+    (b"GIF87a", "image/gif"),
+    (b"GIF89a", "image/gif"),
+    (b"%PDF-", "application/pdf"),
+    (b"PK\x03\x04", "application/zip"),
+    (b"\x1f\x8b", "application/gzip"),
+    (b"\x28\xb5\x2f\xfd", "application/zstd"),
+    (b"BZh", "application/x-bzip2"),
+    (b"\xfd7zXZ\x00", "application/x-xz"),
+    (b"OggS", "application/ogg"),
+    (b"ID3", "audio/mpeg"),
+    (b"fLaC", "audio/flac"),
+    (b"\x1a\x45\xdf\xa3", "video/webm"),
+    (b"\x00asm", "application/wasm"),
+];
+
+// 只在回环地址上监听，网关不做访问控制
+pub async fn bind(addr: SocketAddr) -> Result<TcpListener> {
+    if !addr.ip().is_loopback() {
+        return Err(Error::InvalidArgument(format!(
+            "The gateway only listens on loopback addresses, got {}",
+            addr
+        )));
+    }
+    Ok(TcpListener::bind(addr).await?)
+}
+
+// 本地HTTP网关，处理`GET /dfs/<name-or-hash>`：解析名称或内容标识，
+// 从提供节点获取数据块并按顺序写入响应，支持单个字节范围请求
+pub async fn serve(listener: TcpListener, client: Client, store: BlockStore) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                tokio::spawn(handle(stream, client.clone(), store.clone()));
+            }
+            Err(e) => warn!("Failed to accept gateway connection: {}", e),
+        }
+    }
+}
+
+// 网关请求
+struct Request {
+    method: String,
+    path: String,
+    range: Option<String>,
+}
+
+// 每个连接只处理一个请求，响应后关闭连接
+async fn handle(mut stream: TcpStream, client: Client, store: BlockStore) {
+    let request = match read_request(&mut stream).await {
+        Ok(request) => request,
+        Err(e) => {
+            debug!("Invalid gateway request: {}", e);
+            let _ = write_error(&mut stream, 400, &e.to_string()).await;
+            return;
+        }
+    };
+    debug!("Gateway request {} {}", request.method, request.path);
+    if let Err(e) = respond(&mut stream, client, &store, request).await {
+        debug!("Failed to send gateway response: {}", e);
+    }
+}
+
+async fn read_request(stream: &mut TcpStream) -> io::Result<Request> {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 1024];
+    loop {
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        buf.extend_from_slice(&chunk[..n]);
+
+        let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+        let mut request = httparse::Request::new(&mut headers);
+        match request.parse(&buf) {
+            Ok(httparse::Status::Complete(_)) => {
+                let range = request
+                    .headers
+                    .iter()
+                    .find(|h| h.name.eq_ignore_ascii_case("range"))
+                    .and_then(|h| std::str::from_utf8(h.value).ok())
+                    .map(str::to_string);
+                return Ok(Request {
+                    method: request.method.unwrap_or_default().to_string(),
+                    path: request.path.unwrap_or_default().to_string(),
+                    range,
+                });
+            }
+            Ok(httparse::Status::Partial) if buf.len() < MAX_HEADER_SIZE => {}
+            Ok(httparse::Status::Partial) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Request header is too large",
+                ))
+            }
+            Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+        }
+    }
+}
+
+async fn respond(
+    stream: &mut TcpStream,
+    client: Client,
+    store: &BlockStore,
+    request: Request,
+) -> Result<()> {
+    if request.method != "GET" && request.method != "HEAD" {
+        return write_error(stream, 405, "Only GET and HEAD are supported").await;
+    }
+    let target = match parse_target(&request.path) {
+        Ok(target) => target,
+        Err(e) => return write_error(stream, 404, &e.to_string()).await,
+    };
+
+    let (progress, _) = watch::channel(Progress::default());
+    let (cid, node, mut downloader) = match locate(client, store, target, progress).await {
+        Ok(located) => located,
+        Err(e) => return write_error(stream, status_of(&e), &e.to_string()).await,
+    };
+    let manifest = match node {
+        Node::File(manifest) => manifest,
+        // 目录返回目录项列表，子目录以`/`结尾
+        Node::Directory(directory) => {
+            let listing: String = directory
+                .entries
+                .iter()
+                .map(|e| format!("{}{}\n", e.name, if e.is_dir { "/" } else { "" }))
+                .collect();
+            let headers = [
+                ("Content-Type", "text/plain; charset=utf-8".to_string()),
+                ("Content-Length", listing.len().to_string()),
+                ("ETag", format!("\"{}\"", cid)),
+            ];
+            write_head(stream, 200, &headers).await?;
+            if request.method == "GET" {
+                stream.write_all(listing.as_bytes()).await?;
+            }
+            return Ok(());
+        }
+    };
+
+    // 按第一个数据块的内容识别内容类型
+    let content_type = match manifest.blocks.first() {
+        Some(first) => {
+            if let Err(e) = downloader.fetch(&[*first]).await {
+                return write_error(stream, status_of(&e), &e.to_string()).await;
+            }
+            sniff(&store.get(first)?)
+        }
+        None => "application/octet-stream",
+    };
+
+    let size = manifest.size;
+    let mut headers = vec![
+        ("Content-Type", content_type.to_string()),
+        ("Accept-Ranges", "bytes".to_string()),
+        ("ETag", format!("\"{}\"", cid)),
+    ];
+    let (status, start, end) = match request.range.as_deref().and_then(|r| parse_range(r, size)) {
+        None => (200, 0, size),
+        Some(Some((start, end))) => {
+            headers.push((
+                "Content-Range",
+                format!("bytes {}-{}/{}", start, end - 1, size),
+            ));
+            (206, start, end)
+        }
+        Some(None) => {
+            headers.push(("Content-Range", format!("bytes */{}", size)));
+            headers.push(("Content-Length", "0".to_string()));
+            return write_head(stream, 416, &headers).await;
+        }
+    };
+    headers.push(("Content-Length", (end - start).to_string()));
+    write_head(stream, status, &headers).await?;
+    if request.method == "GET" && start < end {
+        write_body(stream, &mut downloader, store, &manifest, start, end).await?;
+    }
+    Ok(())
+}
+
+// 按顺序分批获取数据块，写入`[start, end)`范围内的内容。
+// 响应头已经发出，出错时直接关闭连接，客户端通过内容长度发现响应不完整
+async fn write_body(
+    stream: &mut TcpStream,
+    downloader: &mut Downloader,
+    store: &BlockStore,
+    manifest: &Manifest,
+    start: u64,
+    end: u64,
+) -> Result<()> {
+    let chunk_size = CHUNK_SIZE as u64;
+    let first = (start / chunk_size) as usize;
+    let last = ((end - 1) / chunk_size) as usize;
+    for batch in (first..=last).step_by(FETCH_BATCH) {
+        let blocks = &manifest.blocks[batch..(batch + FETCH_BATCH).min(last + 1)];
+        downloader.fetch(blocks).await?;
+        for (i, cid) in blocks.iter().enumerate() {
+            let data = store.get(cid)?;
+            let offset = (batch + i) as u64 * chunk_size;
+            let from = start.saturating_sub(offset) as usize;
+            let to = ((end - offset) as usize).min(data.len());
+            stream.write_all(&data[from..to]).await?;
+        }
+    }
+    stream.flush().await?;
+    Ok(())
+}
+
+async fn write_head(stream: &mut TcpStream, status: u16, headers: &[(&str, String)]) -> Result<()> {
+    let mut head = format!("HTTP/1.1 {} {}\r\n", status, reason(status));
+    for (name, value) in headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("Connection: close\r\n\r\n");
+    stream.write_all(head.as_bytes()).await?;
+    Ok(())
+}
+
+async fn write_error(stream: &mut TcpStream, status: u16, message: &str) -> Result<()> {
+    let body = format!("{}\n", message);
+    let mut headers = vec![
+        ("Content-Type", "text/plain; charset=utf-8".to_string()),
+        ("Content-Length", body.len().to_string()),
+    ];
+    if status == 405 {
+        headers.push(("Allow", "GET, HEAD".to_string()));
+    }
+    write_head(stream, status, &headers).await?;
+    stream.write_all(body.as_bytes()).await?;
+    Ok(())
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        206 => "Partial Content",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        416 => "Range Not Satisfiable",
+        _ => "Bad Gateway",
+    }
+}
+
+// 获取失败时返回的状态码
+fn status_of(e: &Error) -> u16 {
+    match e {
+        Error::NotFound(_) | Error::NoProviders(_) => 404,
+        Error::InvalidArgument(_) => 400,
+        Error::Forbidden { .. } => 403,
+        _ => 502,
+    }
+}
+
+// 解析请求路径，形如`/dfs/<hash>[/<sub-path>]`或`/dfs/<peer-id>/<path>`，
+// 可以通过`?token=<token>`出示能力令牌
+fn parse_target(path: &str) -> Result<Target> {
+    let (path, query) = path.split_once('?').unwrap_or((path, ""));
+    let path = path
+        .strip_prefix(PATH_PREFIX)
+        .ok_or_else(|| Error::NotFound(format!("Path {}", path)))?;
+    let path = percent_decode(path)?;
+    let path = path.trim_matches('/');
+
+    let token = query
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("token="))
+        .map(|token| token.parse())
+        .transpose()
+        .map_err(Error::InvalidArgument)?;
+
+    let (first, rest) = path.split_once('/').unwrap_or((path, ""));
+    if let Ok(cid) = first.parse::<Cid>() {
+        return Ok(Target {
+            name: None,
+            cid: Some(cid),
+            sub_path: (!rest.is_empty()).then(|| PathBuf::from(rest)),
+            token,
+        });
+    }
+    let name = path.parse::<Name>().map_err(Error::InvalidArgument)?;
+    Ok(Target {
+        name: Some(name),
+        cid: None,
+        sub_path: None,
+        token,
+    })
+}
+
+// 解码路径中的百分号编码
+fn percent_decode(s: &str) -> Result<String> {
+    let invalid = || Error::InvalidArgument(format!("Invalid percent encoding in {}", s));
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = s.get(i + 1..i + 3).ok_or_else(invalid)?;
+            decoded.push(u8::from_str_radix(hex, 16).map_err(|_| invalid())?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).map_err(|_| invalid())
+}
+
+// 解析单个字节范围，返回`[start, end)`。无法识别或包含多个范围时返回None，按完整内容响应；
+// 范围无法满足时返回Some(None)
+fn parse_range(range: &str, size: u64) -> Option<Option<(u64, u64)>> {
+    let spec = range.trim().strip_prefix("bytes=")?;
+    if spec.contains(',') || size == 0 {
+        return None;
+    }
+    let (start, end) = spec.split_once('-')?;
+    let (start, end) = match (start.trim(), end.trim()) {
+        // 最后n个字节
+        ("", suffix) => {
+            let suffix: u64 = suffix.parse().ok()?;
+            if suffix == 0 {
+                return Some(None);
+            }
+            (size.saturating_sub(suffix), size)
+        }
+        (start, "") => (start.parse().ok()?, size),
+        (start, end) => {
+            let (start, end): (u64, u64) = (start.parse().ok()?, end.parse().ok()?);
+            if end < start {
+                return None;
+            }
+            (start, end.saturating_add(1).min(size))
+        }
+    };
+    if start >= size {
+        return Some(None);
+    }
+    Some(Some((start, end)))
+}
+
+// 按内容开头识别内容类型，无法识别的UTF-8文本视为纯文本
+fn sniff(data: &[u8]) -> &'static str {
+    let data = &data[..data.len().min(SNIFF_LEN)];
+    if let Some((_, content_type)) = SIGNATURES.iter().find(|(magic, _)| data.starts_with(magic)) {
+        return content_type;
+    }
+    if data.len() >= 12 && data.starts_with(b"RIFF") {
+        match &data[8..12] {
+            b"WEBP" => return "image/webp",
+            b"WAVE" => return "audio/wav",
+            b"AVI " => return "video/x-msvideo",
+            _ => {}
+        }
+    }
+    if data.len() >= 8 && &data[4..8] == b"ftyp" {
+        return "video/mp4";
+    }
+
+    // 截断处可能是不完整的多字节字符
+    let text = match std::str::from_utf8(data) {
+        Ok(text) => text,
+        Err(e) if e.error_len().is_none() => {
+            std::str::from_utf8(&data[..e.valid_up_to()]).unwrap_or_default()
+        }
+        Err(_) => return "application/octet-stream",
+    };
+    if text
+        .chars()
+        .any(|c| c.is_control() && !matches!(c, '\t' | '\n' | '\r' | '\x0c' | '\x1b'))
+    {
+        return "application/octet-stream";
+    }
+    let head = text.trim_start().to_ascii_lowercase();
+    if head.starts_with("<!doctype html") || head.starts_with("<html") {
+        "text/html; charset=utf-8"
+    } else if head.starts_with("<svg") {
+        "image/svg+xml"
+    } else if head.starts_with("<?xml") {
+        "application/xml"
+    } else {
+        "text/plain; charset=utf-8"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_range_boundaries() {
+        assert_eq!(parse_range("bytes=0-0", 10), Some(Some((0, 1))));
+        assert_eq!(parse_range("bytes=0-9", 10), Some(Some((0, 10))));
+        assert_eq!(parse_range("bytes=5-100", 10), Some(Some((5, 10))));
+        assert_eq!(parse_range("bytes=9-", 10), Some(Some((9, 10))));
+        assert_eq!(parse_range("bytes=-3", 10), Some(Some((7, 10))));
+        assert_eq!(parse_range("bytes=-100", 10), Some(Some((0, 10))));
+        assert_eq!(parse_range(" bytes= 2 - 4 ", 10), Some(Some((2, 5))));
+        assert_eq!(
+            parse_range("bytes=0-18446744073709551615", 10),
+            Some(Some((0, 10)))
+        );
+
+        // 无法满足
+        assert_eq!(parse_range("bytes=10-", 10), Some(None));
+        assert_eq!(parse_range("bytes=10-20", 10), Some(None));
+        assert_eq!(parse_range("bytes=-0", 10), Some(None));
+
+        // 忽略，按完整内容响应
+        assert_eq!(parse_range("bytes=5-4", 10), None);
+        assert_eq!(parse_range("bytes=0-1,3-4", 10), None);
+        assert_eq!(parse_range("bytes=0-1", 0), None);
+        assert_eq!(parse_range("items=0-1", 10), None);
+        assert_eq!(parse_range("bytes=a-1", 10), None);
+        assert_eq!(parse_range("bytes=1", 10), None);
+    }
+
+    #[test]
+    fn decodes_percent_encoding() {
+        assert_eq!(percent_decode("a%20b").unwrap(), "a b");
+        assert_eq!(percent_decode("%E4%BD%A0%e5%a5%bd").unwrap(), "你好");
+        assert_eq!(percent_decode("plain+name").unwrap(), "plain+name");
+        assert!(percent_decode("bad%2").is_err());
+        assert!(percent_decode("bad%zz").is_err());
+        assert!(percent_decode("%ff").is_err());
+        assert!(percent_decode("%E4%B").is_err());
+    }
+
+    #[test]
+    fn sniffs_content_types() {
+        assert_eq!(sniff(b"\x89PNG\r\n\x1a\n...."), "image/png");
+        assert_eq!(sniff(b"%PDF-1.7"), "application/pdf");
+        assert_eq!(sniff(b"RIFF\0\0\0\0WEBPVP8 "), "image/webp");
+        assert_eq!(sniff(b"RIFF\0\0\0\0WAVEfmt "), "audio/wav");
+        assert_eq!(sniff(b"\0\0\0\x18ftypmp42"), "video/mp4");
+        assert_eq!(sniff(b"  <!DOCTYPE html><p>"), "text/html; charset=utf-8");
+        assert_eq!(sniff(b"<svg xmlns=\"\">"), "image/svg+xml");
+        assert_eq!(sniff(b"<?xml version=\"1.0\"?>"), "application/xml");
+        assert_eq!(sniff(b"hello\tworld\n"), "text/plain; charset=utf-8");
+        assert_eq!(sniff(b""), "text/plain; charset=utf-8");
+        assert_eq!(sniff(b"hello\0world"), "application/octet-stream");
+        assert_eq!(sniff(b"\xff\xfe\xfd"), "application/octet-stream");
+
+        // 截断在多字节字符中间的文本仍视为文本
+        let text = "字".repeat(SNIFF_LEN);
+        assert!(!text.is_char_boundary(SNIFF_LEN));
+        assert_eq!(sniff(text.as_bytes()), "text/plain; charset=utf-8");
+    }
+}
@@ -1,35 +1,82 @@
+mod access;
+mod bandwidth;
 mod behaviour;
+mod catalog;
 mod client;
+mod control;
+mod crypto;
+mod daemon;
+mod download;
+mod error;
 mod event;
+mod exchange;
+mod gateway;
+mod limit;
+mod name;
+mod partial;
 mod protocol;
+mod replication;
+mod search;
+mod store;
+mod sync;
+#[cfg(test)]
+mod testing;
+mod watcher;
 
+use access::{AccessPolicy, Capability};
+use bandwidth::{Bandwidth, RateLimits};
 use behaviour::ComposedBehaviour;
+use catalog::{Catalog, CatalogEntry};
 use clap::Parser;
 use client::Client;
+use control::{ControlRequest, ControlResponse, ProvidedFile};
+use daemon::{Daemon, StorageLimits};
+use download::{Downloader, Progress, PROGRESS_INTERVAL};
+pub use error::{Error, Result};
 use event::{Event, EventLoop};
-use futures::FutureExt;
+use exchange::Exchange;
 use libp2p::Multiaddr;
 use libp2p::{
     identity::{self, ed25519},
-    kad::{store::MemoryStore, Kademlia},
+    kad::{Kademlia, KademliaConfig, KademliaStoreInserts},
     multiaddr::Protocol,
-    request_response::{ProtocolSupport, RequestResponse},
+    request_response::{ProtocolSupport, RequestResponse, RequestResponseConfig, ResponseChannel},
     swarm::SwarmBuilder,
     PeerId,
 };
+use limit::Limits;
+use name::Name;
 use protocol::*;
+use record_store::DiskStore;
+use replication::{maintain_replicas, Replicator};
+use search::{IndexEntry, SearchResult};
 use std::env;
-use std::{error::Error, iter, path::PathBuf};
-use tokio::sync::mpsc::{self, Receiver};
-use tracing_subscriber;
+use std::{
+    collections::HashSet,
+    fs, iter,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use store::{BlockStore, Cid, FileRecord, Node};
+use sync::SyncStats;
+use tokio::sync::{
+    mpsc::{self, Receiver},
+    watch,
+};
 
 #[macro_use]
 extern crate tracing;
 
+// 数据目录下保存节点私钥的文件
+const IDENTITY_FILE: &str = "identity";
+// 网络事件通道的容量，服务任务处理不过来时事件循环回复繁忙或丢弃新事件
+const EVENT_BUFFER: usize = 256;
+
 #[derive(Debug, Parser)]
 #[clap(name = "dfs")]
 pub struct Opt {
-    // 生成密钥对的种子
+    // 生成密钥对的种子，只用于测试和演示；不指定时使用数据目录下保存的密钥对
     #[clap(long)]
     pub secret_key_seed: Option<u8>,
 
@@ -41,6 +88,82 @@ pub struct Opt {
     #[clap(long)]
     pub listen_address: Option<Multiaddr>,
 
+    // 数据目录，存放内容寻址的数据块和DHT记录
+    #[clap(long, default_value = "dfs-data")]
+    pub data_dir: PathBuf,
+
+    // 守护进程控制接口的套接字路径，默认为数据目录下的dfs.sock
+    #[clap(long)]
+    pub socket: Option<PathBuf>,
+
+    // 重新公布提供者记录的间隔（秒），其他节点上的提供者记录在两倍间隔后过期
+    #[clap(long, default_value = "43200")]
+    pub republish_interval: u64,
+
+    // 同时向其他节点传输数据块的最大数量
+    #[clap(long, default_value = "16")]
+    pub max_transfers: usize,
+
+    // 等待传输的最大请求数，超出时回复繁忙
+    #[clap(long, default_value = "256")]
+    pub max_queued_requests: usize,
+
+    // 每个节点每秒的最大请求数，超出时回复繁忙
+    #[clap(long, default_value = "32")]
+    pub peer_request_rate: f64,
+
+    // 每个节点允许的突发请求数
+    #[clap(long, default_value = "64")]
+    pub peer_request_burst: f64,
+
+    // 检查提供文件副本数量的间隔（秒）
+    #[clap(long, default_value = "600")]
+    pub replication_interval: u64,
+
+    // 守护进程接受其他节点的副本请求，默认拒绝
+    #[clap(long)]
+    pub accept_replicas: bool,
+
+    // 为其他节点持有的副本总大小上限（字节）
+    #[clap(long, default_value = "1073741824")]
+    pub max_replica_bytes: u64,
+
+    // 数据块存储配额（字节），守护进程在超出时回收未固定且未提供的缓存内容，默认不限制
+    #[clap(long)]
+    pub storage_quota: Option<u64>,
+
+    // 全局上传速率上限（字节每秒），默认不限制
+    #[clap(long)]
+    pub upload_limit: Option<u64>,
+
+    // 全局下载速率上限（字节每秒），默认不限制
+    #[clap(long)]
+    pub download_limit: Option<u64>,
+
+    // 向每个节点上传的速率上限（字节每秒），默认不限制
+    #[clap(long)]
+    pub peer_upload_limit: Option<u64>,
+
+    // 从每个节点下载的速率上限（字节每秒），默认不限制
+    #[clap(long)]
+    pub peer_download_limit: Option<u64>,
+
+    // 守护进程的HTTP网关监听地址，只允许回环地址，如127.0.0.1:8080，默认不启动
+    #[clap(long)]
+    pub gateway: Option<SocketAddr>,
+
+    // 守护进程监视的目录，自动提供其中新增和修改的文件，停止提供删除的文件，默认不监视
+    #[clap(long)]
+    pub watch: Option<PathBuf>,
+
+    // 监视目录中文件名称路径的前缀，默认为目录名
+    #[clap(long, requires = "watch")]
+    pub watch_name: Option<String>,
+
+    // 下载时通过数据块交换协议向提供节点交换想要列表，由对方推送数据块，而不是逐个请求
+    #[clap(long)]
+    pub exchange: bool,
+
     // 子命令
     #[clap(subcommand)]
     pub argument: CliArgument,
@@ -48,149 +171,1249 @@ pub struct Opt {
 
 #[derive(Debug, Parser)]
 pub enum CliArgument {
-    // 提供文件子命令
+    // 提供文件或目录子命令
     Provide {
         #[clap(long)]
-        path: PathBuf, // 文件全路径
+        path: PathBuf, // 文件或目录全路径
         #[clap(long)]
-        name: String, // 文件名称
+        name: String, // 本节点命名空间下的名称路径，发布为`<peer-id>/<name>`
+        #[clap(long, multiple_occurrences = true)]
+        allow: Vec<PeerId>, // 只允许这些节点和持有能力令牌的节点访问
+        #[clap(long)]
+        private: bool, // 只允许持有能力令牌的节点访问
+        #[clap(long, default_value = "0", conflicts_with_all = &["allow", "private"])]
+        replicas: usize, // 请求其他节点保存并提供的副本数
+        #[clap(long, multiple_occurrences = true)]
+        recipient: Vec<PeerId>, // 将文件加密给这些节点，只有接收者和本节点可以解密
+        #[clap(long, multiple_occurrences = true)]
+        tag: Vec<String>, // 除名称路径外用于搜索的标签
     },
     // 获取文件内容子命令
     Get {
+        #[clap(long, required_unless_present = "cid")]
+        name: Option<Name>, // 名称，形如`<peer-id>/<path>`
+        #[clap(long, conflicts_with = "name")]
+        cid: Option<Cid>, // 文件根清单的内容标识
+        #[clap(long)]
+        sub_path: Option<PathBuf>, // 只获取目录中的子路径
+        #[clap(long)]
+        output: Option<PathBuf>, // 保存路径，默认为当前目录下的文件名称
+        #[clap(long)]
+        token: Option<Capability>, // 提供节点签发的能力令牌
+    },
+    // 将本地文件或目录同步为远端的版本，只获取本地旧文件中没有的数据块
+    Sync {
+        #[clap(long, required_unless_present = "cid")]
+        name: Option<Name>, // 名称，形如`<peer-id>/<path>`
+        #[clap(long, conflicts_with = "name")]
+        cid: Option<Cid>, // 根清单的内容标识
+        #[clap(long)]
+        sub_path: Option<PathBuf>, // 只同步目录中的子路径
+        #[clap(long)]
+        path: PathBuf, // 本地文件或目录路径
+        #[clap(long)]
+        delete: bool, // 删除本地目录中远端没有的文件和子目录
+        #[clap(long)]
+        token: Option<Capability>, // 提供节点签发的能力令牌
+    },
+    // 用本节点私钥为其他节点签发能力令牌，不启动网络
+    Grant {
+        #[clap(long)]
+        cid: Cid, // 授权访问的根清单
+        #[clap(long)]
+        peer: PeerId, // 被授权的节点
+        #[clap(long, default_value = "86400")]
+        ttl: u64, // 有效期（秒）
+    },
+    // 搜索名称路径或标签包含所有关键词的文件
+    Search {
+        query: String, // 空格分隔的关键词
+    },
+    // 列出节点提供的文件和目录
+    List {
+        peer: PeerId, // 提供节点
+    },
+    // 以守护进程运行，通过本地控制接口管理多个共享文件
+    Daemon,
+    // 向守护进程发送控制命令
+    Ctl {
+        #[clap(subcommand)]
+        command: ControlCommand,
+    },
+}
+
+#[derive(Debug, Parser)]
+pub enum ControlCommand {
+    // 提供文件或目录
+    Provide {
+        #[clap(long)]
+        path: PathBuf, // 文件或目录路径
+        #[clap(long)]
+        name: String, // 本节点命名空间下的名称路径
+        #[clap(long, multiple_occurrences = true)]
+        allow: Vec<PeerId>, // 只允许这些节点和持有能力令牌的节点访问
+        #[clap(long)]
+        private: bool, // 只允许持有能力令牌的节点访问
+        #[clap(long, default_value = "0", conflicts_with_all = &["allow", "private"])]
+        replicas: usize, // 在其他节点上维持的副本数
+        #[clap(long, multiple_occurrences = true)]
+        recipient: Vec<PeerId>, // 将文件加密给这些节点，只有接收者和本节点可以解密
+        #[clap(long, multiple_occurrences = true)]
+        tag: Vec<String>, // 除名称路径外用于搜索的标签
+    },
+    // 停止提供文件或目录
+    Unprovide {
+        #[clap(long)]
+        name: String, // 本节点命名空间下的名称路径
+    },
+    // 获取文件或目录
+    Get {
+        #[clap(long, required_unless_present = "cid")]
+        name: Option<Name>, // 名称，形如`<peer-id>/<path>`
+        #[clap(long, conflicts_with = "name")]
+        cid: Option<Cid>, // 文件根清单的内容标识
+        #[clap(long)]
+        sub_path: Option<PathBuf>, // 只获取目录中的子路径
         #[clap(long)]
-        name: String, // 文件名称
+        output: Option<PathBuf>, // 保存路径，默认为当前目录下的文件名称
+        #[clap(long)]
+        token: Option<String>, // 提供节点签发的能力令牌，由守护进程解析
+    },
+    // 将本地文件或目录同步为远端的版本
+    Sync {
+        #[clap(long, required_unless_present = "cid")]
+        name: Option<Name>, // 名称，形如`<peer-id>/<path>`
+        #[clap(long, conflicts_with = "name")]
+        cid: Option<Cid>, // 根清单的内容标识
+        #[clap(long)]
+        sub_path: Option<PathBuf>, // 只同步目录中的子路径
+        #[clap(long)]
+        path: PathBuf, // 本地文件或目录路径
+        #[clap(long)]
+        delete: bool, // 删除本地目录中远端没有的文件和子目录
+        #[clap(long)]
+        token: Option<String>, // 提供节点签发的能力令牌，由守护进程解析
+    },
+    // 为节点签发访问提供文件的能力令牌
+    Grant {
+        #[clap(long)]
+        name: String, // 本节点命名空间下的名称路径
+        #[clap(long)]
+        peer: PeerId, // 被授权的节点
+        #[clap(long, default_value = "86400")]
+        ttl: u64, // 有效期（秒）
+    },
+    // 设置提供文件在其他节点上维持的副本数，0表示不再维持
+    Replicate {
+        #[clap(long)]
+        name: String, // 本节点命名空间下的名称路径
+        #[clap(long)]
+        replicas: usize, // 副本数
+    },
+    // 列出提供文件的副本状态和本节点为其他节点持有的副本
+    Replicas,
+    // 删除本节点为其他节点持有的副本，此后一段时间内拒绝同一副本的请求
+    DropReplica {
+        #[clap(long)]
+        cid: Cid, // 副本的根清单
+    },
+    // 固定文件或目录，本地缺失的内容先从网络获取，固定的内容不被垃圾回收
+    Pin {
+        #[clap(long, required_unless_present = "cid")]
+        name: Option<Name>, // 名称，形如`<peer-id>/<path>`
+        #[clap(long, conflicts_with = "name")]
+        cid: Option<Cid>, // 根清单的内容标识
     },
+    // 取消固定文件或目录
+    Unpin {
+        #[clap(long, required_unless_present = "cid")]
+        name: Option<Name>, // 名称，形如`<peer-id>/<path>`
+        #[clap(long, conflicts_with = "name")]
+        cid: Option<Cid>, // 根清单的内容标识
+    },
+    // 列出固定的文件和目录
+    Pins,
+    // 回收未固定且未提供的缓存内容
+    Gc,
+    // 查询或调整带宽限制（字节每秒），0表示不限制
+    Bandwidth {
+        #[clap(long)]
+        upload: Option<u64>, // 全局上传速率
+        #[clap(long)]
+        download: Option<u64>, // 全局下载速率
+        #[clap(long)]
+        peer_upload: Option<u64>, // 向每个节点上传的速率
+        #[clap(long)]
+        peer_download: Option<u64>, // 从每个节点下载的速率
+    },
+    // 搜索名称路径或标签包含所有关键词的文件
+    Search {
+        query: String, // 空格分隔的关键词
+    },
+    // 列出提供的文件和目录，指定节点时列出该节点提供的文件和目录
+    List {
+        peer: Option<PeerId>, // 提供节点
+    },
+    // 列出在DHT上宣称提供的文件及其公布状态
+    Advertised,
+    // 查询节点状态
+    Status,
+    // 列出与各节点交换数据块的账本：发送和接收的字节数、对方想要的数据块数
+    Ledgers,
 }
 
-pub async fn run() -> Result<(), Box<dyn Error>> {
+pub async fn run() -> Result<()> {
     env::set_var("RUST_LOG", "DEBUG");
     tracing_subscriber::fmt::init();
     let opt = Opt::parse();
 
-    let (network_client, network_events, network_event_loop) =
-        network(opt.secret_key_seed).await?;
+    // 控制命令只与守护进程通信，不启动网络
+    if let CliArgument::Ctl { command } = opt.argument {
+        let socket = opt
+            .socket
+            .unwrap_or_else(|| opt.data_dir.join(control::SOCKET_FILE));
+        return control_command(&socket, command).await;
+    }
+
+    // 签发令牌只需要本节点私钥，与提供节点使用同一数据目录
+    if let CliArgument::Grant { cid, peer, ttl } = opt.argument {
+        let token = Capability::issue(
+            &keypair(opt.secret_key_seed, &opt.data_dir)?,
+            cid,
+            peer,
+            Duration::from_secs(ttl),
+        )?;
+        println!("{}", token);
+        return Ok(());
+    }
+
+    if opt.max_transfers == 0 || opt.peer_request_rate <= 0.0 || opt.peer_request_burst < 1.0 {
+        return Err(Error::InvalidArgument(
+            "Expect at least one transfer and one request per peer.".to_string(),
+        ));
+    }
+    let store = BlockStore::open(&opt.data_dir)?;
+
+    let (network_client, network_events, network_event_loop) = network(
+        opt.secret_key_seed,
+        &opt.data_dir,
+        store.tmp_dir().to_path_buf(),
+        Duration::from_secs(opt.republish_interval),
+        Limits {
+            max_transfers: opt.max_transfers,
+            max_queued: opt.max_queued_requests,
+            peer_rate: opt.peer_request_rate,
+            peer_burst: opt.peer_request_burst,
+        },
+        RateLimits::default().merge(RateLimits {
+            upload: opt.upload_limit,
+            download: opt.download_limit,
+            peer_upload: opt.peer_upload_limit,
+            peer_download: opt.peer_download_limit,
+        }),
+        opt.exchange,
+    )
+    .await?;
 
     tokio::spawn(async move {
         network_event_loop.run().await;
     });
 
-    process_args(opt, network_client, network_events).await?;
+    process_args(opt, store, network_client, network_events).await?;
 
     Ok(())
 }
 
 async fn process_args(
     opt: Opt,
+    store: BlockStore,
     mut network_client: Client,
-    mut network_events: Receiver<Event>,
-) -> Result<(), Box<dyn Error>> {
-    match opt.listen_address {
-        Some(addr) => network_client
-            .start_listening(addr)
-            .await
-            .expect("Listening not to fail."),
-        None => network_client
-            .start_listening("/ip4/0.0.0.0/tcp/0".parse()?)
-            .await
-            .expect("Listening not to fail."),
-    };
+    network_events: Receiver<Event>,
+) -> Result<()> {
+    let listen_address = opt
+        .listen_address
+        .unwrap_or_else(|| "/ip4/0.0.0.0/tcp/0".parse().expect("Valid multiaddr."));
+    network_client.start_listening(listen_address).await?;
+    let policy = AccessPolicy::new(network_client.public_key());
 
     if let Some(addr) = opt.peer {
         let peer_id = match addr.iter().last() {
-            Some(Protocol::P2p(hash)) => PeerId::from_multihash(hash).expect("Valid hash."),
-            _ => return Err("Expect peer multiaddr to contain peer ID.".into()),
-        };
-        network_client
-            .dial(peer_id, addr)
-            .await
-            .expect("Dial to succeed");
+            Some(Protocol::P2p(hash)) => PeerId::from_multihash(hash).ok(),
+            _ => None,
+        }
+        .ok_or_else(|| Error::InvalidPeerAddress(addr.clone()))?;
+        network_client.dial(peer_id, addr).await?;
     }
 
     match opt.argument {
-        CliArgument::Provide { path, name } => {
-            // Advertise oneself as a provider of the file on the DHT.
-            network_client.start_providing(name.clone()).await;
-
-            loop {
-                match network_events.recv().await {
-                    // Reply with the content of the file on incoming requests.
-                    Some(Event::InboundRequest { request, channel }) => {
-                        if request == name {
-                            let file_content = std::fs::read_to_string(&path)?;
-                            network_client.respond_file(file_content, channel).await;
+        CliArgument::Provide {
+            path,
+            name,
+            allow,
+            private,
+            replicas,
+            recipient,
+            tag,
+        } => {
+            let allow = (private || !allow.is_empty()).then(|| allow.into_iter().collect());
+            let (root, node, name) = provide(
+                &mut network_client,
+                &store,
+                &policy,
+                &path,
+                &name,
+                allow,
+                recipient,
+            )
+            .await?;
+            publish_keywords(&mut network_client, root, &node, &name, &tag).await;
+            let catalog = Catalog::single(ProvidedFile {
+                name: name.path.clone(),
+                path,
+                root,
+                size: node.size(),
+                is_dir: matches!(node, Node::Directory(_)),
+            });
+
+            // 请求其他节点保存副本，本进程退出后文件仍然可以获取
+            if replicas > 0 {
+                tokio::spawn(maintain_replicas(
+                    network_client.clone(),
+                    ReplicaRequest {
+                        root,
+                        size: node.size(),
+                    },
+                    replicas,
+                    Duration::from_secs(opt.replication_interval),
+                ));
+            }
+
+            // Reply with the content of the block on incoming requests.
+            serve_blocks(network_client, store, policy, catalog, None, network_events).await;
+            return Err(Error::Shutdown);
+        }
+
+        CliArgument::Get {
+            name,
+            cid,
+            sub_path,
+            output,
+            token,
+        } => {
+            let target = Target {
+                name,
+                cid,
+                sub_path,
+                token,
+            };
+            // 交换协议中提供节点以请求的方式推送数据块，下载期间需要处理
+            if opt.exchange {
+                tokio::spawn(serve_blocks(
+                    network_client.clone(),
+                    store.clone(),
+                    policy,
+                    Catalog::default(),
+                    None,
+                    network_events,
+                ));
+            }
+            let (progress, updates) = watch::channel(Progress::default());
+            let display = tokio::spawn(show_progress(updates));
+            let result = get(
+                network_client,
+                &store,
+                target,
+                output,
+                Path::new(""),
+                progress,
+            )
+            .await;
+            let _ = display.await;
+            let (cid, node, output) = result?;
+
+            match &node {
+                Node::File(manifest) => info!(
+                    "File {} saved to {} ({} bytes, sha256 {})",
+                    cid,
+                    output.display(),
+                    manifest.size,
+                    manifest.hash
+                ),
+                Node::Directory(directory) => info!(
+                    "Directory {} saved to {} ({} bytes, {} entries)",
+                    cid,
+                    output.display(),
+                    directory.size,
+                    directory.entries.len()
+                ),
+            }
+        }
+
+        CliArgument::Sync {
+            name,
+            cid,
+            sub_path,
+            path,
+            delete,
+            token,
+        } => {
+            let target = Target {
+                name,
+                cid,
+                sub_path,
+                token,
+            };
+            if opt.exchange {
+                tokio::spawn(serve_blocks(
+                    network_client.clone(),
+                    store.clone(),
+                    policy,
+                    Catalog::default(),
+                    None,
+                    network_events,
+                ));
+            }
+            let (progress, updates) = watch::channel(Progress::default());
+            let display = tokio::spawn(show_progress(updates));
+            let result = sync_path(network_client, &store, target, &path, delete, progress).await;
+            let _ = display.await;
+            let (cid, stats) = result?;
+            info!(
+                "Synced {} to {}: {}",
+                cid,
+                path.display(),
+                format_stats(&stats)
+            );
+        }
+
+        CliArgument::Search { query } => {
+            print_search_results(&network_client.search(&query).await?);
+        }
+
+        CliArgument::List { peer } => {
+            print_catalog(&peer.to_string(), &network_client.list_files(peer).await?);
+        }
+
+        CliArgument::Daemon => {
+            let socket = opt
+                .socket
+                .unwrap_or_else(|| opt.data_dir.join(control::SOCKET_FILE));
+            if let Some(addr) = opt.gateway {
+                let listener = gateway::bind(addr).await?;
+                info!(
+                    "HTTP gateway listening on http://{}",
+                    listener.local_addr()?
+                );
+                tokio::spawn(gateway::serve(
+                    listener,
+                    network_client.clone(),
+                    store.clone(),
+                ));
+            }
+            let watch = opt
+                .watch
+                .map(|path| watcher::WatchFolder::new(&path, opt.watch_name, &opt.data_dir))
+                .transpose()?;
+            Daemon::new(
+                network_client,
+                store,
+                policy,
+                &opt.data_dir,
+                Duration::from_secs(opt.replication_interval),
+                StorageLimits {
+                    quota: opt.storage_quota,
+                    replicas: opt.accept_replicas.then_some(opt.max_replica_bytes),
+                },
+                watch,
+            )?
+            .run(&socket, network_events)
+            .await?;
+        }
+
+        CliArgument::Ctl { .. } | CliArgument::Grant { .. } => {
+            unreachable!("Control commands do not start a node.")
+        }
+    }
+
+    Ok(())
+}
+
+// 将文件或目录存入本地存储，在DHT上宣称提供，并将本节点命名空间下的名称指向它。
+// `allow`不为空时只允许其中的节点和持有能力令牌的节点访问，`recipients`不为空时提供加密给它们的文件
+async fn provide(
+    client: &mut Client,
+    store: &BlockStore,
+    policy: &AccessPolicy,
+    path: &Path,
+    name: &str,
+    allow: Option<HashSet<PeerId>>,
+    recipients: Vec<PeerId>,
+) -> Result<(Cid, Node, Name)> {
+    let name = Name::new(client.local_peer_id(), name)?;
+    // 将文件分块存入本地存储，目录递归存入所有文件和子目录清单。
+    // 清单中记录名称路径的最后一段，作为获取时默认的保存名称
+    let file_name = name.path.rsplit('/').next().unwrap_or_default();
+    let (root, node) = if recipients.is_empty() {
+        store.import(path, file_name.to_string())?
+    } else {
+        import_encrypted(client, store, path, file_name, recipients)?
+    };
+    match &node {
+        Node::File(manifest) => info!(
+            "File {} stored as {} ({} bytes, {} blocks)",
+            name,
+            root,
+            manifest.size,
+            manifest.blocks.len()
+        ),
+        Node::Directory(directory) => info!(
+            "Directory {} stored as {} ({} bytes, {} entries)",
+            name,
+            root,
+            directory.size,
+            directory.entries.len()
+        ),
+    }
+    // 在宣称提供之前设置访问策略
+    policy.set(&name.path, root, store.cids(&root)?, allow);
+    // Advertise oneself as a provider of the file on the DHT.
+    if let Err(e) = client.start_providing(root).await {
+        warn!("Failed to announce provider record: {}", e);
+    }
+    publish_name(client, root, &node, &name).await;
+    info!("Published {} as {}", name, root);
+    Ok((root, node, name))
+}
+
+// 将文件加密给接收者和本节点，密文写入临时文件，存入本地存储后删除
+fn import_encrypted(
+    client: &Client,
+    store: &BlockStore,
+    path: &Path,
+    file_name: &str,
+    mut recipients: Vec<PeerId>,
+) -> Result<(Cid, Node)> {
+    if path.is_dir() {
+        return Err(Error::InvalidArgument(
+            "Only files can be encrypted.".to_string(),
+        ));
+    }
+    let local = client.local_peer_id();
+    if !recipients.contains(&local) {
+        recipients.push(local);
+    }
+    let encrypted = store
+        .tmp_dir()
+        .join(format!(".dfs-encrypt-{:016x}.tmp", rand::random::<u64>()));
+    let result = crypto::encrypt_file(path, &encrypted, &recipients)
+        .and_then(|()| Ok(store.import_file(&encrypted, file_name.to_string(), true)?));
+    let _ = fs::remove_file(&encrypted);
+    let (root, manifest) = result?;
+    info!(
+        "Encrypted {} for {} recipients",
+        path.display(),
+        recipients.len()
+    );
+    Ok((root, Node::File(manifest)))
+}
+
+// 发布签名的名称记录，指向根清单、内容哈希和大小
+async fn publish_name(client: &mut Client, root: Cid, node: &Node, name: &Name) {
+    let record = FileRecord {
+        root,
+        hash: match node {
+            Node::File(manifest) => manifest.hash,
+            Node::Directory(_) => root,
+        },
+        size: node.size(),
+    };
+    if let Err(e) = client.publish_name(name, record).await {
+        warn!("Failed to replicate name record: {}", e);
+    }
+}
+
+// 发布文件的关键词索引，关键词取自名称路径和标签
+async fn publish_keywords(
+    client: &mut Client,
+    root: Cid,
+    node: &Node,
+    name: &Name,
+    tags: &[String],
+) {
+    let entry = IndexEntry {
+        path: name.path.clone(),
+        root,
+        size: node.size(),
+        is_dir: matches!(node, Node::Directory(_)),
+    };
+    let keywords = search::file_keywords(&name.path, tags);
+    if let Err(e) = client.publish_keywords(entry, &keywords).await {
+        warn!("Failed to publish keywords of {}: {}", name, e);
+    }
+}
+
+// 返回本地存储中的数据块，不符合访问策略的请求返回拒绝访问。
+// 没有`replicator`时拒绝其他节点的副本请求。同时运行数据块交换，向其他节点推送它们想要的数据块
+async fn serve_blocks(
+    mut client: Client,
+    store: BlockStore,
+    policy: AccessPolicy,
+    catalog: Catalog,
+    replicator: Option<Replicator>,
+    mut events: Receiver<Event>,
+) {
+    tokio::spawn(
+        client
+            .exchange()
+            .clone()
+            .run(client.clone(), store.clone(), policy.clone()),
+    );
+    while let Some(event) = events.recv().await {
+        match event {
+            Event::InboundRequest {
+                peer,
+                request,
+                channel,
+            } => {
+                let result = match *request {
+                    FileRequest::Block { cid, token, .. } => {
+                        if !policy.is_allowed(&peer, &cid, token.as_deref()) {
+                            debug!("Denied {} access to {}", peer, cid);
+                            client.respond_forbidden(channel).await
+                        } else if store.has(&cid) {
+                            store.touch(&cid);
+                            client
+                                .respond_file(peer, store.block_path(&cid), channel)
+                                .await
+                        } else {
+                            continue;
+                        }
+                    }
+                    FileRequest::Checksums { cid, token } => {
+                        if !policy.is_allowed(&peer, &cid, token.as_deref()) {
+                            debug!("Denied {} access to {}", peer, cid);
+                            client.respond_forbidden(channel).await
+                        } else {
+                            // 计算校验和需要读取整个文件，不阻塞其他请求
+                            tokio::spawn(respond_checksums(
+                                client.clone(),
+                                store.clone(),
+                                cid,
+                                channel,
+                            ));
+                            continue;
                         }
                     }
-                    e => todo!("{:?}", e),
+                    // 只列出请求节点无需能力令牌即可访问的文件
+                    FileRequest::Catalog(request) => {
+                        let page = catalog.page(&store, &policy, &peer, &request);
+                        debug!("Listing {} catalog entries to {}", page.entries.len(), peer);
+                        client.respond_catalog(page, channel).await
+                    }
+                };
+                // 事件循环停止后不再处理请求
+                if result.is_err() {
+                    return;
                 }
             }
+            Event::ReplicaRequest {
+                peer,
+                request,
+                channel,
+            } => {
+                // 检查存储配额需要统计占用，不阻塞其他请求
+                match &replicator {
+                    Some(replicator) => {
+                        let replicator = replicator.clone();
+                        let mut client = client.clone();
+                        tokio::spawn(async move {
+                            let response = replicator.accept(peer, request).await;
+                            let _ = client.respond_replica(response, channel).await;
+                        });
+                    }
+                    None => {
+                        if client
+                            .respond_replica(ReplicaResponse::Declined, channel)
+                            .await
+                            .is_err()
+                        {
+                            return;
+                        }
+                    }
+                }
+            }
+            Event::ExchangeMessage {
+                peer,
+                message,
+                channel,
+            } => {
+                tokio::spawn(exchange::receive(
+                    client.clone(),
+                    store.clone(),
+                    policy.clone(),
+                    peer,
+                    message,
+                    channel,
+                ));
+            }
+        }
+    }
+}
+
+// 回复文件清单下各数据块的弱校验和，清单不在本地时不回复
+async fn respond_checksums(
+    mut client: Client,
+    store: BlockStore,
+    cid: Cid,
+    channel: ResponseChannel<FileResponse>,
+) {
+    let result = tokio::task::spawn_blocking(move || sync::block_checksums(&store, &cid)).await;
+    match result {
+        Ok(Ok(checksums)) => {
+            let _ = client.respond_checksums(checksums, channel).await;
         }
+        Ok(Err(e)) => debug!("Failed to compute checksums of {}: {}", cid, e),
+        Err(e) => warn!("Checksum task failed: {}", e),
+    }
+}
 
-        CliArgument::Get { name } => {
-            // 找到提供该文件的所有节点
-            let providers = network_client.get_providers(name.clone()).await;
-            if providers.is_empty() {
-                return Err(format!("Could not find provider for file {}.", name).into());
-            }
+// 要获取的内容
+struct Target {
+    // 名称，形如`<peer-id>/<path>`
+    name: Option<Name>,
+    // 根清单的内容标识
+    cid: Option<Cid>,
+    // 只获取目录中的子路径
+    sub_path: Option<PathBuf>,
+    // 提供节点签发的能力令牌
+    token: Option<Capability>,
+}
 
-            // 从每个节点请求文件的内容。
-            let requests = providers.into_iter().map(|p| {
-                let mut network_client = network_client.clone();
-                let name = name.clone();
-                async move { network_client.request_file(p, name).await }.boxed()
-            });
+// 在标准错误输出上显示下载进度，下载结束后换行
+async fn show_progress(mut updates: watch::Receiver<Progress>) {
+    let mut shown = false;
+    while updates.changed().await.is_ok() {
+        eprint!("\r{}\x1b[K", *updates.borrow());
+        shown = true;
+        tokio::time::sleep(PROGRESS_INTERVAL).await;
+    }
+    if shown {
+        eprintln!();
+    }
+}
+
+// 解析名称或内容标识，获取根清单并沿子路径找到目标清单，返回用于继续下载内容的调度器
+async fn locate(
+    mut client: Client,
+    store: &BlockStore,
+    Target {
+        name,
+        cid,
+        sub_path,
+        token,
+    }: Target,
+    progress: watch::Sender<Progress>,
+) -> Result<(Cid, Node, Downloader)> {
+    // 解析文件根清单的内容标识，按名称获取时解析为签名有效的最新版本，同时得到公布的内容哈希和大小
+    let (root, record) = match (cid, name) {
+        (Some(cid), _) => (cid, None),
+        (None, Some(name)) => {
+            let record = client.resolve_name(&name).await?;
+            info!("Resolved {} to {}", name, record.root);
+            (record.root, Some(record))
+        }
+        (None, None) => {
+            return Err(Error::InvalidArgument(
+                "Expect either --name or --cid.".to_string(),
+            ))
+        }
+    };
 
-            // 等待请求，一旦有一个请求成功，就忽略剩下的请求。
-            let file = futures::future::select_ok(requests)
-                .await
-                .map_err(|_| "None of the providers returned file.")?
-                .0;
+    // 找到提供该文件的所有节点
+    let providers = client.get_providers(root).await?;
+    if providers.is_empty() {
+        return Err(Error::NoProviders(root));
+    }
+    info!("Found {} providers for file {}", providers.len(), root);
 
-            info!("Content of file {}: {}", name, file);
+    // 获取根清单，再从所有提供节点并行获取数据块，逐块校验内容
+    let mut downloader = Downloader::new(client, store.clone(), providers, token, progress);
+    downloader.hold(root);
+    downloader.fetch(&[root]).await?;
+    let mut node = store.node(&root)?;
+    if let Some(record) = record {
+        if !record.matches(&node) {
+            return Err(Error::InvalidData(format!(
+                "Manifest {} does not match the name record",
+                root
+            )));
         }
     }
 
+    // 沿子路径逐层获取目录清单
+    let mut cid = root;
+    for component in sub_path.iter().flat_map(|p| p.iter()) {
+        let component = component.to_string_lossy();
+        let entry = match &node {
+            Node::Directory(directory) => directory
+                .entries
+                .iter()
+                .find(|e| e.name == component)
+                .ok_or_else(|| {
+                    Error::NotFound(format!("Entry {} in directory {}", component, cid))
+                })?,
+            Node::File(_) => {
+                return Err(Error::InvalidArgument(format!(
+                    "{} is not a directory",
+                    cid
+                )));
+            }
+        };
+        cid = entry.cid;
+        downloader.fetch(&[cid]).await?;
+        node = store.node(&cid)?;
+    }
+    Ok((cid, node, downloader))
+}
+
+// 获取文件或目录，返回获取的清单节点及其保存路径。
+// 保存路径为空时保存到`dir`下的文件名称，下载进度发布到`progress`
+async fn get(
+    client: Client,
+    store: &BlockStore,
+    target: Target,
+    output: Option<PathBuf>,
+    dir: &Path,
+    progress: watch::Sender<Progress>,
+) -> Result<(Cid, Node, PathBuf)> {
+    let (cid, node, mut downloader) = locate(client.clone(), store, target, progress).await?;
+
+    // 数据块写入部分文件，中断后重新执行时从中断处继续。
+    // 根清单的名称不是单个路径组件时改用内容标识，避免写到保存目录之外
+    let output = dir.join(output.unwrap_or_else(|| {
+        if store::is_valid_name(node.name()) {
+            PathBuf::from(node.name())
+        } else {
+            warn!("Ignoring invalid name {:?} of {}", node.name(), cid);
+            PathBuf::from(cid.to_string())
+        }
+    }));
+    downloader.download(cid, &node, &output).await?;
+    // 清单标记为加密的文件在校验后解密，不根据内容判断
+    if matches!(&node, Node::File(manifest) if manifest.encrypted) {
+        client.decrypt(&output)?;
+        info!("Decrypted {}", output.display());
+    }
+    Ok((cid, node, output))
+}
+
+// 将本地路径同步为名称或内容标识对应的文件或目录树
+async fn sync_path(
+    client: Client,
+    store: &BlockStore,
+    target: Target,
+    path: &Path,
+    delete: bool,
+    progress: watch::Sender<Progress>,
+) -> Result<(Cid, SyncStats)> {
+    let (cid, node, mut downloader) = locate(client, store, target, progress).await?;
+    let stats = sync::sync_tree(&mut downloader, store, cid, &node, path, delete).await?;
+    Ok((cid, stats))
+}
+
+// 将控制命令发送给守护进程并输出结果
+async fn control_command(socket: &Path, command: ControlCommand) -> Result<()> {
+    // 守护进程的工作目录可能不同，路径转换为绝对路径
+    let cwd = env::current_dir()?;
+    let request = match command {
+        ControlCommand::Provide {
+            path,
+            name,
+            allow,
+            private,
+            replicas,
+            recipient,
+            tag,
+        } => ControlRequest::Provide {
+            path: path.canonicalize()?,
+            name,
+            allow: (private || !allow.is_empty())
+                .then(|| allow.iter().map(|peer| peer.to_string()).collect()),
+            replicas,
+            recipients: recipient.iter().map(|peer| peer.to_string()).collect(),
+            tags: tag,
+        },
+        ControlCommand::Unprovide { name } => ControlRequest::Unprovide { name },
+        ControlCommand::Get {
+            name,
+            cid,
+            sub_path,
+            output,
+            token,
+        } => ControlRequest::Get {
+            name: name.map(|name| name.to_string()),
+            cid,
+            sub_path,
+            output,
+            dir: cwd,
+            token,
+        },
+        ControlCommand::Sync {
+            name,
+            cid,
+            sub_path,
+            path,
+            delete,
+            token,
+        } => ControlRequest::Sync {
+            name: name.map(|name| name.to_string()),
+            cid,
+            sub_path,
+            path: cwd.join(path),
+            delete,
+            token,
+        },
+        ControlCommand::Grant { name, peer, ttl } => ControlRequest::Grant {
+            name,
+            peer: peer.to_string(),
+            ttl,
+        },
+        ControlCommand::Replicate { name, replicas } => {
+            ControlRequest::Replicate { name, replicas }
+        }
+        ControlCommand::Replicas => ControlRequest::Replicas,
+        ControlCommand::DropReplica { cid } => ControlRequest::DropReplica { root: cid },
+        ControlCommand::Pin { name, cid } => ControlRequest::Pin {
+            name: name.map(|name| name.to_string()),
+            cid,
+        },
+        ControlCommand::Unpin { name, cid } => ControlRequest::Unpin {
+            name: name.map(|name| name.to_string()),
+            cid,
+        },
+        ControlCommand::Pins => ControlRequest::Pins,
+        ControlCommand::Gc => ControlRequest::Gc,
+        ControlCommand::Bandwidth {
+            upload,
+            download,
+            peer_upload,
+            peer_download,
+        } => ControlRequest::Bandwidth(RateLimits {
+            upload,
+            download,
+            peer_upload,
+            peer_download,
+        }),
+        ControlCommand::Search { query } => ControlRequest::Search { query },
+        ControlCommand::List { peer: None } => ControlRequest::List,
+        ControlCommand::List { peer: Some(peer) } => ControlRequest::Catalog {
+            peer: peer.to_string(),
+        },
+        ControlCommand::Advertised => ControlRequest::Advertised,
+        ControlCommand::Status => ControlRequest::Status,
+        ControlCommand::Ledgers => ControlRequest::Ledgers,
+    };
+
+    let mut shown = false;
+    let response = control::request(socket, &request, |progress| {
+        eprint!("\r{}\x1b[K", progress);
+        shown = true;
+    })
+    .await;
+    if shown {
+        eprintln!();
+    }
+    match response? {
+        ControlResponse::Provided { file, name } => {
+            println!("Providing {} as {} ({} bytes)", name, file.root, file.size)
+        }
+        ControlResponse::Unprovided(file) => {
+            println!("Stopped providing {} ({})", file.name, file.root)
+        }
+        ControlResponse::Saved { cid, path, size } => {
+            println!("Saved {} to {} ({} bytes)", cid, path.display(), size)
+        }
+        ControlResponse::Synced { cid, path, stats } => println!(
+            "Synced {} to {}: {}",
+            cid,
+            path.display(),
+            format_stats(&stats)
+        ),
+        ControlResponse::Granted(token) => println!("{}", token),
+        ControlResponse::Replicating { name, replicas } => {
+            println!("Maintaining {} replicas of {}", replicas, name)
+        }
+        ControlResponse::Replicas { files, held } => {
+            for file in files {
+                println!(
+                    "{}\t{}\t{}/{} replicas",
+                    file.name, file.root, file.providers, file.wanted
+                );
+            }
+            for (root, replica) in held {
+                println!(
+                    "{}\t{} bytes\treplica for {}",
+                    root, replica.size, replica.origin
+                );
+            }
+        }
+        ControlResponse::DroppedReplica(root) => println!("Dropped replica {}", root),
+        ControlResponse::Pinned(root) => println!("Pinned {}", root),
+        ControlResponse::Unpinned(root) => println!("Unpinned {}", root),
+        ControlResponse::Pins(pins) => {
+            for (root, name, size) in pins {
+                println!("{}\t{}\t{}", root, name, size);
+            }
+        }
+        ControlResponse::Collected(stats) => println!(
+            "Removed {} blocks ({} bytes), {} bytes in use",
+            stats.removed, stats.freed, stats.used
+        ),
+        ControlResponse::Bandwidth(limits) => println!("Bandwidth limits: {}", limits),
+        ControlResponse::SearchResults(results) => print_search_results(&results),
+        ControlResponse::Files(files) => {
+            for file in files {
+                println!(
+                    "{}\t{}\t{}\t{}{}",
+                    file.name,
+                    file.root,
+                    file.size,
+                    file.path.display(),
+                    if file.is_dir { "/" } else { "" }
+                );
+            }
+        }
+        ControlResponse::Catalog { peer, entries } => print_catalog(&peer, &entries),
+        ControlResponse::Advertised(advertised) => {
+            for (a, name) in advertised {
+                let published = match a.published {
+                    Some(ago) => format!("published {}s ago", ago.as_secs()),
+                    None => "not yet published".to_string(),
+                };
+                println!(
+                    "{}\t{}\t{}, next in {}s{}{}",
+                    a.cid,
+                    name.as_deref().unwrap_or("-"),
+                    published,
+                    a.next_publish.as_secs(),
+                    if a.failures > 0 {
+                        format!(", {} failures", a.failures)
+                    } else {
+                        String::new()
+                    },
+                    if a.expired { ", expired" } else { "" }
+                );
+            }
+        }
+        ControlResponse::Status { network, provided } => {
+            println!("Peer ID: {}", network.peer_id);
+            for addr in network.listeners {
+                println!("Listening on {}", addr);
+            }
+            println!("Connected peers: {}", network.connected_peers.len());
+            for peer in network.connected_peers {
+                println!("  {}", peer);
+            }
+            println!("Provided files: {}", provided);
+        }
+        ControlResponse::Ledgers(ledgers) => {
+            for ledger in ledgers {
+                println!(
+                    "{}\tsent {} bytes\treceived {} bytes\t{} wants",
+                    ledger.peer, ledger.sent, ledger.received, ledger.wants
+                );
+            }
+        }
+        ControlResponse::Progress(_) => unreachable!("Progress is handled by control::request."),
+        ControlResponse::Error(e) => return Err(Error::Control(e)),
+    }
     Ok(())
 }
 
+// 每行输出一个搜索结果：名称、根清单、大小和提供节点数
+fn print_search_results(results: &[SearchResult]) {
+    for result in results {
+        println!(
+            "{}{}\t{}\t{} bytes\t{} providers",
+            result.name,
+            if result.is_dir { "/" } else { "" },
+            result.root,
+            result.size,
+            result.providers
+        );
+    }
+}
+
+fn format_stats(stats: &SyncStats) -> String {
+    format!(
+        "{} updated, {} unchanged, {} removed, {} bytes reused, {} bytes fetched",
+        stats.updated, stats.unchanged, stats.removed, stats.reused, stats.fetched
+    )
+}
+
+// 每行输出一个目录条目：名称、根清单、内容哈希、大小和修改时间
+fn print_catalog(peer: &str, entries: &[CatalogEntry]) {
+    for entry in entries {
+        println!(
+            "{}/{}{}\t{}\t{}\t{} bytes\t{}",
+            peer,
+            entry.name,
+            if entry.is_dir { "/" } else { "" },
+            entry.root,
+            entry.hash,
+            entry.size,
+            entry
+                .modified
+                .map_or_else(|| "-".to_string(), |t| format!("modified {}", t))
+        );
+    }
+}
+
+// 由种子生成密钥对，只用于测试和演示。没有种子时加载数据目录下保存的密钥对，
+// 首次启动时随机生成并保存，节点ID和签名、加密所用的密钥在重启后保持不变
+fn keypair(secret_key_seed: Option<u8>, data_dir: &Path) -> Result<identity::Keypair> {
+    if let Some(seed) = secret_key_seed {
+        let mut bytes = [0u8; 32];
+        bytes[0] = seed;
+        let secret_key = ed25519::SecretKey::from_bytes(&mut bytes)
+            .expect("this returns `Err` only if the length is wrong; the length is correct; qed");
+        return Ok(identity::Keypair::Ed25519(secret_key.into()));
+    }
+
+    let path = data_dir.join(IDENTITY_FILE);
+    match fs::read(&path) {
+        Ok(mut bytes) => {
+            let secret_key = ed25519::SecretKey::from_bytes(&mut bytes).map_err(|_| {
+                Error::InvalidData(format!("Invalid identity key file {}", path.display()))
+            })?;
+            Ok(identity::Keypair::Ed25519(secret_key.into()))
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            let keypair = ed25519::Keypair::generate();
+            fs::create_dir_all(data_dir)?;
+            // 先写入临时文件再重命名，创建时即只有所有者可读写
+            let tmp = path.with_extension("tmp");
+            let mut options = fs::OpenOptions::new();
+            options.write(true).create(true).truncate(true);
+            #[cfg(unix)]
+            std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+            let mut file = options.open(&tmp)?;
+            std::io::Write::write_all(&mut file, keypair.secret().as_ref())?;
+            file.sync_all()?;
+            fs::rename(&tmp, &path)?;
+            Ok(identity::Keypair::Ed25519(keypair))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
 pub async fn network(
     secret_key_seed: Option<u8>,
-) -> Result<(Client, Receiver<Event>, EventLoop), Box<dyn Error>> {
+    data_dir: &Path,
+    download_dir: PathBuf,
+    republish_interval: Duration,
+    limits: Limits,
+    rate_limits: RateLimits,
+    exchange: bool,
+) -> Result<(Client, Receiver<Event>, EventLoop)> {
     // 创建密钥对
-    let id_keys = match secret_key_seed {
-        Some(seed) => {
-            let mut bytes = [0u8; 32];
-            bytes[0] = seed;
-            let secret_key = ed25519::SecretKey::from_bytes(&mut bytes).expect(
-                "this returns `Err` only if the length is wrong; the length is correct; qed",
-            );
-            identity::Keypair::Ed25519(secret_key.into())
-        }
-        None => identity::Keypair::generate_ed25519(),
-    };
+    let id_keys = keypair(secret_key_seed, data_dir)?;
     // 根据公钥生成节点ID
     let peer_id = id_keys.public().to_peer_id();
+    // 编解码器和Client共享带宽限制，运行时调整对所有流生效
+    let bandwidth = Bandwidth::new(rate_limits);
+
+    // 大文件传输耗时较长，放宽请求超时时间
+    let mut request_response_config = RequestResponseConfig::default();
+    request_response_config.set_request_timeout(Duration::from_secs(300));
+
+    // 提供者记录由事件循环定期重新公布
+    let provider_record_ttl = republish_interval * 2;
+    let mut kademlia_config = KademliaConfig::default();
+    kademlia_config
+        .set_provider_publication_interval(None)
+        .set_provider_record_ttl(Some(provider_record_ttl))
+        // 名称记录由事件循环校验签名后再存储
+        .set_record_filtering(KademliaStoreInserts::FilterBoth);
 
     // 构建网络层管理组件Swarm
     let swarm = SwarmBuilder::new(
-        libp2p::development_transport(id_keys).await?,
+        libp2p::development_transport(id_keys.clone()).await?,
         ComposedBehaviour {
-            kademlia: Kademlia::new(peer_id, MemoryStore::new(peer_id)),
+            // DHT记录持久化到数据目录，重启后恢复
+            kademlia: Kademlia::with_config(
+                peer_id,
+                DiskStore::open(&data_dir.join("kad"), peer_id)?,
+                kademlia_config,
+            ),
             request_response: RequestResponse::new(
-                FileSwapCodec(),
-                iter::once((FileSwapProtocol(), ProtocolSupport::Full)),
+                FileSwapCodec::new(download_dir, bandwidth.clone()),
+                FileSwapProtocol::ALL
+                    .into_iter()
+                    .map(|protocol| (protocol, ProtocolSupport::Full)),
+                request_response_config,
+            ),
+            replication: RequestResponse::new(
+                ReplicaCodec::default(),
+                iter::once((ReplicaProtocol(), ProtocolSupport::Full)),
+                Default::default(),
+            ),
+            exchange: RequestResponse::new(
+                ExchangeCodec::default(),
+                iter::once((ExchangeProtocol(), ProtocolSupport::Full)),
                 Default::default(),
             ),
         },
         peer_id,
     )
+    // 编解码器使用tokio文件读写，连接处理需运行在tokio运行时中
+    .executor(Box::new(|fut| {
+        tokio::spawn(fut);
+    }))
     .build();
 
     let (command_sender, command_receiver) = mpsc::channel(1);
-    let (event_sender, event_receiver) = mpsc::channel(1);
+    let (event_sender, event_receiver) = mpsc::channel(EVENT_BUFFER);
 
     Ok((
-        Client::new(command_sender),
+        Client::new(command_sender, id_keys, bandwidth, Exchange::new(exchange)),
         event_receiver,
-        EventLoop::new(swarm, command_receiver, event_sender),
+        EventLoop::new(
+            swarm,
+            command_receiver,
+            event_sender,
+            republish_interval,
+            provider_record_ttl,
+            limits,
+        ),
     ))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn persists_generated_identity() {
+        let dir = tempfile::tempdir().unwrap();
+        let data_dir = dir.path().join("data");
+        let first = keypair(None, &data_dir).unwrap();
+        let again = keypair(None, &data_dir).unwrap();
+        assert_eq!(first.public(), again.public());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let metadata = fs::metadata(data_dir.join(IDENTITY_FILE)).unwrap();
+            assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+        }
+
+        // 种子生成的密钥对不读写数据目录
+        let other = tempfile::tempdir().unwrap();
+        let seeded = keypair(Some(1), other.path()).unwrap();
+        assert_eq!(
+            seeded.public(),
+            keypair(Some(1), &data_dir).unwrap().public()
+        );
+        assert_eq!(fs::read_dir(other.path()).unwrap().count(), 0);
+    }
+}
@@ -0,0 +1,205 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    time::Instant,
+};
+
+use libp2p::{
+    request_response::{RequestId, ResponseChannel},
+    PeerId,
+};
+
+use crate::protocol::{FileRequest, FileResponse};
+
+// 提供节点处理数据块请求的限制
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    // 同时进行的传输数
+    pub max_transfers: usize,
+    // 等待传输的请求数，超出时回复繁忙
+    pub max_queued: usize,
+    // 每个节点每秒的请求数
+    pub peer_rate: f64,
+    // 每个节点允许的突发请求数
+    pub peer_burst: f64,
+}
+
+// 等待传输的请求
+pub struct Inbound {
+    pub request_id: RequestId,
+    pub peer: PeerId,
+    pub request: FileRequest,
+    pub channel: ResponseChannel<FileResponse>,
+}
+
+// 节点的请求令牌桶
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    // 按经过的时间补充令牌
+    fn refill(&mut self, now: Instant, rate: f64, burst: f64) {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(burst);
+        self.updated = now;
+    }
+}
+
+// 入站请求的准入控制：每个节点的请求按令牌桶限速，同时进行的传输数有上限，
+// 超出上限的请求排队等待，队列已满或节点超出速率时回复繁忙
+pub struct InboundLimiter {
+    limits: Limits,
+    // 已交给服务任务、响应尚未发送完成的请求
+    active: HashSet<RequestId>,
+    // 等待传输的请求
+    queued: VecDeque<Inbound>,
+    buckets: HashMap<PeerId, Bucket>,
+}
+
+impl InboundLimiter {
+    pub fn new(limits: Limits) -> Self {
+        Self {
+            limits,
+            active: Default::default(),
+            queued: Default::default(),
+            buckets: Default::default(),
+        }
+    }
+
+    // 判断是否接收节点的请求，节点超出速率，或传输数已满且队列已满时由调用方回复繁忙
+    pub fn admit(&mut self, peer: PeerId) -> bool {
+        let now = Instant::now();
+        let Limits {
+            peer_rate,
+            peer_burst,
+            ..
+        } = self.limits;
+        let bucket = self.buckets.entry(peer).or_insert(Bucket {
+            tokens: peer_burst,
+            updated: now,
+        });
+        bucket.refill(now, peer_rate, peer_burst);
+        // 有空闲的传输位置时请求立即开始，不占用队列
+        let full = self.active.len() >= self.limits.max_transfers
+            && self.queued.len() >= self.limits.max_queued;
+        if bucket.tokens < 1.0 || full {
+            return false;
+        }
+        bucket.tokens -= 1.0;
+        true
+    }
+
+    // 将已接收的请求加入队列
+    pub fn push(&mut self, inbound: Inbound) {
+        self.queued.push_back(inbound);
+    }
+
+    // 取出下一个可以开始传输的请求，跳过连接已关闭的请求
+    pub fn next(&mut self) -> Option<Inbound> {
+        while self.active.len() < self.limits.max_transfers {
+            let inbound = self.queued.pop_front()?;
+            if inbound.channel.is_open() {
+                self.active.insert(inbound.request_id);
+                return Some(inbound);
+            }
+        }
+        None
+    }
+
+    // 响应发送完成或失败，释放传输位置
+    pub fn complete(&mut self, request_id: &RequestId) {
+        if !self.active.remove(request_id) {
+            self.queued
+                .retain(|inbound| inbound.request_id != *request_id);
+        }
+    }
+
+    // 删除已补满的令牌桶，避免为不再请求的节点保留状态
+    pub fn prune(&mut self) {
+        let now = Instant::now();
+        let Limits {
+            peer_rate,
+            peer_burst,
+            ..
+        } = self.limits;
+        self.buckets.retain(|_, bucket| {
+            bucket.refill(now, peer_rate, peer_burst);
+            bucket.tokens < peer_burst
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn limits(peer_rate: f64, peer_burst: f64) -> Limits {
+        Limits {
+            max_transfers: 1,
+            max_queued: 8,
+            peer_rate,
+            peer_burst,
+        }
+    }
+
+    #[test]
+    fn refills_bucket_up_to_burst() {
+        let start = Instant::now();
+        let mut bucket = Bucket {
+            tokens: 0.0,
+            updated: start,
+        };
+        bucket.refill(start + Duration::from_millis(500), 4.0, 10.0);
+        assert_eq!(bucket.tokens, 2.0);
+        bucket.refill(start + Duration::from_secs(60), 4.0, 10.0);
+        assert_eq!(bucket.tokens, 10.0);
+        assert_eq!(bucket.updated, start + Duration::from_secs(60));
+    }
+
+    #[test]
+    fn admits_bursts_per_peer() {
+        let mut limiter = InboundLimiter::new(limits(0.001, 2.0));
+        let (peer, other) = (PeerId::random(), PeerId::random());
+        assert!(limiter.admit(peer));
+        assert!(limiter.admit(peer));
+        assert!(!limiter.admit(peer));
+        assert!(limiter.admit(other));
+
+        // 用完令牌的节点保留令牌桶，补满的节点被清理
+        limiter.prune();
+        assert!(limiter.buckets.contains_key(&peer));
+        let mut limiter = InboundLimiter::new(limits(0.001, 2.0));
+        limiter.buckets.insert(
+            other,
+            Bucket {
+                tokens: 2.0,
+                updated: Instant::now(),
+            },
+        );
+        limiter.prune();
+        assert!(limiter.buckets.is_empty());
+    }
+
+    #[test]
+    fn rejects_when_queue_is_full() {
+        let mut limiter = InboundLimiter::new(Limits {
+            max_transfers: 0,
+            max_queued: 0,
+            ..limits(100.0, 100.0)
+        });
+        assert!(!limiter.admit(PeerId::random()));
+        assert!(limiter.next().is_none());
+    }
+
+    #[test]
+    fn admits_without_queue_while_transfers_are_free() {
+        let mut limiter = InboundLimiter::new(Limits {
+            max_queued: 0,
+            ..limits(100.0, 100.0)
+        });
+        assert!(limiter.admit(PeerId::random()));
+    }
+}
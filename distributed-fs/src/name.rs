@@ -0,0 +1,227 @@
+use std::{fmt, str::FromStr};
+
+use libp2p::{
+    identity::{Keypair, PublicKey},
+    PeerId,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{Error, Result},
+    store::FileRecord,
+};
+
+// DHT中名称记录键的前缀
+const NAME_KEY_PREFIX: &str = "/dfs/name/";
+
+// 可变名称，由发布者的节点ID和路径组成，形如`<peer-id>/<path>`。
+// 只有持有发布者私钥的节点才能更新名称指向的内容
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Name {
+    // 发布者节点ID
+    pub publisher: PeerId,
+    // 发布者命名空间下的路径
+    pub path: String,
+}
+
+impl Name {
+    pub fn new(publisher: PeerId, path: &str) -> Result<Self> {
+        let path = path.trim_matches('/');
+        if path.is_empty() {
+            return Err(Error::InvalidArgument("Name path is empty.".to_string()));
+        }
+        Ok(Self {
+            publisher,
+            path: path.to_string(),
+        })
+    }
+
+    // 名称记录在DHT中的键
+    pub fn key(&self) -> Vec<u8> {
+        format!("{}{}", NAME_KEY_PREFIX, self).into_bytes()
+    }
+
+    // 从DHT记录的键解析名称，不是名称记录时返回None
+    pub fn from_key(key: &[u8]) -> Option<Self> {
+        std::str::from_utf8(key)
+            .ok()?
+            .strip_prefix(NAME_KEY_PREFIX)?
+            .parse()
+            .ok()
+    }
+}
+
+impl fmt::Display for Name {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.publisher, self.path)
+    }
+}
+
+impl FromStr for Name {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let (publisher, path) = s
+            .split_once('/')
+            .ok_or_else(|| "Expect a name of the form <peer-id>/<path>.".to_string())?;
+        let publisher = publisher.parse().map_err(|e| format!("{:?}", e))?;
+        Name::new(publisher, path).map_err(|e| e.to_string())
+    }
+}
+
+// 发布者签名的名称记录，指向名称当前的内容，序号越大版本越新
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NameRecord {
+    // 发布者公钥的protobuf编码
+    pub public_key: Vec<u8>,
+    // 版本序号
+    pub sequence: u64,
+    // 名称指向的内容
+    pub value: FileRecord,
+    // 发布者对名称、版本序号和内容的签名
+    pub signature: Vec<u8>,
+}
+
+impl NameRecord {
+    // 用发布者私钥签名名称记录
+    pub fn sign(keypair: &Keypair, name: &Name, sequence: u64, value: FileRecord) -> Result<Self> {
+        if keypair.public().to_peer_id() != name.publisher {
+            return Err(Error::InvalidArgument(format!(
+                "Only {} can publish {}",
+                name.publisher, name
+            )));
+        }
+        let signature = keypair
+            .sign(&signing_bytes(name, sequence, &value))
+            .map_err(|e| Error::InvalidData(format!("Failed to sign name record: {}", e)))?;
+        Ok(Self {
+            public_key: keypair.public().to_protobuf_encoding(),
+            sequence,
+            value,
+            signature,
+        })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        bincode::serialize(self).expect("Name record to be serializable.")
+    }
+
+    // 解析并校验名称记录：公钥属于名称的发布者，且签名有效
+    pub fn decode_verified(name: &Name, data: &[u8]) -> Result<Self> {
+        let record: NameRecord = bincode::deserialize(data)
+            .map_err(|e| Error::InvalidData(format!("Invalid name record: {}", e)))?;
+        let public_key = PublicKey::from_protobuf_encoding(&record.public_key)
+            .map_err(|e| Error::InvalidData(format!("Invalid publisher key: {}", e)))?;
+        if public_key.to_peer_id() != name.publisher {
+            return Err(Error::InvalidData(format!(
+                "Name record of {} is not signed by its publisher",
+                name
+            )));
+        }
+        if !public_key.verify(
+            &signing_bytes(name, record.sequence, &record.value),
+            &record.signature,
+        ) {
+            return Err(Error::InvalidData(format!(
+                "Invalid signature on name record of {}",
+                name
+            )));
+        }
+        Ok(record)
+    }
+
+    // 从多个节点返回的记录中选出签名有效且序号最大的记录
+    pub fn latest<'a>(name: &Name, values: impl IntoIterator<Item = &'a [u8]>) -> Option<Self> {
+        values
+            .into_iter()
+            .filter_map(|data| match NameRecord::decode_verified(name, data) {
+                Ok(record) => Some(record),
+                Err(e) => {
+                    warn!("Ignoring name record: {}", e);
+                    None
+                }
+            })
+            .max_by_key(|record| record.sequence)
+    }
+}
+
+// 签名的内容：名称、版本序号和名称指向的内容
+fn signing_bytes(name: &Name, sequence: u64, value: &FileRecord) -> Vec<u8> {
+    bincode::serialize(&(name.key(), sequence, value)).expect("Name record to be serializable.")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::store::Cid;
+
+    use super::*;
+
+    fn value(content: &[u8]) -> FileRecord {
+        FileRecord {
+            root: Cid::of(content),
+            hash: Cid::of(content),
+            size: content.len() as u64,
+        }
+    }
+
+    #[test]
+    fn parses_names_and_keys() {
+        let publisher = PeerId::random();
+        let name: Name = format!("{}//docs/a.txt/", publisher).parse().unwrap();
+        assert_eq!(name, Name::new(publisher, "docs/a.txt").unwrap());
+        assert_eq!(Name::from_key(&name.key()), Some(name));
+        assert_eq!(Name::from_key(b"/dfs/file/a.txt"), None);
+        assert!(format!("{}/", publisher).parse::<Name>().is_err());
+        assert!("a.txt".parse::<Name>().is_err());
+    }
+
+    #[test]
+    fn verifies_publisher_signature() {
+        let keypair = Keypair::generate_ed25519();
+        let name = Name::new(keypair.public().to_peer_id(), "a.txt").unwrap();
+        let data = NameRecord::sign(&keypair, &name, 1, value(b"a"))
+            .unwrap()
+            .encode();
+        let record = NameRecord::decode_verified(&name, &data).unwrap();
+        assert_eq!(record.sequence, 1);
+        assert_eq!(record.value, value(b"a"));
+
+        // 其他名称、其他发布者和篡改的记录都无法通过校验
+        let other = Name::new(name.publisher, "b.txt").unwrap();
+        assert!(NameRecord::decode_verified(&other, &data).is_err());
+        let stranger = Keypair::generate_ed25519();
+        assert!(NameRecord::sign(&stranger, &name, 1, value(b"a")).is_err());
+        let mut forged = NameRecord::sign(&keypair, &name, 1, value(b"a")).unwrap();
+        forged.public_key = stranger.public().to_protobuf_encoding();
+        forged.signature = stranger
+            .sign(&signing_bytes(&name, 1, &forged.value))
+            .unwrap();
+        assert!(NameRecord::decode_verified(&name, &forged.encode()).is_err());
+        let mut tampered = record;
+        tampered.sequence = 2;
+        assert!(NameRecord::decode_verified(&name, &tampered.encode()).is_err());
+        assert!(NameRecord::decode_verified(&name, b"garbage").is_err());
+    }
+
+    #[test]
+    fn picks_latest_valid_record() {
+        let keypair = Keypair::generate_ed25519();
+        let name = Name::new(keypair.public().to_peer_id(), "a.txt").unwrap();
+        let sign = |sequence, content| {
+            NameRecord::sign(&keypair, &name, sequence, value(content))
+                .unwrap()
+                .encode()
+        };
+        let mut invalid = NameRecord::sign(&keypair, &name, 9, value(b"old")).unwrap();
+        invalid.sequence = 10;
+        let values = [
+            sign(3, b"v3"),
+            invalid.encode(),
+            sign(7, b"v7"),
+            sign(5, b"v5"),
+        ];
+        let latest = NameRecord::latest(&name, values.iter().map(|v| v.as_slice())).unwrap();
+        assert_eq!((latest.sequence, latest.value), (7, value(b"v7")));
+        assert!(NameRecord::latest(&name, [invalid.encode().as_slice()]).is_none());
+    }
+}
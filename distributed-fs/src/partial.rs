@@ -0,0 +1,244 @@
+use std::{
+    ffi::OsString,
+    fs::{self, File, OpenOptions},
+    io::{self, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::store::{hash_file, Cid, Manifest, CHUNK_SIZE};
+
+// 下载日志，记录已写入部分文件的数据块
+#[derive(Debug, Serialize, Deserialize)]
+struct Journal {
+    // 文件根清单的内容标识
+    root: Cid,
+    // 数据块数量
+    blocks: u64,
+    // 已完成数据块的位图
+    bitmap: Vec<u8>,
+}
+
+impl Journal {
+    fn new(root: Cid, blocks: usize) -> Self {
+        Self {
+            root,
+            blocks: blocks as u64,
+            bitmap: vec![0; blocks.div_ceil(8)],
+        }
+    }
+
+    fn is_set(&self, index: usize) -> bool {
+        self.bitmap[index / 8] & (1 << (index % 8)) != 0
+    }
+
+    fn set(&mut self, index: usize) {
+        self.bitmap[index / 8] |= 1 << (index % 8);
+    }
+}
+
+// 下载中的部分文件，数据块按偏移写入`<output>.part`，
+// 完成情况记录在`<output>.part.journal`中，中断后重新下载同一文件时从中断处继续
+pub struct PartialFile {
+    output: PathBuf,
+    part_path: PathBuf,
+    journal_path: PathBuf,
+    file: File,
+    journal: Journal,
+}
+
+impl PartialFile {
+    // 打开部分文件，日志与当前文件一致时恢复已完成的数据块。位图长度与数据块数量不符的日志
+    // 视为损坏，重新开始
+    pub fn open(output: &Path, root: Cid, manifest: &Manifest) -> io::Result<Self> {
+        let part_path = with_suffix(output, ".part");
+        let journal_path = with_suffix(output, ".part.journal");
+
+        let journal = match fs::read(&journal_path)
+            .ok()
+            .and_then(|data| bincode::deserialize::<Journal>(&data).ok())
+        {
+            Some(journal)
+                if journal.root == root
+                    && journal.blocks == manifest.blocks.len() as u64
+                    && journal.bitmap.len() == manifest.blocks.len().div_ceil(8)
+                    && part_path.is_file() =>
+            {
+                journal
+            }
+            _ => Journal::new(root, manifest.blocks.len()),
+        };
+
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(false)
+            .open(&part_path)?;
+        file.set_len(manifest.size)?;
+
+        Ok(Self {
+            output: output.to_path_buf(),
+            part_path,
+            journal_path,
+            file,
+            journal,
+        })
+    }
+
+    // 已完成的数据块数量
+    pub fn completed(&self) -> usize {
+        (0..self.journal.blocks as usize)
+            .filter(|i| self.journal.is_set(*i))
+            .count()
+    }
+
+    pub fn is_complete(&self, index: usize) -> bool {
+        self.journal.is_set(index)
+    }
+
+    // 将数据块写入对应偏移，并持久化下载日志
+    pub fn write_block(&mut self, index: usize, data: &[u8]) -> io::Result<()> {
+        self.file
+            .seek(SeekFrom::Start((index * CHUNK_SIZE) as u64))?;
+        self.file.write_all(data)?;
+        self.file.sync_data()?;
+
+        self.journal.set(index);
+        let tmp = with_suffix(&self.journal_path, ".tmp");
+        fs::write(
+            &tmp,
+            bincode::serialize(&self.journal).expect("Journal to be serializable."),
+        )?;
+        fs::rename(tmp, &self.journal_path)
+    }
+
+    // 所有数据块完成后校验整个文件的内容哈希，将部分文件移动到保存路径并删除日志。
+    // 校验失败时删除日志，下次重新下载所有数据块
+    pub fn finish(self, expected: &Cid) -> io::Result<()> {
+        if self.completed() as u64 != self.journal.blocks {
+            return Err(io::Error::other("Download is not complete."));
+        }
+        drop(self.file);
+        let hash = hash_file(&self.part_path)?;
+        if hash != *expected {
+            let _ = fs::remove_file(&self.journal_path);
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("File hash {} does not match {}", hash, expected),
+            ));
+        }
+        fs::rename(&self.part_path, &self.output)?;
+        fs::remove_file(&self.journal_path)
+    }
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        store::BlockStore,
+        testing::{import_bytes, temp_store},
+    };
+
+    // 跨越九个数据块的文件，位图占两个字节
+    fn nine_blocks(store: &BlockStore, dir: &Path) -> (Cid, Manifest) {
+        let content: Vec<u8> = (0..CHUNK_SIZE * 8 + 100).map(|i| (i / 7) as u8).collect();
+        import_bytes(store, dir, "source", &content)
+    }
+
+    fn write(store: &BlockStore, partial: &mut PartialFile, manifest: &Manifest, index: usize) {
+        let data = store.get(&manifest.blocks[index]).unwrap();
+        partial.write_block(index, &data).unwrap();
+    }
+
+    #[test]
+    fn resumes_completed_blocks() {
+        let (dir, store) = temp_store();
+        let (root, manifest) = nine_blocks(&store, dir.path());
+        let output = dir.path().join("output");
+
+        let mut partial = PartialFile::open(&output, root, &manifest).unwrap();
+        for index in [0, 3, 8] {
+            write(&store, &mut partial, &manifest, index);
+        }
+        drop(partial);
+
+        let mut partial = PartialFile::open(&output, root, &manifest).unwrap();
+        assert_eq!(partial.completed(), 3);
+        assert!(partial.is_complete(8));
+        assert!(!partial.is_complete(7));
+        for index in 0..manifest.blocks.len() {
+            if !partial.is_complete(index) {
+                write(&store, &mut partial, &manifest, index);
+            }
+        }
+        partial.finish(&manifest.hash).unwrap();
+
+        assert_eq!(
+            fs::read(&output).unwrap(),
+            fs::read(dir.path().join("source")).unwrap()
+        );
+        assert!(!with_suffix(&output, ".part").exists());
+        assert!(!with_suffix(&output, ".part.journal").exists());
+    }
+
+    #[test]
+    fn restarts_for_other_files() {
+        let (dir, store) = temp_store();
+        let (root, manifest) = nine_blocks(&store, dir.path());
+        let output = dir.path().join("output");
+        let mut partial = PartialFile::open(&output, root, &manifest).unwrap();
+        write(&store, &mut partial, &manifest, 1);
+        drop(partial);
+
+        let partial = PartialFile::open(&output, Cid::of(b"other"), &manifest).unwrap();
+        assert_eq!(partial.completed(), 0);
+        // 部分文件被删除时日志失效
+        fs::remove_file(with_suffix(&output, ".part")).unwrap();
+        let partial = PartialFile::open(&output, root, &manifest).unwrap();
+        assert_eq!(partial.completed(), 0);
+        assert!(partial.finish(&manifest.hash).is_err());
+    }
+
+    #[test]
+    fn restarts_for_truncated_bitmap() {
+        let (dir, store) = temp_store();
+        let (root, manifest) = nine_blocks(&store, dir.path());
+        let output = dir.path().join("output");
+        let mut partial = PartialFile::open(&output, root, &manifest).unwrap();
+        write(&store, &mut partial, &manifest, 1);
+        drop(partial);
+
+        let journal_path = with_suffix(&output, ".part.journal");
+        let mut journal: Journal = bincode::deserialize(&fs::read(&journal_path).unwrap()).unwrap();
+        journal.bitmap.truncate(1);
+        fs::write(&journal_path, bincode::serialize(&journal).unwrap()).unwrap();
+
+        let mut partial = PartialFile::open(&output, root, &manifest).unwrap();
+        assert_eq!(partial.completed(), 0);
+        write(&store, &mut partial, &manifest, 8);
+        assert!(partial.is_complete(8));
+    }
+
+    #[test]
+    fn rejects_mismatched_content() {
+        let (dir, store) = temp_store();
+        let (root, manifest) = nine_blocks(&store, dir.path());
+        let output = dir.path().join("output");
+        let mut partial = PartialFile::open(&output, root, &manifest).unwrap();
+        for (index, cid) in manifest.blocks.iter().enumerate() {
+            let size = store.get(cid).unwrap().len();
+            partial.write_block(index, &vec![0; size]).unwrap();
+        }
+        assert!(partial.finish(&manifest.hash).is_err());
+        assert!(!output.exists());
+        assert!(!with_suffix(&output, ".part.journal").exists());
+    }
+}
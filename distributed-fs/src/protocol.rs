@@ -1,31 +1,308 @@
+use std::{
+    path::PathBuf,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
 use async_trait::async_trait;
-use futures::{io, AsyncRead, AsyncWrite, AsyncWriteExt};
+use futures::{io, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use libp2p::{
     core::{
-        upgrade::{read_length_prefixed, write_length_prefixed},
+        upgrade::{read_length_prefixed, read_varint, write_length_prefixed, write_varint},
         ProtocolName,
     },
     request_response::RequestResponseCodec,
+    PeerId,
 };
 
-#[derive(Debug, Clone)]
-pub struct FileSwapProtocol();
+use crate::{
+    access::Capability,
+    bandwidth::Bandwidth,
+    catalog::{CatalogPage, CatalogRequest},
+    store::{Cid, MAX_NODE_SIZE},
+};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    fs::{self, File},
+    io::{AsyncReadExt as _, AsyncSeekExt as _, AsyncWriteExt as _},
+};
+
+// 请求消息的最大字节数
+const MAX_REQUEST_SIZE: usize = 4 * 1024;
+// 每个数据帧的最大字节数
+const MAX_FRAME_SIZE: usize = 64 * 1024;
+// 响应状态：后续为文件内容
+const STATUS_CONTENT: u8 = 0;
+// 响应状态：提供节点拒绝访问
+const STATUS_FORBIDDEN: u8 = 1;
+// 响应状态：提供节点繁忙
+const STATUS_BUSY: u8 = 2;
+// 响应状态：后续为目录的一页
+const STATUS_CATALOG: u8 = 3;
+// 响应状态：后续为数据块的弱校验和
+const STATUS_CHECKSUMS: u8 = 4;
+// `/dfs/4`请求类型：数据块
+const REQUEST_BLOCK: u8 = 0;
+// `/dfs/4`请求类型：目录
+const REQUEST_CATALOG: u8 = 1;
+// `/dfs/4`请求类型：文件清单下各数据块的弱校验和
+const REQUEST_CHECKSUMS: u8 = 2;
+// 内容响应的最大字节数：每个响应是一个数据块或一个清单节点，清单节点的上限大于分块大小
+const MAX_CONTENT_SIZE: u64 = MAX_NODE_SIZE as u64;
+// 目录和校验和响应的最大字节数
+const MAX_MESSAGE_SIZE: usize = 1024 * 1024;
+// 数据块交换消息的最大字节数，发送方每条消息中数据块的总大小不超过1 MiB
+const MAX_EXCHANGE_MESSAGE_SIZE: usize = 4 * 1024 * 1024;
+// 只压缩不超过此大小的内容，接收方也据此限制解压后的大小
+const MAX_COMPRESSED_CONTENT: u64 = 4 * 1024 * 1024;
+// zstd压缩级别
+const ZSTD_LEVEL: i32 = 3;
+
+// 为接收到的临时文件生成唯一序号
+static TEMP_FILE_COUNTER: AtomicU64 = AtomicU64::new(0);
 
+// 文件交换协议版本，新版本优先，对端不支持时回退到`/dfs/2`。
+// 按帧传输的格式与整个文件作为一条字符串消息传输的`/dfs/1`不兼容，因此从`/dfs/2`开始编号
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileSwapProtocol {
+    // 内容不压缩
+    V2,
+    // 请求方列出支持的压缩算法，提供方为每次传输选择其中之一
+    V3,
+    // 请求以请求类型开头，除数据块外还可以请求提供节点的目录
+    V4,
+}
+
+// 内容的压缩算法
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Compression {
+    None = 0,
+    Zstd = 1,
+}
+
+impl Compression {
+    // 本节点支持的压缩算法，按优先顺序排列
+    const SUPPORTED: &'static [Compression] = &[Compression::Zstd];
+
+    fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Compression::None),
+            1 => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+}
+
+// 编解码器，响应内容按帧流式写入`download_dir`下的临时文件。
+// 同一个请求的读写使用同一个编解码器实例，`peer`记录当前流的对端节点
 #[derive(Clone)]
-pub struct FileSwapCodec();
+pub struct FileSwapCodec {
+    download_dir: PathBuf,
+    // 读写数据帧之前申请带宽
+    bandwidth: Bandwidth,
+    // 当前流的对端节点
+    peer: Option<PeerId>,
+    // 提供方为当前请求协商的压缩算法
+    compression: Compression,
+}
 
+// 文件交换请求
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub struct FileRequest(pub String);
+pub enum FileRequest {
+    // 请求指定内容标识的数据块，可以附带提供节点签发的能力令牌，令牌较大，装箱以免增大目录请求。
+    // `peer`在请求方为提供节点，只在本地用于限制下载带宽，不写入流中
+    Block {
+        cid: Cid,
+        token: Option<Box<Capability>>,
+        peer: Option<PeerId>,
+    },
+    // 请求提供节点的目录，只有`/dfs/4`支持
+    Catalog(CatalogRequest),
+    // 请求文件清单下各数据块的弱校验和，用于与本地旧文件比较，只有`/dfs/4`支持
+    Checksums {
+        cid: Cid,
+        token: Option<Box<Capability>>,
+    },
+}
 
+// 文件响应
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub struct FileResponse(pub String);
+pub enum FileResponse {
+    // 提供方为待发送的本地文件路径，请求方为已接收内容的临时文件路径。
+    // `peer`在提供方为请求节点，只在本地用于限制上传带宽，不写入流中
+    Content { path: PathBuf, peer: Option<PeerId> },
+    // 目录的一页
+    Catalog(CatalogPage),
+    // 按顺序排列的数据块弱校验和
+    Checksums(Vec<u32>),
+    // 提供节点拒绝访问
+    Forbidden,
+    // 提供节点繁忙，请求方应稍后重试或向其他节点请求
+    Busy,
+}
+
+impl FileSwapProtocol {
+    // 支持的协议版本，按优先顺序排列
+    pub const ALL: [FileSwapProtocol; 3] = [
+        FileSwapProtocol::V4,
+        FileSwapProtocol::V3,
+        FileSwapProtocol::V2,
+    ];
+}
 
 impl ProtocolName for FileSwapProtocol {
     fn protocol_name(&self) -> &[u8] {
-        "/dfs/1".as_bytes()
+        match self {
+            FileSwapProtocol::V2 => "/dfs/2".as_bytes(),
+            FileSwapProtocol::V3 => "/dfs/3".as_bytes(),
+            FileSwapProtocol::V4 => "/dfs/4".as_bytes(),
+        }
+    }
+}
+
+impl FileSwapCodec {
+    pub fn new(download_dir: PathBuf, bandwidth: Bandwidth) -> Self {
+        Self {
+            download_dir,
+            bandwidth,
+            peer: None,
+            compression: Compression::None,
+        }
+    }
+
+    // 生成临时文件路径
+    fn temp_path(&self) -> PathBuf {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or_default();
+        let seq = TEMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed);
+        self.download_dir
+            .join(format!(".dfs-{}-{}-{}.tmp", std::process::id(), nanos, seq))
     }
 }
 
+// 将内容帧写入`out`，读取的字节数必须与声明的大小一致
+async fn read_frames<T, W>(
+    io: &mut T,
+    out: &mut W,
+    size: u64,
+    bandwidth: &Bandwidth,
+    peer: Option<PeerId>,
+) -> io::Result<()>
+where
+    T: AsyncRead + Unpin + Send,
+    W: tokio::io::AsyncWrite + Unpin,
+{
+    let mut received = 0u64;
+    let mut buf = vec![0u8; MAX_FRAME_SIZE];
+    while received < size {
+        let len = read_varint(io).await?;
+        if len == 0 || len > MAX_FRAME_SIZE || received + len as u64 > size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid frame of {} bytes", len),
+            ));
+        }
+        // 推迟读取数据帧，由流的背压限制对端的发送速率
+        bandwidth.download(peer, len).await;
+        io.read_exact(&mut buf[..len]).await?;
+        out.write_all(&buf[..len]).await?;
+        received += len as u64;
+    }
+    out.flush().await?;
+
+    Ok(())
+}
+
+// 读取内容并写入临时文件。压缩的内容先完整接收，解压后校验大小
+async fn read_content<T>(
+    io: &mut T,
+    file: &mut File,
+    compression: Compression,
+    size: u64,
+    bandwidth: &Bandwidth,
+    peer: Option<PeerId>,
+) -> io::Result<()>
+where
+    T: AsyncRead + Unpin + Send,
+{
+    match compression {
+        Compression::None => read_frames(io, file, size, bandwidth, peer).await,
+        Compression::Zstd => {
+            let compressed_size = read_varint(io).await? as u64;
+            if size > MAX_COMPRESSED_CONTENT || compressed_size > MAX_COMPRESSED_CONTENT {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Compressed content of {} bytes is too large", size),
+                ));
+            }
+            let mut compressed = Vec::with_capacity(compressed_size as usize);
+            read_frames(io, &mut compressed, compressed_size, bandwidth, peer).await?;
+            let content = zstd::bulk::decompress(&compressed, size as usize)?;
+            if content.len() as u64 != size {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Decompressed {} bytes, expected {}", content.len(), size),
+                ));
+            }
+            file.write_all(&content).await?;
+            file.flush().await
+        }
+    }
+}
+
+// 按帧发送内容，发送每帧之前申请上传带宽
+async fn write_frames<T, R>(
+    io: &mut T,
+    content: &mut R,
+    size: u64,
+    bandwidth: &Bandwidth,
+    peer: Option<PeerId>,
+) -> io::Result<()>
+where
+    T: AsyncWrite + Unpin + Send,
+    R: tokio::io::AsyncRead + Unpin,
+{
+    let mut sent = 0u64;
+    let mut buf = vec![0u8; MAX_FRAME_SIZE];
+    while sent < size {
+        let n = content.read(&mut buf).await?;
+        if n == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let n = n.min((size - sent) as usize);
+        bandwidth.upload(peer, n).await;
+        write_varint(io, n).await?;
+        io.write_all(&buf[..n]).await?;
+        sent += n as u64;
+    }
+    Ok(())
+}
+
+// 按协商的算法压缩内容，压缩后没有变小时不压缩
+async fn compress(
+    file: &mut File,
+    compression: Compression,
+    size: u64,
+) -> io::Result<Option<Vec<u8>>> {
+    if compression == Compression::None || size > MAX_COMPRESSED_CONTENT {
+        return Ok(None);
+    }
+    let mut content = Vec::with_capacity(size as usize);
+    file.read_to_end(&mut content).await?;
+    if content.len() as u64 != size {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    let compressed = zstd::bulk::compress(&content, ZSTD_LEVEL)?;
+    if compressed.len() < content.len() {
+        return Ok(Some(compressed));
+    }
+    // 不压缩时从头发送文件
+    file.rewind().await?;
+    Ok(None)
+}
+
 #[async_trait]
 impl RequestResponseCodec for FileSwapCodec {
     type Protocol = FileSwapProtocol;
@@ -35,69 +312,675 @@ impl RequestResponseCodec for FileSwapCodec {
     // 读请求
     async fn read_request<T>(
         &mut self,
-        _: &FileSwapProtocol,
+        protocol: &FileSwapProtocol,
         io: &mut T,
     ) -> io::Result<Self::Request>
     where
         T: AsyncRead + Unpin + Send,
     {
+        // `/dfs/4`的请求以请求类型开头，目录请求为长度前缀的bincode消息，
+        // 校验和请求与数据块请求一样是内容标识和能力令牌
+        let mut kind = REQUEST_BLOCK;
+        if *protocol == FileSwapProtocol::V4 {
+            let mut buf = [0u8; 1];
+            io.read_exact(&mut buf).await?;
+            kind = buf[0];
+            match kind {
+                REQUEST_BLOCK | REQUEST_CHECKSUMS => {}
+                REQUEST_CATALOG => {
+                    return Ok(FileRequest::Catalog(
+                        read_message(io, MAX_REQUEST_SIZE).await?,
+                    ))
+                }
+                kind => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Unknown request type {}", kind),
+                    ))
+                }
+            }
+        }
+
         // 读取固定长度的字节
-        let vec = read_length_prefixed(io, 1_000_000).await?;
+        let vec = read_length_prefixed(io, MAX_REQUEST_SIZE).await?;
 
         if vec.is_empty() {
             return Err(io::ErrorKind::UnexpectedEof.into());
         }
 
-        Ok(FileRequest(String::from_utf8(vec).unwrap()))
+        let cid = Cid::from_bytes(&vec)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Invalid content id"))?;
+
+        // 能力令牌是可选的，不带令牌的请求在内容标识后直接结束
+        let vec = read_length_prefixed(io, MAX_REQUEST_SIZE).await?;
+        let token = if vec.is_empty() {
+            None
+        } else {
+            Some(Box::new(Capability::decode(&vec).map_err(|e| {
+                io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+            })?))
+        };
+        if kind == REQUEST_CHECKSUMS {
+            return Ok(FileRequest::Checksums { cid, token });
+        }
+
+        // `/dfs/3`起的请求最后是请求方支持的压缩算法，选择本节点也支持的第一个
+        self.compression = Compression::None;
+        if *protocol != FileSwapProtocol::V2 {
+            let accepted = read_length_prefixed(io, MAX_REQUEST_SIZE).await?;
+            self.compression = Compression::SUPPORTED
+                .iter()
+                .copied()
+                .find(|c| accepted.contains(&(*c as u8)))
+                .unwrap_or(Compression::None);
+        }
+        Ok(FileRequest::Block {
+            cid,
+            token,
+            peer: None,
+        })
     }
 
-    // 读取响应
+    // 读取响应，内容按帧写入临时文件
     async fn read_response<T>(
         &mut self,
-        _: &FileSwapProtocol,
+        protocol: &FileSwapProtocol,
         io: &mut T,
     ) -> io::Result<Self::Response>
     where
         T: AsyncRead + Unpin + Send,
     {
-        // 读取固定长度的字节
-        let vec = read_length_prefixed(io, 1_000_000).await?;
+        let mut status = [0u8; 1];
+        io.read_exact(&mut status).await?;
+        match status[0] {
+            STATUS_CONTENT => {}
+            STATUS_FORBIDDEN => return Ok(FileResponse::Forbidden),
+            STATUS_BUSY => return Ok(FileResponse::Busy),
+            STATUS_CATALOG => {
+                let vec = read_length_prefixed(io, MAX_MESSAGE_SIZE).await?;
+                let page = bincode::deserialize(&vec)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                return Ok(FileResponse::Catalog(page));
+            }
+            STATUS_CHECKSUMS => {
+                let vec = read_length_prefixed(io, MAX_MESSAGE_SIZE).await?;
+                if vec.len() % 4 != 0 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "Invalid checksums",
+                    ));
+                }
+                let checksums = vec
+                    .chunks_exact(4)
+                    .map(|c| u32::from_be_bytes([c[0], c[1], c[2], c[3]]))
+                    .collect();
+                return Ok(FileResponse::Checksums(checksums));
+            }
+            status => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Unknown response status {}", status),
+                ))
+            }
+        }
+        let compression = match protocol {
+            FileSwapProtocol::V2 => Compression::None,
+            FileSwapProtocol::V3 | FileSwapProtocol::V4 => {
+                let mut compression = [0u8; 1];
+                io.read_exact(&mut compression).await?;
+                Compression::from_u8(compression[0]).ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Unknown compression {}", compression[0]),
+                    )
+                })?
+            }
+        };
+        // 大小由对端声明，创建临时文件前校验，避免恶意节点写满磁盘
+        let size = read_varint(io).await? as u64;
+        if size > MAX_CONTENT_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Content of {} bytes is too large", size),
+            ));
+        }
 
-        if vec.is_empty() {
-            return Err(io::ErrorKind::UnexpectedEof.into());
+        let path = self.temp_path();
+        let mut file = File::create(&path).await?;
+        let result =
+            read_content(io, &mut file, compression, size, &self.bandwidth, self.peer).await;
+        if let Err(e) = result {
+            drop(file);
+            let _ = fs::remove_file(&path).await;
+            return Err(e);
         }
 
-        Ok(FileResponse(String::from_utf8(vec).unwrap()))
+        Ok(FileResponse::Content { path, peer: None })
     }
 
     // 写请求
     async fn write_request<T>(
         &mut self,
-        _: &FileSwapProtocol,
+        protocol: &FileSwapProtocol,
         io: &mut T,
-        FileRequest(data): FileRequest,
+        request: FileRequest,
     ) -> io::Result<()>
     where
         T: AsyncWrite + Unpin + Send,
     {
-        write_length_prefixed(io, data).await?;
+        // 目录和校验和请求只有`/dfs/4`支持
+        let unsupported = |what: &str| {
+            Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("Peer does not support {} requests", what),
+            ))
+        };
+        let (cid, token) = match request {
+            FileRequest::Block { cid, token, peer } => {
+                // 读取响应时按提供节点限制下载带宽
+                self.peer = peer;
+                if *protocol == FileSwapProtocol::V4 {
+                    io.write_all(&[REQUEST_BLOCK]).await?;
+                }
+                (cid, token)
+            }
+            FileRequest::Catalog(request) => {
+                if *protocol != FileSwapProtocol::V4 {
+                    return unsupported("catalog");
+                }
+                io.write_all(&[REQUEST_CATALOG]).await?;
+                return write_message(io, &request).await;
+            }
+            FileRequest::Checksums { cid, token } => {
+                if *protocol != FileSwapProtocol::V4 {
+                    return unsupported("checksum");
+                }
+                io.write_all(&[REQUEST_CHECKSUMS]).await?;
+                write_length_prefixed(io, cid.as_bytes()).await?;
+                let token = token.map(|token| token.encode()).unwrap_or_default();
+                write_length_prefixed(io, token).await?;
+                return io.close().await;
+            }
+        };
+        write_length_prefixed(io, cid.as_bytes()).await?;
+        match protocol {
+            FileSwapProtocol::V2 => {
+                if let Some(token) = token {
+                    write_length_prefixed(io, token.encode()).await?;
+                }
+            }
+            // 没有令牌时写入空消息，之后是支持的压缩算法
+            FileSwapProtocol::V3 | FileSwapProtocol::V4 => {
+                let token = token.map(|token| token.encode()).unwrap_or_default();
+                write_length_prefixed(io, token).await?;
+                let accepted: Vec<u8> = Compression::SUPPORTED.iter().map(|c| *c as u8).collect();
+                write_length_prefixed(io, accepted).await?;
+            }
+        }
         io.close().await?;
 
         Ok(())
     }
 
+    // 写响应，从本地文件按帧读取并发送
     async fn write_response<T>(
         &mut self,
-        _: &FileSwapProtocol,
+        protocol: &FileSwapProtocol,
         io: &mut T,
-        FileResponse(data): FileResponse,
+        response: FileResponse,
     ) -> io::Result<()>
     where
         T: AsyncWrite + Unpin + Send,
     {
-        write_length_prefixed(io, data).await?;
+        let path = match response {
+            FileResponse::Content { path, peer } => {
+                self.peer = peer;
+                path
+            }
+            FileResponse::Catalog(page) => {
+                io.write_all(&[STATUS_CATALOG]).await?;
+                return write_message(io, &page).await;
+            }
+            FileResponse::Checksums(checksums) => {
+                io.write_all(&[STATUS_CHECKSUMS]).await?;
+                let vec: Vec<u8> = checksums.iter().flat_map(|c| c.to_be_bytes()).collect();
+                write_length_prefixed(io, vec).await?;
+                return io.close().await;
+            }
+            FileResponse::Forbidden => {
+                io.write_all(&[STATUS_FORBIDDEN]).await?;
+                io.close().await?;
+                return Ok(());
+            }
+            FileResponse::Busy => {
+                io.write_all(&[STATUS_BUSY]).await?;
+                io.close().await?;
+                return Ok(());
+            }
+        };
+        let mut file = File::open(&path).await?;
+        let size = file.metadata().await?.len();
+
+        io.write_all(&[STATUS_CONTENT]).await?;
+        if *protocol == FileSwapProtocol::V2 {
+            write_varint(io, size as usize).await?;
+            write_frames(io, &mut file, size, &self.bandwidth, self.peer).await?;
+            return io.close().await;
+        }
+
+        // `/dfs/3`起在内容大小之前写入压缩算法，压缩的内容还要写入压缩后的大小
+        match compress(&mut file, self.compression, size).await? {
+            Some(compressed) => {
+                io.write_all(&[Compression::Zstd as u8]).await?;
+                write_varint(io, size as usize).await?;
+                write_varint(io, compressed.len()).await?;
+                let len = compressed.len() as u64;
+                write_frames(io, &mut &compressed[..], len, &self.bandwidth, self.peer).await?;
+            }
+            None => {
+                io.write_all(&[Compression::None as u8]).await?;
+                write_varint(io, size as usize).await?;
+                write_frames(io, &mut file, size, &self.bandwidth, self.peer).await?;
+            }
+        }
         io.close().await?;
 
         Ok(())
     }
 }
+
+#[derive(Debug, Clone)]
+pub struct ReplicaProtocol();
+
+// 副本请求的编解码器，请求和响应均为长度前缀的bincode消息
+#[derive(Debug, Clone, Default)]
+pub struct ReplicaCodec();
+
+// 请求节点获取并提供文件的副本
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReplicaRequest {
+    // 根清单的内容标识
+    pub root: Cid,
+    // 文件大小或目录总大小
+    pub size: u64,
+}
+
+// 副本响应
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ReplicaResponse {
+    // 节点将获取并提供副本
+    Accepted,
+    // 节点不保存副本
+    Declined,
+}
+
+impl ProtocolName for ReplicaProtocol {
+    fn protocol_name(&self) -> &[u8] {
+        "/dfs-replicate/1".as_bytes()
+    }
+}
+
+async fn read_message<T, M>(io: &mut T, max_size: usize) -> io::Result<M>
+where
+    T: AsyncRead + Unpin + Send,
+    M: serde::de::DeserializeOwned,
+{
+    let vec = read_length_prefixed(io, max_size).await?;
+    bincode::deserialize(&vec).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+async fn write_message<T, M>(io: &mut T, message: &M) -> io::Result<()>
+where
+    T: AsyncWrite + Unpin + Send,
+    M: Serialize,
+{
+    let vec = bincode::serialize(message).expect("Message to be serializable.");
+    write_length_prefixed(io, vec).await?;
+    io.close().await
+}
+
+#[async_trait]
+impl RequestResponseCodec for ReplicaCodec {
+    type Protocol = ReplicaProtocol;
+    type Request = ReplicaRequest;
+    type Response = ReplicaResponse;
+
+    async fn read_request<T>(
+        &mut self,
+        _: &ReplicaProtocol,
+        io: &mut T,
+    ) -> io::Result<Self::Request>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read_message(io, MAX_REQUEST_SIZE).await
+    }
+
+    async fn read_response<T>(
+        &mut self,
+        _: &ReplicaProtocol,
+        io: &mut T,
+    ) -> io::Result<Self::Response>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read_message(io, MAX_REQUEST_SIZE).await
+    }
+
+    async fn write_request<T>(
+        &mut self,
+        _: &ReplicaProtocol,
+        io: &mut T,
+        request: ReplicaRequest,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_message(io, &request).await
+    }
+
+    async fn write_response<T>(
+        &mut self,
+        _: &ReplicaProtocol,
+        io: &mut T,
+        response: ReplicaResponse,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_message(io, &response).await
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ExchangeProtocol();
+
+impl ProtocolName for ExchangeProtocol {
+    fn protocol_name(&self) -> &[u8] {
+        "/dfs-exchange/1".as_bytes()
+    }
+}
+
+// 数据块交换的编解码器，每条消息作为一个请求发送，接收方处理完后以空响应确认
+#[derive(Debug, Clone, Default)]
+pub struct ExchangeCodec();
+
+// 想要列表中的条目
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Want {
+    pub cid: Cid,
+    // 为真时只需要知道对方是否拥有该数据块，拥有时回复在拥有列表中而不发送内容
+    pub have_only: bool,
+}
+
+// 数据块交换消息。想要列表增量发送，接收方记住发送方想要的数据块，
+// 之后获得时再推送，直到发送方取消
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExchangeMessage {
+    // 发送方新增想要的数据块，按优先顺序排列
+    pub wants: Vec<Want>,
+    // 发送方不再想要的数据块
+    pub cancels: Vec<Cid>,
+    // 发送方拥有的、接收方想要的数据块
+    pub haves: Vec<Cid>,
+    // 发送方没有的、接收方想要的数据块
+    pub dont_haves: Vec<Cid>,
+    // 发送方不允许接收方访问的、接收方想要的数据块
+    pub forbidden: Vec<Cid>,
+    // 接收方想要的数据块内容
+    pub blocks: Vec<Vec<u8>>,
+    // 编码后的能力令牌，为空时没有
+    pub token: Vec<u8>,
+}
+
+impl ExchangeMessage {
+    pub fn is_empty(&self) -> bool {
+        self.wants.is_empty()
+            && self.cancels.is_empty()
+            && self.haves.is_empty()
+            && self.dont_haves.is_empty()
+            && self.forbidden.is_empty()
+            && self.blocks.is_empty()
+            && self.token.is_empty()
+    }
+}
+
+#[async_trait]
+impl RequestResponseCodec for ExchangeCodec {
+    type Protocol = ExchangeProtocol;
+    type Request = ExchangeMessage;
+    type Response = ();
+
+    async fn read_request<T>(
+        &mut self,
+        _: &ExchangeProtocol,
+        io: &mut T,
+    ) -> io::Result<Self::Request>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read_message(io, MAX_EXCHANGE_MESSAGE_SIZE).await
+    }
+
+    async fn read_response<T>(
+        &mut self,
+        _: &ExchangeProtocol,
+        io: &mut T,
+    ) -> io::Result<Self::Response>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read_message(io, MAX_REQUEST_SIZE).await
+    }
+
+    async fn write_request<T>(
+        &mut self,
+        _: &ExchangeProtocol,
+        io: &mut T,
+        message: ExchangeMessage,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_message(io, &message).await
+    }
+
+    async fn write_response<T>(
+        &mut self,
+        _: &ExchangeProtocol,
+        io: &mut T,
+        response: (),
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_message(io, &response).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::Path, time::Duration};
+
+    use futures::io::Cursor;
+    use libp2p::{identity::Keypair, PeerId};
+
+    use super::*;
+    use crate::bandwidth::RateLimits;
+
+    fn codec(dir: &Path) -> FileSwapCodec {
+        FileSwapCodec::new(dir.to_path_buf(), Bandwidth::new(RateLimits::default()))
+    }
+
+    // 请求方写入请求，提供方读取
+    async fn send_request(
+        protocol: &FileSwapProtocol,
+        provider: &mut FileSwapCodec,
+        request: FileRequest,
+    ) -> io::Result<FileRequest> {
+        let dir = tempfile::tempdir()?;
+        let mut io = Cursor::new(Vec::new());
+        codec(dir.path())
+            .write_request(protocol, &mut io, request)
+            .await?;
+        io.set_position(0);
+        provider.read_request(protocol, &mut io).await
+    }
+
+    // 提供方写入响应，请求方读取
+    async fn send_response(
+        protocol: &FileSwapProtocol,
+        provider: &mut FileSwapCodec,
+        requester: &mut FileSwapCodec,
+        response: FileResponse,
+    ) -> io::Result<FileResponse> {
+        let mut io = Cursor::new(Vec::new());
+        provider.write_response(protocol, &mut io, response).await?;
+        io.set_position(0);
+        requester.read_response(protocol, &mut io).await
+    }
+
+    #[tokio::test]
+    async fn transfers_content_in_every_version() {
+        let dir = tempfile::tempdir().unwrap();
+        let cid = Cid::of(b"block");
+        let issuer = Keypair::generate_ed25519();
+        let token =
+            Capability::issue(&issuer, cid, PeerId::random(), Duration::from_secs(60)).unwrap();
+        // 可压缩的内容，跨越多个数据帧
+        let content: Vec<u8> = (0..MAX_FRAME_SIZE * 3 + 7)
+            .map(|i| (i % 13) as u8)
+            .collect();
+        let source = dir.path().join("source");
+        std::fs::write(&source, &content).unwrap();
+
+        for protocol in FileSwapProtocol::ALL {
+            for token in [None, Some(Box::new(token.clone()))] {
+                let mut provider = codec(dir.path());
+                let request = FileRequest::Block {
+                    cid,
+                    token: token.clone(),
+                    peer: Some(PeerId::random()),
+                };
+                let received = send_request(&protocol, &mut provider, request)
+                    .await
+                    .unwrap();
+                assert_eq!(
+                    received,
+                    FileRequest::Block {
+                        cid,
+                        token,
+                        peer: None
+                    }
+                );
+                let expected = match protocol {
+                    FileSwapProtocol::V2 => Compression::None,
+                    FileSwapProtocol::V3 | FileSwapProtocol::V4 => Compression::Zstd,
+                };
+                assert_eq!(provider.compression, expected);
+
+                let response = FileResponse::Content {
+                    path: source.clone(),
+                    peer: None,
+                };
+                match send_response(&protocol, &mut provider, &mut codec(dir.path()), response)
+                    .await
+                    .unwrap()
+                {
+                    FileResponse::Content { path, .. } => {
+                        assert_eq!(std::fs::read(&path).unwrap(), content, "{:?}", protocol);
+                        std::fs::remove_file(path).unwrap();
+                    }
+                    response => panic!("Unexpected response {:?}", response),
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn transfers_catalogs_and_checksums_in_v4() {
+        let dir = tempfile::tempdir().unwrap();
+        let cid = Cid::of(b"file");
+        let catalog = FileRequest::Catalog(CatalogRequest {
+            after: Some("a".to_string()),
+            limit: 10,
+        });
+        let checksums = FileRequest::Checksums { cid, token: None };
+        for request in [catalog, checksums] {
+            let mut provider = codec(dir.path());
+            let received = send_request(&FileSwapProtocol::V4, &mut provider, request.clone())
+                .await
+                .unwrap();
+            assert_eq!(received, request);
+            for protocol in [FileSwapProtocol::V2, FileSwapProtocol::V3] {
+                let error = send_request(&protocol, &mut provider, request.clone())
+                    .await
+                    .unwrap_err();
+                assert_eq!(error.kind(), io::ErrorKind::Unsupported);
+            }
+        }
+
+        let page = CatalogPage {
+            entries: Vec::new(),
+            next: Some("b".to_string()),
+        };
+        for response in [
+            FileResponse::Catalog(page),
+            FileResponse::Checksums(vec![1, u32::MAX]),
+            FileResponse::Forbidden,
+            FileResponse::Busy,
+        ] {
+            let received = send_response(
+                &FileSwapProtocol::V4,
+                &mut codec(dir.path()),
+                &mut codec(dir.path()),
+                response.clone(),
+            )
+            .await
+            .unwrap();
+            assert_eq!(received, response);
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_oversized_content() {
+        let dir = tempfile::tempdir().unwrap();
+        for (protocol, header) in [
+            (FileSwapProtocol::V2, vec![STATUS_CONTENT]),
+            (
+                FileSwapProtocol::V3,
+                vec![STATUS_CONTENT, Compression::None as u8],
+            ),
+        ] {
+            let mut data = header;
+            write_varint(&mut data, MAX_CONTENT_SIZE as usize + 1)
+                .await
+                .unwrap();
+            let mut io = Cursor::new(data);
+            let error = codec(dir.path())
+                .read_response(&protocol, &mut io)
+                .await
+                .unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        }
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn removes_temp_file_of_invalid_content() {
+        let dir = tempfile::tempdir().unwrap();
+
+        // 声明的大小与内容不符
+        let mut data = vec![STATUS_CONTENT];
+        write_varint(&mut data, 10).await.unwrap();
+        write_varint(&mut data, 20).await.unwrap();
+        data.extend_from_slice(&[0; 20]);
+        let mut io = Cursor::new(data);
+        let error = codec(dir.path())
+            .read_response(&FileSwapProtocol::V2, &mut io)
+            .await
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+}
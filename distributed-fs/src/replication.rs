@@ -0,0 +1,341 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+
+use crate::{
+    client::Client,
+    daemon::{load_index, save_index},
+    download::{Downloader, Progress},
+    error::{Error, Result},
+    protocol::{ReplicaRequest, ReplicaResponse},
+    store::{BlockStore, Cid},
+};
+
+// 数据目录下保存本节点为其他节点持有的副本的文件名称
+const REPLICAS_FILE: &str = "replicas";
+// 接受请求的节点在此期间计入副本数量，等待其获取完成并宣称提供
+const PENDING_REPLICA_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+// 请求节点在每次检查副本数量时向已提供的节点再次请求，超过此时间没有再次请求的副本过期删除
+const REPLICA_EXPIRY: Duration = Duration::from_secs(24 * 60 * 60);
+// 手动删除的副本在此期间拒绝再次请求，等待请求节点上的提供者记录过期
+const DROPPED_REPLICA_TIMEOUT: Duration = Duration::from_secs(24 * 60 * 60);
+
+// 本节点为其他节点持有的副本
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Replica {
+    // 请求保存副本的节点
+    pub origin: String,
+    // 文件大小或目录总大小
+    pub size: u64,
+    // 最近一次收到请求的时间（Unix秒）
+    pub requested: u64,
+}
+
+// 处理其他节点的副本请求：获取根清单下的所有内容后在DHT上宣称提供，
+// 提供者记录由事件循环从数据目录恢复并定期重新公布
+#[derive(Clone)]
+pub struct Replicator {
+    client: Client,
+    store: BlockStore,
+    // 副本列表的持久化路径
+    index_path: PathBuf,
+    // 按根清单索引的已持有副本
+    replicas: Arc<Mutex<BTreeMap<Cid, Replica>>>,
+    // 正在获取的副本及其预留的字节数
+    fetching: Arc<Mutex<HashMap<Cid, u64>>>,
+    // 手动删除的副本及删除时间，此后一段时间内拒绝同一副本的请求
+    dropped: Arc<Mutex<HashMap<Cid, Instant>>>,
+    // 为其他节点持有的副本总大小上限，为空时拒绝所有副本请求
+    limit: Option<u64>,
+    // 存储配额，当前占用加上正在获取的副本会超出配额时拒绝请求
+    quota: Option<u64>,
+}
+
+impl Replicator {
+    // 加载数据目录下的副本列表
+    pub fn new(
+        client: Client,
+        store: BlockStore,
+        data_dir: &Path,
+        limit: Option<u64>,
+        quota: Option<u64>,
+    ) -> io::Result<Self> {
+        let index_path = data_dir.join(REPLICAS_FILE);
+        let replicas = load_index(&index_path)?.unwrap_or_default();
+        Ok(Self {
+            client,
+            store,
+            index_path,
+            replicas: Arc::new(Mutex::new(replicas)),
+            fetching: Default::default(),
+            dropped: Default::default(),
+            limit,
+            quota,
+        })
+    }
+
+    // 接受副本请求并在后台获取内容，已持有或正在获取的副本直接接受，已持有的副本刷新请求时间。
+    // 请求中的大小在获取根清单后校验，此前按该大小预留副本上限和配额
+    pub async fn accept(&self, peer: PeerId, request: ReplicaRequest) -> ReplicaResponse {
+        let limit = match self.limit {
+            Some(limit) => limit,
+            None => return ReplicaResponse::Declined,
+        };
+        if self.is_dropped(&request.root) {
+            return ReplicaResponse::Declined;
+        }
+        if let Some(replica) = self.replicas().get_mut(&request.root) {
+            replica.requested = unix_time();
+            return ReplicaResponse::Accepted;
+        }
+        if self.fetching().contains_key(&request.root) {
+            return ReplicaResponse::Accepted;
+        }
+        // 统计占用需要遍历整个存储目录，不阻塞运行时
+        let used = match self.quota {
+            Some(_) => {
+                let store = self.store.clone();
+                match tokio::task::spawn_blocking(move || store.usage()).await {
+                    Ok(Ok(used)) => Some(used),
+                    Ok(Err(e)) => {
+                        warn!("Failed to check storage usage: {}", e);
+                        return ReplicaResponse::Declined;
+                    }
+                    Err(e) => {
+                        warn!("Storage usage task failed: {}", e);
+                        return ReplicaResponse::Declined;
+                    }
+                }
+            }
+            None => None,
+        };
+
+        let held: u64 = self.replicas().values().map(|replica| replica.size).sum();
+        let mut fetching = self.fetching();
+        if fetching.contains_key(&request.root) {
+            return ReplicaResponse::Accepted;
+        }
+        let reserved: u64 = fetching.values().sum();
+        if held.saturating_add(reserved).saturating_add(request.size) > limit {
+            info!(
+                "Declined to replicate {}, replica limit exceeded",
+                request.root
+            );
+            return ReplicaResponse::Declined;
+        }
+        if let (Some(quota), Some(used)) = (self.quota, used) {
+            if used.saturating_add(reserved).saturating_add(request.size) > quota {
+                info!(
+                    "Declined to replicate {}, storage quota exceeded",
+                    request.root
+                );
+                return ReplicaResponse::Declined;
+            }
+        }
+        fetching.insert(request.root, request.size);
+        drop(fetching);
+        info!("Replicating {} for {}", request.root, peer);
+        tokio::spawn(self.clone().replicate(peer, request));
+        ReplicaResponse::Accepted
+    }
+
+    // 已持有的副本，副本内容不被垃圾回收
+    pub fn replicas(&self) -> std::sync::MutexGuard<'_, BTreeMap<Cid, Replica>> {
+        self.replicas.lock().expect("Lock not to be poisoned.")
+    }
+
+    // 删除为其他节点持有的副本，此后一段时间内拒绝同一副本的请求。
+    // 正在获取的副本在获取完成后丢弃，返回是否持有或正在获取该副本
+    pub fn remove(&self, root: &Cid) -> io::Result<bool> {
+        self.dropped().insert(*root, Instant::now());
+        let fetching = self.fetching().contains_key(root);
+        let mut replicas = self.replicas();
+        if replicas.remove(root).is_none() {
+            return Ok(fetching);
+        }
+        save_index(&self.index_path, &*replicas)?;
+        Ok(true)
+    }
+
+    // 删除请求节点超过`REPLICA_EXPIRY`没有再次请求的副本，返回删除的根清单
+    pub fn expire(&self) -> io::Result<Vec<Cid>> {
+        let deadline = unix_time().saturating_sub(REPLICA_EXPIRY.as_secs());
+        let mut replicas = self.replicas();
+        let expired: Vec<Cid> = replicas
+            .iter()
+            .filter(|(_, replica)| replica.requested < deadline)
+            .map(|(root, _)| *root)
+            .collect();
+        for root in &expired {
+            replicas.remove(root);
+        }
+        // 请求时间只在内存中刷新，随过期检查一起保存
+        save_index(&self.index_path, &*replicas)?;
+        Ok(expired)
+    }
+
+    // 正在获取的副本的根清单，获取完成前其内容不被垃圾回收
+    pub fn fetching_roots(&self) -> Vec<Cid> {
+        self.fetching().keys().copied().collect()
+    }
+
+    fn is_dropped(&self, root: &Cid) -> bool {
+        let mut dropped = self.dropped();
+        dropped.retain(|_, since| since.elapsed() < DROPPED_REPLICA_TIMEOUT);
+        dropped.contains_key(root)
+    }
+
+    fn fetching(&self) -> std::sync::MutexGuard<'_, HashMap<Cid, u64>> {
+        self.fetching.lock().expect("Lock not to be poisoned.")
+    }
+
+    fn dropped(&self) -> std::sync::MutexGuard<'_, HashMap<Cid, Instant>> {
+        self.dropped.lock().expect("Lock not to be poisoned.")
+    }
+
+    async fn replicate(mut self, origin: PeerId, request: ReplicaRequest) {
+        let result = self.fetch(origin, &request).await;
+        self.fetching().remove(&request.root);
+        match result {
+            Ok(()) => info!("Replicated {} for {}", request.root, origin),
+            Err(e) => warn!("Failed to replicate {} for {}: {}", request.root, origin, e),
+        }
+    }
+
+    // 从请求节点和其他提供节点获取副本。先获取根清单，大小与请求中预留的一致后
+    // 才获取其余内容，完成后记录并宣称提供
+    async fn fetch(&mut self, origin: PeerId, request: &ReplicaRequest) -> Result<()> {
+        let mut providers = self.client.get_providers(request.root).await?;
+        providers.insert(origin);
+        providers.remove(&self.client.local_peer_id());
+        let (progress, _) = watch::channel(Progress::default());
+        let mut downloader = Downloader::new(
+            self.client.clone(),
+            self.store.clone(),
+            providers,
+            None,
+            progress,
+        );
+        downloader.fetch(&[request.root]).await?;
+        let size = self.store.node(&request.root)?.size();
+        if size != request.size {
+            return Err(Error::InvalidData(format!(
+                "Replica {} has {} bytes, expected {}",
+                request.root, size, request.size
+            )));
+        }
+        downloader.fetch_tree(request.root).await?;
+        if self.is_dropped(&request.root) {
+            return Err(Error::InvalidArgument(format!(
+                "Replica {} was dropped while fetching",
+                request.root
+            )));
+        }
+        self.replicas().insert(
+            request.root,
+            Replica {
+                origin: origin.to_string(),
+                size,
+                requested: unix_time(),
+            },
+        );
+        save_index(&self.index_path, &*self.replicas())?;
+        self.client.start_providing(request.root).await
+    }
+}
+
+// 维持文件的副本数量：定期查询提供节点，副本不足时请求距离根清单最近的节点保存副本
+pub async fn maintain_replicas(
+    mut client: Client,
+    request: ReplicaRequest,
+    wanted: usize,
+    interval: Duration,
+) {
+    // 已接受请求、尚未宣称提供的节点
+    let mut pending = HashMap::new();
+    loop {
+        match ensure_replicas(&mut client, &request, wanted, &mut pending).await {
+            Ok(replicas) => debug!("{} has {} of {} replicas", request.root, replicas, wanted),
+            Err(Error::Shutdown) => return,
+            Err(e) => warn!("Failed to check replicas of {}: {}", request.root, e),
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
+
+// 查询提供节点，副本不足时补充请求，返回本节点之外的提供节点数
+async fn ensure_replicas(
+    client: &mut Client,
+    request: &ReplicaRequest,
+    wanted: usize,
+    pending: &mut HashMap<PeerId, Instant>,
+) -> Result<usize> {
+    let root = request.root;
+    let local = client.local_peer_id();
+    let mut providers = client.get_providers(root).await?;
+    providers.remove(&local);
+    // 向已提供的节点再次请求，持有副本的节点据此刷新副本的过期时间
+    for peer in &providers {
+        if let Err(e) = client.request_replica(*peer, request.clone()).await {
+            debug!(
+                "Failed to refresh the replica of {} on {}: {}",
+                root, peer, e
+            );
+        }
+    }
+    pending.retain(|peer, since| {
+        !providers.contains(peer) && since.elapsed() < PENDING_REPLICA_TIMEOUT
+    });
+    let mut replicas = providers.len() + pending.len();
+    if replicas >= wanted {
+        return Ok(providers.len());
+    }
+
+    info!(
+        "{} has {} of {} replicas, requesting more",
+        root, replicas, wanted
+    );
+    for peer in client.get_closest_peers(root).await? {
+        if replicas >= wanted {
+            break;
+        }
+        if peer == local || providers.contains(&peer) || pending.contains_key(&peer) {
+            continue;
+        }
+        match client.request_replica(peer, request.clone()).await {
+            Ok(ReplicaResponse::Accepted) => {
+                info!("{} accepted to replicate {}", peer, root);
+                pending.insert(peer, Instant::now());
+                replicas += 1;
+            }
+            Ok(ReplicaResponse::Declined) => debug!("{} declined to replicate {}", peer, root),
+            Err(e) => debug!(
+                "Failed to request a replica of {} from {}: {}",
+                root, peer, e
+            ),
+        }
+    }
+    if replicas < wanted {
+        warn!(
+            "Only {} of {} replicas of {} are available",
+            replicas, wanted, root
+        );
+    }
+    Ok(providers.len())
+}
+
+// 当前时间（Unix秒）
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
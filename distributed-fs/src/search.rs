@@ -0,0 +1,180 @@
+use std::collections::BTreeSet;
+
+use libp2p::{
+    identity::{Keypair, PublicKey},
+    PeerId,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{Error, Result},
+    store::Cid,
+};
+
+// DHT中关键词索引记录键的前缀
+const KEYWORD_KEY_PREFIX: &str = "/dfs/keyword/";
+// 关键词的最小和最大字符数
+const MIN_KEYWORD_LEN: usize = 2;
+const MAX_KEYWORD_LEN: usize = 64;
+// 每个文件最多发布的关键词数
+pub const MAX_KEYWORDS: usize = 32;
+// 每条索引记录最多的条目数，限制记录大小
+pub const MAX_ENTRIES: usize = 256;
+
+// 索引记录中的文件
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IndexEntry {
+    // 发布者命名空间下的名称路径
+    pub path: String,
+    // 根清单的内容标识
+    pub root: Cid,
+    // 文件大小或目录总大小
+    pub size: u64,
+    // 是否为目录
+    pub is_dir: bool,
+}
+
+// 搜索结果
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchResult {
+    // 名称，形如`<peer-id>/<path>`
+    pub name: String,
+    // 根清单的内容标识
+    pub root: Cid,
+    // 文件大小或目录总大小
+    pub size: u64,
+    // 是否为目录
+    pub is_dir: bool,
+    // DHT上提供根清单的节点数
+    pub providers: usize,
+}
+
+// 从名称路径和标签中提取关键词：按字母和数字之外的字符切分并转为小写
+pub fn keywords<'a>(texts: impl IntoIterator<Item = &'a str>) -> BTreeSet<String> {
+    texts
+        .into_iter()
+        .flat_map(|text| text.split(|c: char| !c.is_alphanumeric()))
+        .map(|word| word.to_lowercase())
+        .filter(|word| (MIN_KEYWORD_LEN..=MAX_KEYWORD_LEN).contains(&word.chars().count()))
+        .take(MAX_KEYWORDS)
+        .collect()
+}
+
+// 文件的关键词，取自名称路径和标签
+pub fn file_keywords(path: &str, tags: &[String]) -> BTreeSet<String> {
+    keywords(std::iter::once(path).chain(tags.iter().map(String::as_str)))
+}
+
+// 发布者在DHT上宣称提供关键词的内容标识，搜索时据此找到发布了该关键词的节点
+pub fn keyword_cid(keyword: &str) -> Cid {
+    Cid::of(format!("{}{}", KEYWORD_KEY_PREFIX, keyword).as_bytes())
+}
+
+// 发布者的关键词索引记录在DHT中的键，形如`/dfs/keyword/<keyword>/<peer-id>`
+pub fn keyword_key(keyword: &str, publisher: &PeerId) -> Vec<u8> {
+    format!("{}{}/{}", KEYWORD_KEY_PREFIX, keyword, publisher).into_bytes()
+}
+
+// 从DHT记录的键解析关键词和发布者，不是索引记录时返回None
+pub fn parse_keyword_key(key: &[u8]) -> Option<(String, PeerId)> {
+    let (keyword, publisher) = std::str::from_utf8(key)
+        .ok()?
+        .strip_prefix(KEYWORD_KEY_PREFIX)?
+        .split_once('/')?;
+    Some((keyword.to_string(), publisher.parse().ok()?))
+}
+
+// 发布者签名的关键词索引记录，列出发布者名下包含该关键词的所有文件，序号越大版本越新
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeywordRecord {
+    // 发布者公钥的protobuf编码
+    pub public_key: Vec<u8>,
+    // 版本序号
+    pub sequence: u64,
+    // 包含关键词的文件
+    pub entries: Vec<IndexEntry>,
+    // 发布者对记录键、版本序号和文件列表的签名
+    pub signature: Vec<u8>,
+}
+
+impl KeywordRecord {
+    // 用发布者私钥签名索引记录
+    pub fn sign(
+        keypair: &Keypair,
+        keyword: &str,
+        sequence: u64,
+        entries: Vec<IndexEntry>,
+    ) -> Result<Self> {
+        let key = keyword_key(keyword, &keypair.public().to_peer_id());
+        let signature = keypair
+            .sign(&signing_bytes(&key, sequence, &entries))
+            .map_err(|e| Error::InvalidData(format!("Failed to sign keyword record: {}", e)))?;
+        Ok(Self {
+            public_key: keypair.public().to_protobuf_encoding(),
+            sequence,
+            entries,
+            signature,
+        })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        bincode::serialize(self).expect("Keyword record to be serializable.")
+    }
+
+    // 解析并校验索引记录：公钥属于发布者，签名有效，且条目数不超过上限
+    pub fn decode_verified(keyword: &str, publisher: &PeerId, data: &[u8]) -> Result<Self> {
+        let record: KeywordRecord = bincode::deserialize(data)
+            .map_err(|e| Error::InvalidData(format!("Invalid keyword record: {}", e)))?;
+        let public_key = PublicKey::from_protobuf_encoding(&record.public_key)
+            .map_err(|e| Error::InvalidData(format!("Invalid publisher key: {}", e)))?;
+        if public_key.to_peer_id() != *publisher {
+            return Err(Error::InvalidData(format!(
+                "Keyword record of {} is not signed by {}",
+                keyword, publisher
+            )));
+        }
+        if record.entries.len() > MAX_ENTRIES {
+            return Err(Error::InvalidData(format!(
+                "Keyword record of {} has {} entries",
+                keyword,
+                record.entries.len()
+            )));
+        }
+        let key = keyword_key(keyword, publisher);
+        if !public_key.verify(
+            &signing_bytes(&key, record.sequence, &record.entries),
+            &record.signature,
+        ) {
+            return Err(Error::InvalidData(format!(
+                "Invalid signature on keyword record of {} by {}",
+                keyword, publisher
+            )));
+        }
+        Ok(record)
+    }
+
+    // 从多个节点返回的记录中选出签名有效且序号最大的记录
+    pub fn latest<'a>(
+        keyword: &str,
+        publisher: &PeerId,
+        values: impl IntoIterator<Item = &'a [u8]>,
+    ) -> Option<Self> {
+        values
+            .into_iter()
+            .filter_map(
+                |data| match KeywordRecord::decode_verified(keyword, publisher, data) {
+                    Ok(record) => Some(record),
+                    Err(e) => {
+                        warn!("Ignoring keyword record: {}", e);
+                        None
+                    }
+                },
+            )
+            .max_by_key(|record| record.sequence)
+    }
+}
+
+// 签名的内容：记录键、版本序号和文件列表
+fn signing_bytes(key: &[u8], sequence: u64, entries: &[IndexEntry]) -> Vec<u8> {
+    bincode::serialize(&(key, sequence, entries)).expect("Keyword record to be serializable.")
+}
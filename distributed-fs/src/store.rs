@@ -0,0 +1,757 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    fs::{self, File},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+// 文件分块大小
+pub const CHUNK_SIZE: usize = 256 * 1024;
+// 清单节点编码后的最大字节数，足以容纳128 GiB文件的清单。请求方据此限制接收的内容大小
+pub const MAX_NODE_SIZE: usize = 16 * 1024 * 1024;
+// 最近使用过的数据块不被回收，避免删除正在下载或读取的内容
+const GC_GRACE_PERIOD: Duration = Duration::from_secs(60);
+
+// 为存入数据块的临时文件生成唯一序号
+static PUT_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+// 内容标识，即数据的SHA-256哈希
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Cid(pub [u8; 32]);
+
+impl Cid {
+    // 计算数据的内容标识
+    pub fn of(data: &[u8]) -> Self {
+        Cid(Sha256::digest(data).into())
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        bytes.try_into().ok().map(Cid)
+    }
+}
+
+impl fmt::Display for Cid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&hex::encode(self.0))
+    }
+}
+
+impl fmt::Debug for Cid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Cid({})", self)
+    }
+}
+
+impl FromStr for Cid {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = hex::decode(s).map_err(|e| e.to_string())?;
+        Cid::from_bytes(&bytes).ok_or_else(|| "Expect a 32 bytes hex hash.".to_string())
+    }
+}
+
+// 文件清单，作为Merkle DAG的根节点，按顺序记录所有数据块的内容标识
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    // 文件名称
+    pub name: String,
+    // 文件大小
+    pub size: u64,
+    // 整个文件内容的SHA-256哈希
+    pub hash: Cid,
+    // 文件权限
+    pub mode: u32,
+    // 数据块的内容标识
+    pub blocks: Vec<Cid>,
+    // 内容是否为加密给接收者的格式，获取后由接收者解密
+    pub encrypted: bool,
+}
+
+impl Manifest {
+    // 第`index`个数据块的大小，只有最后一个数据块可能小于分块大小
+    pub fn block_size(&self, index: usize) -> u64 {
+        let offset = (index * CHUNK_SIZE) as u64;
+        self.size.saturating_sub(offset).min(CHUNK_SIZE as u64)
+    }
+}
+
+// 目录清单，记录目录下每个文件和子目录清单的内容标识
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Directory {
+    // 目录名称
+    pub name: String,
+    // 目录下所有文件的总大小
+    pub size: u64,
+    // 目录权限
+    pub mode: u32,
+    // 按名称排序的目录项
+    pub entries: Vec<DirEntry>,
+}
+
+// 目录项
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DirEntry {
+    // 文件或子目录名称
+    pub name: String,
+    // 文件或子目录清单的内容标识
+    pub cid: Cid,
+    // 文件大小或子目录总大小
+    pub size: u64,
+    // 是否为子目录
+    pub is_dir: bool,
+}
+
+impl DirEntry {
+    pub fn is_valid_name(&self) -> bool {
+        is_valid_name(&self.name)
+    }
+}
+
+// 校验名称只包含单个路径组件，避免写出目标目录之外。名称来自远端提供的清单，不可信任
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name != "." && name != ".." && !name.contains(['/', '\\'])
+}
+
+// Merkle DAG的节点：文件清单或目录清单
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Node {
+    File(Manifest),
+    Directory(Directory),
+}
+
+impl Node {
+    pub fn encode(&self) -> Vec<u8> {
+        bincode::serialize(self).expect("Node to be serializable.")
+    }
+
+    pub fn decode(data: &[u8]) -> io::Result<Self> {
+        bincode::deserialize(data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            Node::File(manifest) => &manifest.name,
+            Node::Directory(directory) => &directory.name,
+        }
+    }
+
+    pub fn size(&self) -> u64 {
+        match self {
+            Node::File(manifest) => manifest.size,
+            Node::Directory(directory) => directory.size,
+        }
+    }
+}
+
+// DHT中文件名称对应的记录，提供节点公布文件的根清单、内容哈希和大小
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileRecord {
+    // 根清单的内容标识
+    pub root: Cid,
+    // 整个文件内容的SHA-256哈希，目录为目录清单的内容标识
+    pub hash: Cid,
+    // 文件大小或目录总大小
+    pub size: u64,
+}
+
+impl FileRecord {
+    // 校验根清单与记录一致
+    pub fn matches(&self, node: &Node) -> bool {
+        let hash = match node {
+            Node::File(manifest) => manifest.hash,
+            Node::Directory(_) => self.root,
+        };
+        self.hash == hash && self.size == node.size()
+    }
+}
+
+// 垃圾回收结果
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct GcStats {
+    // 删除的数据块数
+    pub removed: usize,
+    // 释放的字节数
+    pub freed: u64,
+    // 回收后占用的字节数
+    pub used: u64,
+}
+
+// 本地内容寻址的数据块存储
+#[derive(Debug, Clone)]
+pub struct BlockStore {
+    // 数据块目录
+    blocks_dir: PathBuf,
+    // 临时文件目录
+    tmp_dir: PathBuf,
+    // 存储版本，存入或删除数据块时递增，用于判断缓存的数据块状态是否过期
+    generation: Arc<AtomicU64>,
+    // 正在获取的根清单及其获取者数量
+    active: Arc<Mutex<HashMap<Cid, usize>>>,
+}
+
+// 正在获取的根清单，释放前其下已存入的内容不被垃圾回收
+#[derive(Debug)]
+pub struct ActiveRoot {
+    root: Cid,
+    active: Arc<Mutex<HashMap<Cid, usize>>>,
+}
+
+impl Drop for ActiveRoot {
+    fn drop(&mut self) {
+        let mut active = self.active.lock().expect("Lock not to be poisoned.");
+        if let Some(count) = active.get_mut(&self.root) {
+            *count -= 1;
+            if *count == 0 {
+                active.remove(&self.root);
+            }
+        }
+    }
+}
+
+impl BlockStore {
+    // 在数据目录下打开数据块存储
+    pub fn open(data_dir: &Path) -> io::Result<Self> {
+        let blocks_dir = data_dir.join("blocks");
+        let tmp_dir = data_dir.join("tmp");
+        fs::create_dir_all(&blocks_dir)?;
+        fs::create_dir_all(&tmp_dir)?;
+        Ok(Self {
+            blocks_dir,
+            tmp_dir,
+            generation: Default::default(),
+            active: Default::default(),
+        })
+    }
+
+    // 接收中的临时文件目录，与数据块目录位于同一文件系统
+    pub fn tmp_dir(&self) -> &Path {
+        &self.tmp_dir
+    }
+
+    // 数据块的存储路径
+    pub fn block_path(&self, cid: &Cid) -> PathBuf {
+        let hex = cid.to_string();
+        self.blocks_dir.join(&hex[..2]).join(hex)
+    }
+
+    // 标记根清单正在获取，返回的标记释放前垃圾回收保留其下已存入的内容
+    pub fn hold(&self, root: Cid) -> ActiveRoot {
+        *self
+            .active
+            .lock()
+            .expect("Lock not to be poisoned.")
+            .entry(root)
+            .or_default() += 1;
+        ActiveRoot {
+            root,
+            active: self.active.clone(),
+        }
+    }
+
+    // 正在获取的根清单
+    pub fn active_roots(&self) -> Vec<Cid> {
+        self.active
+            .lock()
+            .expect("Lock not to be poisoned.")
+            .keys()
+            .copied()
+            .collect()
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Acquire)
+    }
+
+    pub fn has(&self, cid: &Cid) -> bool {
+        self.block_path(cid).is_file()
+    }
+
+    // 读取数据块并校验内容，写入中断等原因损坏的数据块被删除，调用方可以重新获取
+    pub fn get(&self, cid: &Cid) -> io::Result<Vec<u8>> {
+        let data = fs::read(self.block_path(cid))?;
+        if Cid::of(&data) != *cid {
+            self.remove_corrupted(cid);
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Block {} is corrupted", cid),
+            ));
+        }
+        self.touch(cid);
+        Ok(data)
+    }
+
+    // 更新数据块的最近使用时间，垃圾回收时先淘汰最久未使用的数据块
+    pub fn touch(&self, cid: &Cid) {
+        if let Err(e) =
+            File::open(self.block_path(cid)).and_then(|f| f.set_modified(SystemTime::now()))
+        {
+            debug!("Failed to update last use of {}: {}", cid, e);
+        }
+    }
+
+    // 存储数据块，返回其内容标识，相同内容只存储一份
+    // 已有的数据块读取失败或内容不符时重新写入
+    pub fn put(&self, data: &[u8]) -> io::Result<Cid> {
+        let cid = Cid::of(data);
+        if self.has(&cid) && self.get(&cid).is_ok() {
+            return Ok(cid);
+        }
+        // 并发存入相同内容时各自使用不同的临时文件，重命名是原子的
+        let seq = PUT_COUNTER.fetch_add(1, Ordering::Relaxed);
+        let tmp = self
+            .tmp_dir
+            .join(format!("{}-{}-{}.put", cid, std::process::id(), seq));
+        let result = File::create(&tmp)
+            .and_then(|mut file| {
+                file.write_all(data)?;
+                file.sync_all()
+            })
+            .and_then(|()| self.move_into_place(&tmp, &self.block_path(&cid)));
+        if result.is_err() {
+            let _ = fs::remove_file(&tmp);
+        }
+        result.map(|()| cid)
+    }
+
+    // 将已校验内容的临时文件写入磁盘后移入存储
+    pub fn insert_block(&self, tmp: &Path, cid: &Cid) -> io::Result<()> {
+        File::open(tmp)?.sync_all()?;
+        self.move_into_place(tmp, &self.block_path(cid))
+    }
+
+    fn remove_corrupted(&self, cid: &Cid) {
+        warn!("Removing corrupted block {}", cid);
+        match fs::remove_file(self.block_path(cid)) {
+            Ok(()) => {
+                self.generation.fetch_add(1, Ordering::AcqRel);
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => warn!("Failed to remove corrupted block {}: {}", cid, e),
+        }
+    }
+
+    fn move_into_place(&self, tmp: &Path, path: &Path) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::rename(tmp, path)?;
+        self.generation.fetch_add(1, Ordering::AcqRel);
+        Ok(())
+    }
+
+    // 将文件或目录存入存储，返回根清单的内容标识
+    pub fn import(&self, path: &Path, name: String) -> io::Result<(Cid, Node)> {
+        if path.is_dir() {
+            let (cid, directory) = self.import_dir(path, name)?;
+            Ok((cid, Node::Directory(directory)))
+        } else {
+            let (cid, manifest) = self.import_file(path, name, false)?;
+            Ok((cid, Node::File(manifest)))
+        }
+    }
+
+    // 递归地将目录存入存储，返回目录清单的内容标识。符号链接等特殊文件被忽略
+    pub fn import_dir(&self, path: &Path, name: String) -> io::Result<(Cid, Directory)> {
+        let mut directory = Directory {
+            name,
+            size: 0,
+            mode: mode_of(&fs::metadata(path)?),
+            entries: Vec::new(),
+        };
+        let mut children = fs::read_dir(path)?.collect::<io::Result<Vec<_>>>()?;
+        children.sort_by_key(|e| e.file_name());
+        for child in children {
+            let name = match child.file_name().into_string() {
+                Ok(name) => name,
+                Err(name) => {
+                    warn!("Skipping non UTF-8 file name {:?}", name);
+                    continue;
+                }
+            };
+            let file_type = child.file_type()?;
+            let entry = if file_type.is_dir() {
+                let (cid, sub) = self.import_dir(&child.path(), name.clone())?;
+                DirEntry {
+                    name,
+                    cid,
+                    size: sub.size,
+                    is_dir: true,
+                }
+            } else if file_type.is_file() {
+                let (cid, manifest) = self.import_file(&child.path(), name.clone(), false)?;
+                DirEntry {
+                    name,
+                    cid,
+                    size: manifest.size,
+                    is_dir: false,
+                }
+            } else {
+                debug!("Skipping special file {}", child.path().display());
+                continue;
+            };
+            directory.size += entry.size;
+            directory.entries.push(entry);
+        }
+        let cid = self.put_node(&Node::Directory(directory.clone()))?;
+        Ok((cid, directory))
+    }
+
+    // 将文件分块存入存储，返回根清单的内容标识
+    pub fn import_file(
+        &self,
+        path: &Path,
+        name: String,
+        encrypted: bool,
+    ) -> io::Result<(Cid, Manifest)> {
+        let mut file = File::open(path)?;
+        let mut manifest = Manifest {
+            name,
+            size: 0,
+            hash: Cid([0; 32]),
+            mode: mode_of(&file.metadata()?),
+            blocks: Vec::new(),
+            encrypted,
+        };
+        let mut hasher = Sha256::new();
+        let mut buf = vec![0u8; CHUNK_SIZE];
+        loop {
+            let n = read_chunk(&mut file, &mut buf)?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buf[..n]);
+            manifest.blocks.push(self.put(&buf[..n])?);
+            manifest.size += n as u64;
+        }
+        manifest.hash = Cid(hasher.finalize().into());
+        let root = self.put_node(&Node::File(manifest.clone()))?;
+        Ok((root, manifest))
+    }
+
+    // 存入清单节点，超过`MAX_NODE_SIZE`的清单其他节点无法获取，返回错误
+    fn put_node(&self, node: &Node) -> io::Result<Cid> {
+        let data = node.encode();
+        if data.len() > MAX_NODE_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Manifest of {} is {} bytes, larger than {} bytes",
+                    node.name(),
+                    data.len(),
+                    MAX_NODE_SIZE
+                ),
+            ));
+        }
+        self.put(&data)
+    }
+
+    // 读取并解析清单节点
+    pub fn node(&self, cid: &Cid) -> io::Result<Node> {
+        Node::decode(&self.get(cid)?)
+    }
+
+    // 根清单下的所有清单节点和数据块是否都在本地
+    pub fn is_complete(&self, root: &Cid) -> bool {
+        self.cids(root)
+            .map(|cids| cids.iter().all(|cid| self.has(cid)))
+            .unwrap_or(false)
+    }
+
+    // 所有数据块的内容标识、大小和最近使用时间
+    fn blocks(&self) -> io::Result<Vec<(Cid, u64, SystemTime)>> {
+        let mut blocks = Vec::new();
+        for dir in fs::read_dir(&self.blocks_dir)? {
+            let dir = dir?;
+            if !dir.file_type()?.is_dir() {
+                continue;
+            }
+            for entry in fs::read_dir(dir.path())? {
+                let entry = entry?;
+                let cid = match entry.file_name().to_str().map(Cid::from_str) {
+                    Some(Ok(cid)) => cid,
+                    _ => continue,
+                };
+                let metadata = entry.metadata()?;
+                blocks.push((cid, metadata.len(), metadata.modified()?));
+            }
+        }
+        Ok(blocks)
+    }
+
+    // 数据块占用的字节数
+    pub fn usage(&self) -> io::Result<u64> {
+        Ok(self.blocks()?.iter().map(|(_, size, _)| size).sum())
+    }
+
+    // 按最近使用时间淘汰`protected`之外的数据块，直到占用的字节数不超过`quota`
+    pub fn collect_garbage(&self, protected: &HashSet<Cid>, quota: u64) -> io::Result<GcStats> {
+        let mut blocks = self.blocks()?;
+        let mut stats = GcStats {
+            used: blocks.iter().map(|(_, size, _)| size).sum(),
+            ..Default::default()
+        };
+        blocks.sort_by_key(|(_, _, used)| *used);
+        let now = SystemTime::now();
+        for (cid, size, used) in blocks {
+            if stats.used <= quota {
+                break;
+            }
+            let recent = now
+                .duration_since(used)
+                .map_or(true, |elapsed| elapsed < GC_GRACE_PERIOD);
+            if protected.contains(&cid) || recent {
+                continue;
+            }
+            match fs::remove_file(self.block_path(&cid)) {
+                Ok(()) => {
+                    self.generation.fetch_add(1, Ordering::AcqRel);
+                    stats.removed += 1;
+                    stats.freed += size;
+                    stats.used -= size;
+                }
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(stats)
+    }
+
+    // 根清单下本地已有的清单节点引用的内容标识，包括根清单本身。
+    // 跳过缺失的清单节点，用于在获取完成前保护已存入的内容
+    pub fn local_cids(&self, root: &Cid) -> HashSet<Cid> {
+        let mut cids = HashSet::new();
+        let mut stack = vec![*root];
+        while let Some(cid) = stack.pop() {
+            if !cids.insert(cid) || !self.has(&cid) {
+                continue;
+            }
+            match self.node(&cid) {
+                Ok(Node::File(manifest)) => cids.extend(manifest.blocks),
+                Ok(Node::Directory(directory)) => {
+                    stack.extend(directory.entries.iter().map(|e| e.cid))
+                }
+                Err(e) => debug!("Failed to read node {}: {}", cid, e),
+            }
+        }
+        cids
+    }
+
+    // 根清单下所有清单节点和数据块的内容标识，包括根清单本身
+    pub fn cids(&self, root: &Cid) -> io::Result<HashSet<Cid>> {
+        let mut cids = HashSet::new();
+        let mut stack = vec![*root];
+        while let Some(cid) = stack.pop() {
+            if !cids.insert(cid) {
+                continue;
+            }
+            match self.node(&cid)? {
+                Node::File(manifest) => cids.extend(manifest.blocks),
+                Node::Directory(directory) => stack.extend(directory.entries.iter().map(|e| e.cid)),
+            }
+        }
+        Ok(cids)
+    }
+}
+
+// 文件权限位
+#[cfg(unix)]
+pub fn mode_of(metadata: &fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o7777
+}
+
+#[cfg(not(unix))]
+pub fn mode_of(metadata: &fs::Metadata) -> u32 {
+    if metadata.permissions().readonly() {
+        0o444
+    } else {
+        0o644
+    }
+}
+
+// 设置文件权限位
+#[cfg(unix)]
+pub fn set_mode(path: &Path, mode: u32) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+}
+
+#[cfg(not(unix))]
+pub fn set_mode(path: &Path, mode: u32) -> io::Result<()> {
+    let mut permissions = fs::metadata(path)?.permissions();
+    permissions.set_readonly(mode & 0o222 == 0);
+    fs::set_permissions(path, permissions)
+}
+
+// 计算文件内容的SHA-256哈希
+pub fn hash_file(path: &Path) -> io::Result<Cid> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    loop {
+        match read_chunk(&mut file, &mut buf)? {
+            0 => break,
+            n => hasher.update(&buf[..n]),
+        }
+    }
+    Ok(Cid(hasher.finalize().into()))
+}
+
+// 尽量读满一个分块
+fn read_chunk(file: &mut File, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match file.read(&mut buf[filled..])? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+    Ok(filled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{import_bytes, temp_store};
+
+    #[test]
+    fn rejects_names_outside_target() {
+        assert!(is_valid_name("report.pdf"));
+        assert!(is_valid_name("..hidden"));
+        for name in ["", ".", "..", "../../.bashrc", "/etc/passwd", "a/b", "a\\b"] {
+            assert!(!is_valid_name(name), "{:?} should be rejected", name);
+        }
+    }
+
+    #[test]
+    fn parses_displayed_cids() {
+        let cid = Cid::of(b"hello");
+        assert_eq!(cid.to_string().parse::<Cid>().unwrap(), cid);
+        assert_eq!(Cid::from_bytes(cid.as_bytes()), Some(cid));
+        assert!("abc".parse::<Cid>().is_err());
+        assert!("zz".repeat(32).parse::<Cid>().is_err());
+        assert_eq!(Cid::from_bytes(&[0; 31]), None);
+    }
+
+    #[test]
+    fn imports_and_exports_files() {
+        let (dir, store) = temp_store();
+        let content: Vec<u8> = (0..CHUNK_SIZE * 2 + 100).map(|i| i as u8).collect();
+        let (root, manifest) = import_bytes(&store, dir.path(), "big.bin", &content);
+        assert_eq!(manifest.size, content.len() as u64);
+        assert_eq!(manifest.hash, Cid::of(&content));
+        assert_eq!(manifest.blocks.len(), 3);
+        match store.node(&root).unwrap() {
+            Node::File(stored) => assert_eq!(stored.blocks, manifest.blocks),
+            Node::Directory(_) => panic!("Expect a file manifest."),
+        }
+
+        let joined: Vec<u8> = manifest
+            .blocks
+            .iter()
+            .flat_map(|cid| store.get(cid).unwrap())
+            .collect();
+        assert_eq!(joined, content);
+
+        // 内容相同时根清单相同
+        let (again, _) = import_bytes(&store, dir.path(), "big.bin", &content);
+        assert_eq!(again, root);
+    }
+
+    #[test]
+    fn rewrites_corrupted_blocks() {
+        let (_dir, store) = temp_store();
+        let data = vec![7u8; 1000];
+        let cid = store.put(&data).unwrap();
+        fs::write(store.block_path(&cid), &data[..10]).unwrap();
+        assert_eq!(
+            store.get(&cid).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+        assert!(!store.has(&cid));
+
+        fs::write(store.block_path(&cid), &data[..10]).unwrap();
+        assert_eq!(store.put(&data).unwrap(), cid);
+        assert_eq!(store.get(&cid).unwrap(), data);
+    }
+
+    #[test]
+    fn puts_same_block_concurrently() {
+        let (_dir, store) = temp_store();
+        let data = vec![3u8; CHUNK_SIZE];
+        std::thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| store.put(&data).unwrap());
+            }
+        });
+        assert_eq!(store.get(&Cid::of(&data)).unwrap(), data);
+        assert_eq!(fs::read_dir(store.tmp_dir()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn walks_incomplete_trees_of_active_roots() {
+        let (dir, store) = temp_store();
+        let src = dir.path().join("src");
+        fs::create_dir_all(src.join("sub")).unwrap();
+        fs::write(src.join("b.txt"), b"bbb").unwrap();
+        fs::write(src.join("sub").join("a.txt"), b"aa").unwrap();
+        let (root, _) = store.import(&src, "src".to_string()).unwrap();
+        let all = store.cids(&root).unwrap();
+
+        // 子目录清单尚未获取时只遍历已有的部分
+        let sub = match store.node(&root).unwrap() {
+            Node::Directory(directory) => directory.entries[1].cid,
+            Node::File(_) => panic!("Expect a directory manifest."),
+        };
+        fs::remove_file(store.block_path(&sub)).unwrap();
+        assert!(store.cids(&root).is_err());
+        let local = store.local_cids(&root);
+        assert!(local.contains(&root) && local.contains(&sub));
+        assert_eq!(local.len(), all.len() - 2);
+
+        let first = store.hold(root);
+        let second = store.hold(root);
+        drop(first);
+        assert_eq!(store.active_roots(), vec![root]);
+        drop(second);
+        assert!(store.active_roots().is_empty());
+    }
+
+    #[test]
+    fn imports_directories() {
+        let (dir, store) = temp_store();
+        let src = dir.path().join("src");
+        fs::create_dir_all(src.join("sub")).unwrap();
+        fs::write(src.join("b.txt"), b"bbb").unwrap();
+        fs::write(src.join("sub").join("a.txt"), b"aa").unwrap();
+
+        let (root, _) = store.import(&src, "src".to_string()).unwrap();
+        let directory = match store.node(&root).unwrap() {
+            Node::Directory(directory) => directory,
+            Node::File(_) => panic!("Expect a directory manifest."),
+        };
+        let names: Vec<&str> = directory.entries.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, ["b.txt", "sub"]);
+        assert_eq!(directory.size, 5);
+        assert!(directory.entries[1].is_dir);
+        match store.node(&directory.entries[1].cid).unwrap() {
+            Node::Directory(sub) => assert_eq!(sub.entries[0].name, "a.txt"),
+            Node::File(_) => panic!("Expect a directory manifest."),
+        }
+    }
+}
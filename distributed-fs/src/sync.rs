@@ -0,0 +1,389 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fs::{self, File},
+    io::{self, BufReader, Read},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    download::Downloader,
+    error::{Error, Result},
+    store::{hash_file, set_mode, BlockStore, Cid, Directory, Manifest, Node, CHUNK_SIZE},
+};
+
+// 同步结果
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SyncStats {
+    // 内容有变化、重新生成的文件数
+    pub updated: usize,
+    // 内容相同的文件数
+    pub unchanged: usize,
+    // 删除的本地文件和目录数
+    pub removed: usize,
+    // 从本地旧文件中复用的字节数
+    pub reused: u64,
+    // 需要从网络获取的字节数
+    pub fetched: u64,
+}
+
+// rsync的弱校验和，窗口滑动一个字节时以常数时间更新
+#[derive(Debug, Default)]
+struct Rolling {
+    a: u32,
+    b: u32,
+    len: u32,
+}
+
+impl Rolling {
+    // 在窗口末尾加入一个字节
+    fn push(&mut self, byte: u8) {
+        self.a = self.a.wrapping_add(byte as u32);
+        self.b = self.b.wrapping_add(self.a);
+        self.len += 1;
+    }
+
+    // 移出窗口开头的字节，在末尾加入一个字节
+    fn roll(&mut self, out: u8, byte: u8) {
+        self.a = self.a.wrapping_sub(out as u32).wrapping_add(byte as u32);
+        self.b = self
+            .b
+            .wrapping_sub(self.len.wrapping_mul(out as u32))
+            .wrapping_add(self.a);
+    }
+
+    fn digest(&self) -> u32 {
+        (self.a & 0xffff) | (self.b << 16)
+    }
+}
+
+// 数据的弱校验和
+fn weak_checksum(data: &[u8]) -> u32 {
+    let mut rolling = Rolling::default();
+    data.iter().for_each(|byte| rolling.push(*byte));
+    rolling.digest()
+}
+
+// 文件清单下各数据块的弱校验和，提供节点据此回复校验和请求
+pub fn block_checksums(store: &BlockStore, cid: &Cid) -> io::Result<Vec<u32>> {
+    match store.node(cid)? {
+        Node::File(manifest) => manifest
+            .blocks
+            .iter()
+            .map(|cid| Ok(weak_checksum(&store.get(cid)?)))
+            .collect(),
+        Node::Directory(_) => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} is a directory", cid),
+        )),
+    }
+}
+
+// 用滑动窗口扫描本地旧文件，弱校验和与强校验和都与清单中缺失的数据块一致的窗口存入本地存储，
+// 之后下载时不再从网络获取。返回复用的字节数
+fn seed_blocks(
+    store: &BlockStore,
+    basis: &Path,
+    manifest: &Manifest,
+    checksums: &[u32],
+) -> io::Result<u64> {
+    // 按弱校验和索引本地缺失的完整数据块，最后一个不足分块大小的数据块只与文件末尾比较
+    let mut wanted: HashMap<u32, Vec<Cid>> = HashMap::new();
+    let mut tail = None;
+    for (index, (cid, checksum)) in manifest.blocks.iter().zip(checksums).enumerate() {
+        if store.has(cid) {
+            continue;
+        }
+        match manifest.block_size(index) as usize {
+            CHUNK_SIZE => wanted.entry(*checksum).or_default().push(*cid),
+            size => tail = Some((*cid, size)),
+        }
+    }
+    if wanted.is_empty() && tail.is_none() {
+        return Ok(0);
+    }
+
+    let mut reused = 0;
+    let mut window = VecDeque::with_capacity(CHUNK_SIZE);
+    let mut rolling = Rolling::default();
+    for byte in BufReader::with_capacity(CHUNK_SIZE, File::open(basis)?).bytes() {
+        let byte = byte?;
+        if window.len() == CHUNK_SIZE {
+            let out = window.pop_front().expect("Window to be full.");
+            rolling.roll(out, byte);
+        } else {
+            rolling.push(byte);
+        }
+        window.push_back(byte);
+        if window.len() < CHUNK_SIZE {
+            continue;
+        }
+        let digest = rolling.digest();
+        let candidates = match wanted.get_mut(&digest) {
+            Some(candidates) => candidates,
+            None => continue,
+        };
+        let cid = Cid::of(window.make_contiguous());
+        if !candidates.contains(&cid) {
+            continue;
+        }
+        store.put(window.make_contiguous())?;
+        reused += CHUNK_SIZE as u64;
+        candidates.retain(|c| *c != cid);
+        if candidates.is_empty() {
+            wanted.remove(&digest);
+        }
+        // 匹配后从窗口之后重新开始
+        window.clear();
+        rolling = Rolling::default();
+    }
+    if let Some((cid, size)) = tail.filter(|(_, size)| window.len() >= *size) {
+        let data: Vec<u8> = window.iter().skip(window.len() - size).copied().collect();
+        if Cid::of(&data) == cid {
+            store.put(&data)?;
+            reused += size as u64;
+        }
+    }
+    Ok(reused)
+}
+
+// 将本地路径同步为根清单对应的文件或目录树：内容相同的文件保持不变，有变化的文件只获取
+// 本地旧文件中没有的数据块。`delete`为真时删除目录中远端没有的文件和子目录
+pub async fn sync_tree(
+    downloader: &mut Downloader,
+    store: &BlockStore,
+    root: Cid,
+    node: &Node,
+    path: &Path,
+    delete: bool,
+) -> Result<SyncStats> {
+    let mut stats = SyncStats::default();
+    downloader.reset_progress(node.size());
+    match node {
+        Node::File(manifest) => {
+            sync_file(downloader, store, root, manifest, path, delete, &mut stats).await?
+        }
+        Node::Directory(directory) => {
+            sync_directory(downloader, store, directory, path, delete, &mut stats).await?
+        }
+    }
+    Ok(stats)
+}
+
+// 逐层同步目录，目录权限在其内容同步完成后再设置
+async fn sync_directory(
+    downloader: &mut Downloader,
+    store: &BlockStore,
+    directory: &Directory,
+    path: &Path,
+    delete: bool,
+    stats: &mut SyncStats,
+) -> Result<()> {
+    let mut stack = vec![(directory.clone(), path.to_path_buf())];
+    let mut created = Vec::new();
+    while let Some((directory, path)) = stack.pop() {
+        if path.exists() && !path.is_dir() {
+            replace(&path, delete)?;
+            stats.removed += 1;
+        }
+        fs::create_dir_all(&path)?;
+        created.push((path.clone(), directory.mode));
+
+        let cids: Vec<Cid> = directory.entries.iter().map(|e| e.cid).collect();
+        downloader.fetch(&cids).await?;
+        for entry in &directory.entries {
+            if !entry.is_valid_name() {
+                return Err(Error::InvalidData(format!(
+                    "Invalid entry name {:?} in directory",
+                    entry.name
+                )));
+            }
+            let child = path.join(&entry.name);
+            match (store.node(&entry.cid)?, entry.is_dir) {
+                (Node::Directory(sub), true) => stack.push((sub, child)),
+                (Node::File(manifest), false) => {
+                    sync_file(
+                        downloader, store, entry.cid, &manifest, &child, delete, stats,
+                    )
+                    .await?
+                }
+                _ => {
+                    return Err(Error::InvalidData(format!(
+                        "Entry {} does not match its manifest {}",
+                        child.display(),
+                        entry.cid
+                    )))
+                }
+            }
+        }
+
+        if delete {
+            stats.removed += remove_extra(&directory, &path)?;
+        }
+    }
+    for (path, mode) in created.into_iter().rev() {
+        set_mode(&path, mode)?;
+    }
+    Ok(())
+}
+
+// 同步单个文件，本地已有内容相同的文件时跳过
+async fn sync_file(
+    downloader: &mut Downloader,
+    store: &BlockStore,
+    root: Cid,
+    manifest: &Manifest,
+    path: &Path,
+    delete: bool,
+    stats: &mut SyncStats,
+) -> Result<()> {
+    if path.is_dir() {
+        replace(path, delete)?;
+        stats.removed += 1;
+    }
+    if path.is_file() {
+        let same = fs::metadata(path)?.len() == manifest.size && {
+            let path = path.to_path_buf();
+            blocking(move || hash_file(&path)).await? == manifest.hash
+        };
+        if same {
+            set_mode(path, manifest.mode)?;
+            downloader.skip(manifest.size);
+            stats.unchanged += 1;
+            return Ok(());
+        }
+        // 只有提供节点知道各数据块的弱校验和，获取失败时下载整个文件
+        match downloader.request_checksums(root).await {
+            Ok(checksums) if checksums.len() == manifest.blocks.len() => {
+                let (store, basis, manifest) =
+                    (store.clone(), path.to_path_buf(), manifest.clone());
+                let reused =
+                    blocking(move || seed_blocks(&store, &basis, &manifest, &checksums)).await?;
+                debug!("Reusing {} bytes of {}", reused, path.display());
+                stats.reused += reused;
+            }
+            Ok(_) => warn!("Ignoring invalid checksums of {}", root),
+            Err(Error::Shutdown) => return Err(Error::Shutdown),
+            Err(e) => warn!("Failed to get checksums of {}: {}", path.display(), e),
+        }
+    }
+
+    let mut missing = HashSet::new();
+    stats.fetched += manifest
+        .blocks
+        .iter()
+        .enumerate()
+        .filter(|(_, cid)| !store.has(cid) && missing.insert(**cid))
+        .map(|(index, _)| manifest.block_size(index))
+        .sum::<u64>();
+    downloader.download_file(root, manifest, path).await?;
+    stats.updated += 1;
+    debug!("File {} synced", path.display());
+    Ok(())
+}
+
+// 在阻塞线程池中读取整个文件，大文件需要数秒，不占用运行时的工作线程
+async fn blocking<T, F>(f: F) -> Result<T>
+where
+    F: FnOnce() -> io::Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    Ok(tokio::task::spawn_blocking(f)
+        .await
+        .map_err(io::Error::other)??)
+}
+
+// 删除类型与远端不一致的本地文件或目录，没有指定`delete`时返回错误
+fn replace(path: &Path, delete: bool) -> Result<()> {
+    if !delete {
+        return Err(Error::InvalidArgument(format!(
+            "{} has a different type than the remote entry, use --delete to replace it",
+            path.display()
+        )));
+    }
+    remove(path)?;
+    Ok(())
+}
+
+// 删除目录中远端没有的文件和子目录，保留目录项中断下载的部分文件，返回删除的数量
+fn remove_extra(directory: &Directory, path: &Path) -> io::Result<usize> {
+    let names: HashSet<&str> = directory.entries.iter().map(|e| e.name.as_str()).collect();
+    let mut removed = 0;
+    for child in fs::read_dir(path)? {
+        let child = child?;
+        let name = child.file_name();
+        let name = name.to_string_lossy();
+        let partial = name
+            .strip_suffix(".part.journal")
+            .or_else(|| name.strip_suffix(".part"));
+        if names.contains(name.as_ref()) || partial.is_some_and(|name| names.contains(name)) {
+            continue;
+        }
+        remove(&child.path())?;
+        info!("Removed {}", child.path().display());
+        removed += 1;
+    }
+    Ok(removed)
+}
+
+fn remove(path: &Path) -> io::Result<()> {
+    if path.is_dir() && !path.is_symlink() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, RngCore, SeedableRng};
+
+    use super::*;
+    use crate::testing::{import_bytes, temp_store};
+
+    fn random_bytes(len: usize) -> Vec<u8> {
+        let mut data = vec![0u8; len];
+        StdRng::seed_from_u64(len as u64).fill_bytes(&mut data);
+        data
+    }
+
+    #[test]
+    fn rolling_matches_fresh_checksum() {
+        let data = random_bytes(300);
+        let window = 64;
+        let mut rolling = Rolling::default();
+        data[..window].iter().for_each(|byte| rolling.push(*byte));
+        for start in 1..=data.len() - window {
+            rolling.roll(data[start - 1], data[start + window - 1]);
+            assert_eq!(
+                rolling.digest(),
+                weak_checksum(&data[start..start + window]),
+                "window at {}",
+                start
+            );
+        }
+    }
+
+    #[test]
+    fn reuses_blocks_after_shifted_insertion() {
+        // 提供节点的存储中有新文件，本地存储为空
+        let (dir, remote) = temp_store();
+        let old = random_bytes(CHUNK_SIZE * 4 + 1000);
+        let middle = CHUNK_SIZE + CHUNK_SIZE / 2;
+        let new = [&old[..middle], b"INSERTED", &old[middle..]].concat();
+        let (root, manifest) = import_bytes(&remote, dir.path(), "new", &new);
+        let basis = dir.path().join("old");
+        fs::write(&basis, &old).unwrap();
+        let checksums = block_checksums(&remote, &root).unwrap();
+        let local = BlockStore::open(&dir.path().join("local")).unwrap();
+
+        let reused = seed_blocks(&local, &basis, &manifest, &checksums).unwrap();
+        // 只有包含插入位置的数据块需要获取
+        assert_eq!(reused, new.len() as u64 - CHUNK_SIZE as u64);
+        let missing: Vec<usize> = (0..manifest.blocks.len())
+            .filter(|index| !local.has(&manifest.blocks[*index]))
+            .collect();
+        assert_eq!(missing, vec![1]);
+    }
+}
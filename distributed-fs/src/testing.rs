@@ -0,0 +1,29 @@
+// 单元测试共用的辅助函数
+use std::path::Path;
+
+use libp2p::identity::Keypair;
+use tempfile::TempDir;
+
+use crate::{
+    access::AccessPolicy,
+    store::{BlockStore, Cid, Manifest},
+};
+
+// 临时目录下的数据块存储，目录随返回的`TempDir`一起删除
+pub fn temp_store() -> (TempDir, BlockStore) {
+    let dir = tempfile::tempdir().unwrap();
+    let store = BlockStore::open(&dir.path().join("store")).unwrap();
+    (dir, store)
+}
+
+// 签发者为随机密钥的访问策略，未限制的内容对所有节点开放
+pub fn open_policy() -> AccessPolicy {
+    AccessPolicy::new(Keypair::generate_ed25519().public())
+}
+
+// 将内容写入`dir`下的文件并导入存储，返回根清单的内容标识和文件清单
+pub fn import_bytes(store: &BlockStore, dir: &Path, name: &str, content: &[u8]) -> (Cid, Manifest) {
+    let path = dir.join(name);
+    std::fs::write(&path, content).unwrap();
+    store.import_file(&path, name.to_string(), false).unwrap()
+}
@@ -0,0 +1,152 @@
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Component, Path, PathBuf},
+    time::{Duration, UNIX_EPOCH},
+};
+
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::{self, UnboundedReceiver};
+
+// 路径最后一次变化后等待的时间，文件在此期间没有继续写入才重新提供
+pub const WATCH_DELAY: Duration = Duration::from_secs(2);
+
+// 守护进程监视的目录，其中的文件以`<name>/<相对路径>`的名称提供
+#[derive(Debug, Clone)]
+pub struct WatchFolder {
+    // 监视目录的绝对路径
+    pub path: PathBuf,
+    // 名称路径的前缀
+    pub name: String,
+    // 数据目录位于监视目录中时忽略其中的变化
+    exclude: Option<PathBuf>,
+}
+
+impl WatchFolder {
+    // 未指定名称前缀时使用目录名
+    pub fn new(path: &Path, name: Option<String>, data_dir: &Path) -> io::Result<Self> {
+        let path = path.canonicalize()?;
+        if !path.is_dir() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is not a directory", path.display()),
+            ));
+        }
+        let name = match name {
+            Some(name) => name.trim_matches('/').to_string(),
+            None => path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "Expect --watch-name for the root directory",
+                    )
+                })?,
+        };
+        Ok(Self {
+            path,
+            name,
+            exclude: data_dir.canonicalize().ok(),
+        })
+    }
+
+    // 监视目录中的路径对应的名称路径，隐藏文件、目录外和数据目录中的路径返回None
+    pub fn name_of(&self, path: &Path) -> Option<String> {
+        if self
+            .exclude
+            .as_ref()
+            .is_some_and(|dir| path.starts_with(dir))
+        {
+            return None;
+        }
+        let mut name = self.name.clone();
+        for component in path.strip_prefix(&self.path).ok()?.components() {
+            match component {
+                Component::Normal(part) => {
+                    let part = part.to_str()?;
+                    if part.starts_with('.') {
+                        return None;
+                    }
+                    name.push('/');
+                    name.push_str(part);
+                }
+                _ => return None,
+            }
+        }
+        Some(name)
+    }
+
+    // `path`下现有的普通文件，按名称路径索引。`path`不存在时返回空
+    pub fn scan(&self, path: &Path) -> io::Result<BTreeMap<String, PathBuf>> {
+        let mut files = BTreeMap::new();
+        let mut stack = vec![path.to_path_buf()];
+        while let Some(path) = stack.pop() {
+            let name = match self.name_of(&path) {
+                Some(name) => name,
+                None => continue,
+            };
+            let metadata = match fs::symlink_metadata(&path) {
+                Ok(metadata) => metadata,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            if metadata.is_dir() {
+                for entry in fs::read_dir(&path)? {
+                    stack.push(entry?.path());
+                }
+            } else if metadata.is_file() {
+                files.insert(name, path);
+            }
+        }
+        Ok(files)
+    }
+}
+
+// 文件的大小和修改时间，与上次提供时一致则认为内容没有变化
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Stamp {
+    pub size: u64,
+    // 修改时间（Unix时间戳，纳秒）
+    pub modified: u128,
+}
+
+impl Stamp {
+    pub fn of(path: &Path) -> io::Result<Self> {
+        let metadata = fs::metadata(path)?;
+        let modified = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or_default();
+        Ok(Self {
+            size: metadata.len(),
+            modified,
+        })
+    }
+}
+
+// 递归监视目录，返回发生变化的路径。监视器被丢弃时停止
+pub fn watch(path: &Path) -> notify::Result<(RecommendedWatcher, UnboundedReceiver<PathBuf>)> {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    let root = path.to_path_buf();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        match event {
+            // 事件队列溢出时丢失了部分变化，重新扫描整个目录
+            Ok(event) if event.need_rescan() => {
+                let _ = sender.send(root.clone());
+            }
+            // 只读访问不改变内容
+            Ok(event) if matches!(event.kind, EventKind::Access(_)) => {}
+            Ok(event) => {
+                for path in event.paths {
+                    let _ = sender.send(path);
+                }
+            }
+            Err(e) => warn!("File watcher error: {}", e),
+        }
+    })?;
+    watcher.watch(path, RecursiveMode::Recursive)?;
+    Ok((watcher, receiver))
+}
@@ -1,27 +1,28 @@
 mod store;
 
 use libp2p::{
-    identity,
-    kad::{store::MemoryStore, Kademlia, Quorum,Record,record::Key},
+    identity::{self, ed25519},
+    kad::{Kademlia, Quorum,Record,record::Key},
     swarm::{SwarmBuilder, SwarmEvent},
     PeerId,
 };
 use futures::StreamExt;
-use std::{error::Error, env};
+use record_store::DiskStore;
+use std::{error::Error, env, fs, path::{Path, PathBuf}};
 use store::MyBehaviour;
 use tokio::io;
 use tokio::io::AsyncBufReadExt;
 
-use tracing_subscriber;
-
-#[macro_use]
-extern crate tracing;
-
 pub async fn run() -> Result<(), Box<dyn Error>> {
     env::set_var("RUST_LOG", "DEBUG");
     tracing_subscriber::fmt::init();
-    // 生成密钥对
-    let key_pair = identity::Keypair::generate_ed25519();
+    // 数据目录，存放持久化的kv记录和节点密钥
+    let data_dir = env::var("DKV_DATA_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| PathBuf::from("dkv-data"));
+
+    // 加载数据目录下的密钥对，重启后节点ID不变
+    let key_pair = keypair(&data_dir)?;
 
     // 基于密钥对的公钥，生成节点唯一标识peerId
     let peer_id = PeerId::from(key_pair.public());
@@ -32,7 +33,7 @@ pub async fn run() -> Result<(), Box<dyn Error>> {
 
     // 创建Swarm网络管理器，来管理节点网络及事件。
     let mut swarm = {
-        let behaviour = MyBehaviour::new(peer_id).await?;
+        let behaviour = MyBehaviour::new(peer_id, &data_dir).await?;
 
         SwarmBuilder::new(transport, behaviour, peer_id)
             .executor(Box::new(|fut| {
@@ -62,8 +63,39 @@ pub async fn run() -> Result<(), Box<dyn Error>> {
     }
 }
 
+// 数据目录下保存节点私钥的文件
+const IDENTITY_FILE: &str = "identity";
+
+// 读取数据目录下的节点私钥，不存在时生成并保存，只有所有者可读写
+fn keypair(data_dir: &Path) -> Result<identity::Keypair, Box<dyn Error>> {
+    let path = data_dir.join(IDENTITY_FILE);
+    match fs::read(&path) {
+        Ok(mut bytes) => {
+            let secret_key = ed25519::SecretKey::from_bytes(&mut bytes)
+                .map_err(|e| format!("Invalid identity key file {}: {e}", path.display()))?;
+            Ok(identity::Keypair::Ed25519(secret_key.into()))
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            let keypair = ed25519::Keypair::generate();
+            fs::create_dir_all(data_dir)?;
+            // 先写入临时文件再重命名，创建时即只有所有者可读写
+            let tmp = path.with_extension("tmp");
+            let mut options = fs::OpenOptions::new();
+            options.write(true).create(true).truncate(true);
+            #[cfg(unix)]
+            std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+            let mut file = options.open(&tmp)?;
+            std::io::Write::write_all(&mut file, keypair.secret().as_ref())?;
+            file.sync_all()?;
+            fs::rename(&tmp, &path)?;
+            Ok(identity::Keypair::Ed25519(keypair))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
 // 处理输入命令
-fn handle_input_line(kademlia: &mut Kademlia<MemoryStore>, line: String) {
+fn handle_input_line(kademlia: &mut Kademlia<DiskStore>, line: String) {
     let mut args = line.split(' ');
 
     match args.next() {
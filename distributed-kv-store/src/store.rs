@@ -1,26 +1,27 @@
+use std::path::Path;
+
 use anyhow::Result;
 use libp2p::{
-    kad::{
-        store::MemoryStore, AddProviderOk, Kademlia, KademliaEvent, PeerRecord, PutRecordOk,
-        QueryResult, Record,
-    },
+    kad::{AddProviderOk, Kademlia, KademliaEvent, PeerRecord, PutRecordOk, QueryResult, Record},
     mdns::{Mdns, MdnsEvent},
     swarm::NetworkBehaviourEventProcess,
     NetworkBehaviour, PeerId,
 };
+use record_store::DiskStore;
 
 // 自定义网络行为，组合Kademlia和mDNS.
 #[derive(NetworkBehaviour)]
 #[behaviour(event_process = true)]
 pub struct MyBehaviour {
-    pub kademlia: Kademlia<MemoryStore>, // 内存存储
+    pub kademlia: Kademlia<DiskStore>, // 持久化存储
     pub mdns: Mdns,
 }
 
 impl MyBehaviour {
-    // 传入peerId，构建MyBehaviour
-    pub async fn new(peer_id: PeerId) -> Result<Self> {
-        let store = MemoryStore::new(peer_id);
+    // 传入peerId和数据目录，构建MyBehaviour
+    pub async fn new(peer_id: PeerId, data_dir: &Path) -> Result<Self> {
+        // 记录保存在数据目录中，重启后恢复
+        let store = DiskStore::open(data_dir, peer_id)?;
         let kademlia = Kademlia::new(peer_id, store);
 
         Ok(Self {
@@ -0,0 +1,480 @@
+use std::{
+    borrow::Cow,
+    collections::HashSet,
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use libp2p::{
+    kad::record::{
+        store::{MemoryStore, MemoryStoreConfig, RecordStore, Result},
+        Key, ProviderRecord, Record,
+    },
+    Multiaddr, PeerId,
+};
+use serde::{Deserialize, Serialize};
+
+#[macro_use]
+extern crate tracing;
+
+// 日志文件名称
+const LOG_FILE: &str = "records.log";
+// 追加的日志条目超过该值且多于存活记录的两倍时压缩日志
+const COMPACT_THRESHOLD: usize = 1024;
+
+// 追加日志中的一条操作
+#[derive(Debug, Serialize, Deserialize)]
+enum LogEntry {
+    // 存储记录
+    Put {
+        key: Vec<u8>,
+        value: Vec<u8>,
+        publisher: Option<Vec<u8>>,
+        // 过期时间，Unix毫秒时间戳
+        expires: Option<u64>,
+    },
+    // 删除记录
+    Remove {
+        key: Vec<u8>,
+    },
+    // 存储提供者记录
+    AddProvider {
+        key: Vec<u8>,
+        provider: Vec<u8>,
+        expires: Option<u64>,
+        addresses: Vec<Vec<u8>>,
+    },
+    // 删除提供者记录
+    RemoveProvider {
+        key: Vec<u8>,
+        provider: Vec<u8>,
+    },
+}
+
+impl LogEntry {
+    fn put(record: &Record) -> Self {
+        LogEntry::Put {
+            key: record.key.to_vec(),
+            value: record.value.clone(),
+            publisher: record.publisher.map(|p| p.to_bytes()),
+            expires: record.expires.map(to_unix_millis),
+        }
+    }
+
+    fn add_provider(record: &ProviderRecord) -> Self {
+        LogEntry::AddProvider {
+            key: record.key.to_vec(),
+            provider: record.provider.to_bytes(),
+            expires: record.expires.map(to_unix_millis),
+            addresses: record.addresses.iter().map(|a| a.to_vec()).collect(),
+        }
+    }
+}
+
+// 将单调时钟时间转换为Unix毫秒时间戳
+fn to_unix_millis(instant: Instant) -> u64 {
+    let now = Instant::now();
+    let system = if instant >= now {
+        SystemTime::now() + (instant - now)
+    } else {
+        SystemTime::now() - (now - instant)
+    };
+    system
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+// 将Unix毫秒时间戳转换为单调时钟时间，已过期时返回None
+fn from_unix_millis(millis: u64) -> Option<Instant> {
+    let system = UNIX_EPOCH + Duration::from_millis(millis);
+    system
+        .duration_since(SystemTime::now())
+        .ok()
+        .map(|remaining| Instant::now() + remaining)
+}
+
+// 持久化的Kademlia记录存储。
+// 记录和提供者记录保存在内存中，每次修改追加写入数据目录下的日志文件，
+// 重启时回放日志恢复未过期的记录，并定期压缩日志。
+pub struct DiskStore {
+    // 内存中的记录
+    memory: MemoryStore,
+    // 所有存在提供者记录的键
+    provider_keys: HashSet<Key>,
+    // 日志文件路径
+    path: PathBuf,
+    // 日志文件写入端
+    log: BufWriter<File>,
+    // 上次压缩后追加的日志条目数
+    appended: usize,
+}
+
+impl DiskStore {
+    // 在数据目录下打开记录存储
+    pub fn open(dir: &Path, local_id: PeerId) -> io::Result<Self> {
+        Self::with_config(dir, local_id, Default::default())
+    }
+
+    pub fn with_config(
+        dir: &Path,
+        local_id: PeerId,
+        config: MemoryStoreConfig,
+    ) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let path = dir.join(LOG_FILE);
+
+        let mut memory = MemoryStore::with_config(local_id, config);
+        let mut provider_keys = HashSet::new();
+        if path.is_file() {
+            let mut reader = BufReader::new(File::open(&path)?);
+            // 日志末尾可能存在写入中断的条目，读取失败时停止回放
+            while let Ok(entry) = bincode::deserialize_from::<_, LogEntry>(&mut reader) {
+                replay(&mut memory, &mut provider_keys, entry);
+            }
+        }
+
+        // 回放后立即压缩，丢弃过期记录和损坏的日志末尾
+        let log = write_snapshot(&path, &memory, &provider_keys)?;
+        Ok(Self {
+            memory,
+            provider_keys,
+            path,
+            log,
+            appended: 0,
+        })
+    }
+
+    // 将当前所有记录写入新的日志文件并替换旧日志
+    fn compact(&mut self) -> io::Result<()> {
+        self.log = write_snapshot(&self.path, &self.memory, &self.provider_keys)?;
+        self.appended = 0;
+        Ok(())
+    }
+
+    // 追加一条日志，写入失败时只记录警告，内存中的记录仍然有效
+    fn append(&mut self, entry: LogEntry) {
+        let result = bincode::serialize_into(&mut self.log, &entry)
+            .map_err(io::Error::other)
+            .and_then(|_| self.log.flush());
+        if let Err(e) = result {
+            warn!(
+                "Failed to append to record log {}: {}",
+                self.path.display(),
+                e
+            );
+            return;
+        }
+
+        self.appended += 1;
+        let live = self.memory.records().count() + self.provider_keys.len();
+        if self.appended > COMPACT_THRESHOLD && self.appended > live * 2 {
+            if let Err(e) = self.compact() {
+                warn!(
+                    "Failed to compact record log {}: {}",
+                    self.path.display(),
+                    e
+                );
+            }
+        }
+    }
+}
+
+// 将内存中的所有记录写入新的日志文件并替换旧日志，返回新日志的追加写入端
+fn write_snapshot(
+    path: &Path,
+    memory: &MemoryStore,
+    provider_keys: &HashSet<Key>,
+) -> io::Result<BufWriter<File>> {
+    let tmp = path.with_extension("log.tmp");
+    let mut writer = BufWriter::new(File::create(&tmp)?);
+    for record in memory.records() {
+        bincode::serialize_into(&mut writer, &LogEntry::put(&record)).map_err(io::Error::other)?;
+    }
+    for key in provider_keys {
+        for record in memory.providers(key) {
+            bincode::serialize_into(&mut writer, &LogEntry::add_provider(&record))
+                .map_err(io::Error::other)?;
+        }
+    }
+    writer.flush()?;
+    writer.get_ref().sync_all()?;
+    drop(writer);
+    fs::rename(&tmp, path)?;
+
+    Ok(BufWriter::new(OpenOptions::new().append(true).open(path)?))
+}
+
+// 将一条日志应用到内存存储，跳过已过期和无法解析的记录
+fn replay(memory: &mut MemoryStore, provider_keys: &mut HashSet<Key>, entry: LogEntry) {
+    match entry {
+        LogEntry::Put {
+            key,
+            value,
+            publisher,
+            expires,
+        } => {
+            let expires = match expires {
+                Some(millis) => match from_unix_millis(millis) {
+                    Some(instant) => Some(instant),
+                    None => {
+                        memory.remove(&Key::from(key));
+                        return;
+                    }
+                },
+                None => None,
+            };
+            let record = Record {
+                key: Key::from(key),
+                value,
+                publisher: publisher.and_then(|p| PeerId::from_bytes(&p).ok()),
+                expires,
+            };
+            if let Err(e) = memory.put(record) {
+                debug!("Record not restored: {:?}", e);
+            }
+        }
+        LogEntry::Remove { key } => memory.remove(&Key::from(key)),
+        LogEntry::AddProvider {
+            key,
+            provider,
+            expires,
+            addresses,
+        } => {
+            let key = Key::from(key);
+            let provider = match PeerId::from_bytes(&provider) {
+                Ok(provider) => provider,
+                Err(_) => return,
+            };
+            let expires = match expires {
+                Some(millis) => match from_unix_millis(millis) {
+                    Some(instant) => Some(instant),
+                    None => {
+                        memory.remove_provider(&key, &provider);
+                        return;
+                    }
+                },
+                None => None,
+            };
+            let record = ProviderRecord {
+                key: key.clone(),
+                provider,
+                expires,
+                addresses: addresses
+                    .into_iter()
+                    .filter_map(|a| Multiaddr::try_from(a).ok())
+                    .collect(),
+            };
+            match memory.add_provider(record) {
+                Ok(()) => {
+                    provider_keys.insert(key);
+                }
+                Err(e) => debug!("Provider record not restored: {:?}", e),
+            }
+        }
+        LogEntry::RemoveProvider { key, provider } => {
+            let key = Key::from(key);
+            if let Ok(provider) = PeerId::from_bytes(&provider) {
+                memory.remove_provider(&key, &provider);
+                if memory.providers(&key).is_empty() {
+                    provider_keys.remove(&key);
+                }
+            }
+        }
+    }
+}
+
+impl<'a> RecordStore<'a> for DiskStore {
+    type RecordsIter = <MemoryStore as RecordStore<'a>>::RecordsIter;
+    type ProvidedIter = <MemoryStore as RecordStore<'a>>::ProvidedIter;
+
+    fn get(&'a self, k: &Key) -> Option<Cow<'a, Record>> {
+        self.memory.get(k)
+    }
+
+    fn put(&'a mut self, r: Record) -> Result<()> {
+        let entry = LogEntry::put(&r);
+        self.memory.put(r)?;
+        self.append(entry);
+        Ok(())
+    }
+
+    fn remove(&'a mut self, k: &Key) {
+        if self.memory.get(k).is_some() {
+            self.memory.remove(k);
+            self.append(LogEntry::Remove { key: k.to_vec() });
+        }
+    }
+
+    fn records(&'a self) -> Self::RecordsIter {
+        self.memory.records()
+    }
+
+    fn add_provider(&'a mut self, record: ProviderRecord) -> Result<()> {
+        let entry = LogEntry::add_provider(&record);
+        let key = record.key.clone();
+        self.memory.add_provider(record)?;
+        self.provider_keys.insert(key);
+        self.append(entry);
+        Ok(())
+    }
+
+    fn providers(&'a self, key: &Key) -> Vec<ProviderRecord> {
+        self.memory.providers(key)
+    }
+
+    fn provided(&'a self) -> Self::ProvidedIter {
+        self.memory.provided()
+    }
+
+    fn remove_provider(&'a mut self, k: &Key, p: &PeerId) {
+        let providers = self.memory.providers(k);
+        if providers.iter().any(|r| r.provider == *p) {
+            self.memory.remove_provider(k, p);
+            if providers.len() == 1 {
+                self.provider_keys.remove(k);
+            }
+            self.append(LogEntry::RemoveProvider {
+                key: k.to_vec(),
+                provider: p.to_bytes(),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    fn record(key: &str, value: &str) -> Record {
+        Record::new(Key::new(&key), value.as_bytes().to_vec())
+    }
+
+    fn provider(key: &str, provider: PeerId, expires: Option<Instant>) -> ProviderRecord {
+        ProviderRecord {
+            key: Key::new(&key),
+            provider,
+            expires,
+            addresses: Vec::new(),
+        }
+    }
+
+    fn value(store: &DiskStore, key: &str) -> Option<Vec<u8>> {
+        store.get(&Key::new(&key)).map(|r| r.value.clone())
+    }
+
+    // 日志文件中的条目数
+    fn log_entries(dir: &Path) -> usize {
+        let mut reader = BufReader::new(File::open(dir.join(LOG_FILE)).unwrap());
+        let mut count = 0;
+        while bincode::deserialize_from::<_, LogEntry>(&mut reader).is_ok() {
+            count += 1;
+        }
+        count
+    }
+
+    #[test]
+    fn replays_records_after_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let local_id = PeerId::random();
+        let peer = PeerId::random();
+        {
+            let mut store = DiskStore::open(dir.path(), local_id).unwrap();
+            store.put(record("a", "1")).unwrap();
+            store.put(record("b", "2")).unwrap();
+            store.put(record("a", "3")).unwrap();
+            store.remove(&Key::new(&"b"));
+            store.add_provider(provider("p", peer, None)).unwrap();
+            store.add_provider(provider("q", peer, None)).unwrap();
+            store.remove_provider(&Key::new(&"q"), &peer);
+        }
+
+        let store = DiskStore::open(dir.path(), local_id).unwrap();
+        assert_eq!(value(&store, "a"), Some(b"3".to_vec()));
+        assert_eq!(value(&store, "b"), None);
+        assert_eq!(store.providers(&Key::new(&"p"))[0].provider, peer);
+        assert!(store.providers(&Key::new(&"q")).is_empty());
+        assert_eq!(store.provider_keys, HashSet::from([Key::new(&"p")]));
+    }
+
+    #[test]
+    fn compacts_log() {
+        let dir = tempfile::tempdir().unwrap();
+        let local_id = PeerId::random();
+        {
+            let mut store = DiskStore::open(dir.path(), local_id).unwrap();
+            for i in 0..=COMPACT_THRESHOLD {
+                store.put(record("a", &i.to_string())).unwrap();
+            }
+            assert_eq!(store.appended, 0);
+            assert_eq!(log_entries(dir.path()), 1);
+            store.put(record("b", "b")).unwrap();
+            assert_eq!(log_entries(dir.path()), 2);
+        }
+
+        let store = DiskStore::open(dir.path(), local_id).unwrap();
+        assert_eq!(
+            value(&store, "a"),
+            Some(COMPACT_THRESHOLD.to_string().into_bytes())
+        );
+        assert_eq!(value(&store, "b"), Some(b"b".to_vec()));
+    }
+
+    #[test]
+    fn ignores_truncated_final_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let local_id = PeerId::random();
+        {
+            let mut store = DiskStore::open(dir.path(), local_id).unwrap();
+            store.put(record("a", "1")).unwrap();
+            store.put(record("b", "2")).unwrap();
+        }
+        let path = dir.path().join(LOG_FILE);
+        let len = fs::metadata(&path).unwrap().len();
+        OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(len - 1)
+            .unwrap();
+
+        {
+            let mut store = DiskStore::open(dir.path(), local_id).unwrap();
+            assert_eq!(value(&store, "a"), Some(b"1".to_vec()));
+            assert_eq!(value(&store, "b"), None);
+            store.put(record("c", "3")).unwrap();
+        }
+
+        // 损坏的末尾已在打开时丢弃，之后追加的条目可以正常回放
+        let store = DiskStore::open(dir.path(), local_id).unwrap();
+        assert_eq!(value(&store, "a"), Some(b"1".to_vec()));
+        assert_eq!(value(&store, "c"), Some(b"3".to_vec()));
+        assert_eq!(log_entries(dir.path()), 2);
+    }
+
+    #[test]
+    fn drops_expired_providers() {
+        let dir = tempfile::tempdir().unwrap();
+        let local_id = PeerId::random();
+        let peer = PeerId::random();
+        {
+            let mut store = DiskStore::open(dir.path(), local_id).unwrap();
+            let expires = Instant::now() + Duration::from_millis(50);
+            store
+                .add_provider(provider("p", peer, Some(expires)))
+                .unwrap();
+            store.add_provider(provider("q", peer, None)).unwrap();
+        }
+        thread::sleep(Duration::from_millis(100));
+
+        let store = DiskStore::open(dir.path(), local_id).unwrap();
+        assert!(store.providers(&Key::new(&"p")).is_empty());
+        assert_eq!(store.providers(&Key::new(&"q")).len(), 1);
+        assert_eq!(store.provider_keys, HashSet::from([Key::new(&"q")]));
+        assert_eq!(log_entries(dir.path()), 1);
+    }
+}
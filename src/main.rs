@@ -18,6 +18,9 @@ async fn main() -> Result<(), Box<dyn Error>> {
     #[cfg(feature = "dkv")]
     dkv::run().await;
     #[cfg(feature = "dfs")]
-    dfs::run().await;
+    if let Err(e) = dfs::run().await {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
     Ok(())
 }